@@ -0,0 +1,80 @@
+// Types for /_maypaper/maypaper.js
+
+export interface Monitor {
+    name: string;
+    x: number;
+    y: number;
+    width: number;
+    height: number;
+    scale: number;
+    // 0 when the compositor doesn't report it
+    refreshRate: number;
+}
+
+export interface SystemInfo {
+    version: string;
+    os: string;
+    arch: string;
+    hostname: string | null;
+    cpus: number;
+    // Seconds
+    uptime: number | null;
+    // 1, 5 and 15 minute load averages
+    load: [number, number, number] | null;
+    // Bytes
+    memory: { total: number; available: number } | null;
+}
+
+export interface PropertyChange {
+    key: string;
+    value: unknown;
+}
+
+export type ControlMessage =
+    | { type: "set_property"; key: string; value: unknown }
+    | { type: "set_named"; name: string; properties?: Record<string, unknown> }
+    | { type: "next" };
+
+export type ControlReply = { type: "ok" } | { type: "error"; message: string };
+
+export interface Maypaper {
+    // Injected into every page maypaper serves
+    connector: string | null;
+    monitor: Monitor | null;
+    monitors: Monitor[];
+    version: string;
+    properties: Record<string, unknown>;
+    // Null on remote pages and with --shared-server, where control is refused
+    controlToken: string | null;
+    base: string;
+
+    // Added by maypaper.js
+    sdk: number;
+    on(type: "focus" | "pause", listener: (value: boolean) => void): () => void;
+    on(type: "property", listener: (change: PropertyChange) => void): () => void;
+
+    isFocused(): boolean;
+    onFocus(listener: (focused: boolean) => void): () => void;
+    setFocused(value: boolean): void;
+
+    isPaused(): boolean;
+    onPause(listener: (paused: boolean) => void): () => void;
+    setPaused(value: boolean): void;
+
+    property<T = unknown>(key: string, fallback?: T): T;
+    onProperty(listener: (change: PropertyChange) => void): () => void;
+    setProperty(key: string, value: unknown): Promise<ControlReply>;
+    setNamed(name: string, properties?: Record<string, unknown>): Promise<ControlReply>;
+    next(): Promise<ControlReply>;
+
+    getMonitor(): Monitor | null;
+    getMonitors(): Monitor[];
+
+    getSystem(): Promise<SystemInfo>;
+
+    send(message: ControlMessage): Promise<ControlReply>;
+}
+
+declare global {
+    var maypaper: Maypaper;
+}
@@ -0,0 +1,107 @@
+/*
+ * maypaper.js, served by every maypaper webserver at /_maypaper/maypaper.js
+ *
+ *     <script src="/_maypaper/maypaper.js"></script>
+ *
+ * Adds helpers to the globalThis.maypaper context maypaper injects into each page.
+ * Types are at /_maypaper/maypaper.d.ts
+ */
+(() => {
+    "use strict";
+
+    const mp = (globalThis.maypaper ??= {});
+    // Included twice, keep the first copy and its listeners
+    if (mp.sdk) return;
+
+    const events = new EventTarget();
+    const state = {focused: true, paused: false};
+
+    function emit(type, detail) {
+        events.dispatchEvent(new CustomEvent(type, {detail}));
+    }
+
+    // Returns a function that removes the listener again
+    function on(type, listener) {
+        const wrapped = (e) => listener(e.detail);
+        events.addEventListener(type, wrapped);
+        return () => events.removeEventListener(type, wrapped);
+    }
+
+    function isPaused() {
+        return state.paused || document.hidden;
+    }
+
+    // Sent by maypaper when a property changes, see webview.qml
+    globalThis.addEventListener("maypaper:property", (e) => emit("property", e.detail));
+    document.addEventListener("visibilitychange", () => emit("pause", isPaused()));
+
+    async function send(message) {
+        if (!mp.controlToken || !mp.connector) {
+            throw new Error("maypaper: control is only available to local pages on their own server");
+        }
+
+        // Servers can be mounted under a prefix, which the context reports as base
+        const url = new URL("api/control", location.origin + (mp.base ?? "/"));
+        const res = await fetch(url, {
+            method: "POST",
+            headers: {"content-type": "application/json", "x-maypaper-token": mp.controlToken},
+            body: JSON.stringify({connector: mp.connector, ...message}),
+        });
+
+        const reply = await res.json().catch(() => null);
+        if (!res.ok) {
+            throw new Error(`maypaper: ${reply?.message ?? `control request failed with ${res.status}`}`);
+        }
+        return reply;
+    }
+
+    Object.assign(mp, {
+        sdk: 1,
+
+        on,
+
+        // Focus, whether the wallpaper's window is the active one
+        isFocused: () => state.focused,
+        onFocus: (listener) => on("focus", listener),
+        setFocused(value) {
+            value = !!value;
+            if (value === state.focused) return;
+            state.focused = value;
+            emit("focus", value);
+        },
+
+        // Pause, requested by maypaper or because the page is hidden
+        isPaused,
+        onPause: (listener) => on("pause", listener),
+        setPaused(value) {
+            const before = isPaused();
+            state.paused = !!value;
+            if (isPaused() !== before) emit("pause", isPaused());
+        },
+
+        // Properties, as given with `mypctl set --property`
+        property: (key, fallback = null) => mp.properties?.[key] ?? fallback,
+        onProperty: (listener) => on("property", listener),
+        setProperty: (key, value) => send({type: "set_property", key, value}),
+
+        // Replaces this page with a named wallpaper, from wallpapers.toml or the library
+        setNamed: (name, properties = {}) => send({type: "set_named", name, properties}),
+
+        // Moves the monitor's playlist on, fails when none is running
+        next: () => send({type: "next"}),
+
+        // Monitor info
+        getMonitor: () => mp.monitor ?? null,
+        getMonitors: () => mp.monitors ?? [],
+
+        // System info, fetched fresh on every call
+        async getSystem() {
+            const res = await fetch(new URL("api/system", location.origin + (mp.base ?? "/")), {cache: "no-store"});
+            if (!res.ok) throw new Error(`maypaper: system info failed with ${res.status}`);
+            return res.json();
+        },
+
+        // Messaging, any control message maypaper accepts from pages
+        send,
+    });
+})();
@@ -1,19 +1,20 @@
-use std::io::{self, Write};
-use std::os::unix::net::UnixStream;
-use std::path::PathBuf;
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
+use std::env;
+use std::path::{Path, PathBuf};
 
 use clap::{Parser, Subcommand};
-use maypaper::event::Ipc;
-use maypaper::{get_default_socket_path};
+use maypaper::event::{CursorMode, Ipc, IpcReply, Playlist, PlaylistEntry, PowerMode, Properties};
+use maypaper::{Paths, get_default_socket_path, library, send_msg, subscribe};
+use percent_encoding::percent_decode_str;
 use tracing::error;
 
 #[derive(Parser, Debug)]
 #[command(name = "mypctl", version, about = "Control maypaper via IPC")]
 struct Cli {
-    /// The config directory. If left unspecified, XDG_CONFIG_HOME/maypaper is used
-//#[arg(long)]
-    //config_dir: Option<PathBuf>,
+    /// The config directory, whose wallpapers/ is the library. If left unspecified,
+    /// XDG_CONFIG_HOME/maypaper is used
+    #[arg(long, value_name = "PATH")]
+    config_dir: Option<PathBuf>,
 
     /// Path to the maypaper IPC socket (defaults to /run/user/<uid>/maypaper.sock)
     #[arg(long, value_name = "PATH")]
@@ -29,50 +30,502 @@ enum Cmd {
         #[arg(long)]
         monitor: Option<String>,
 
-        #[arg(long, conflicts_with = "path", required = true)]
+        /// A remote URL, or a file:// URL to a local wallpaper
+        #[arg(long, conflicts_with_all = ["path", "name"], required = true)]
         url: Option<String>,
 
-        #[arg(long, conflicts_with = "url", required = true)]
+        /// A local wallpaper: a directory, an .html file, an image or video, or a .zip/.tar.zst
+        /// archive. Relative paths and ~ are resolved here, not by the daemon
+        #[arg(long, conflicts_with_all = ["url", "name"], required = true)]
         path: Option<String>,
+
+        /// A [[wallpapers]] entry of wallpapers.toml, or a wallpaper in the library
+        #[arg(long, conflicts_with_all = ["url", "path"], required = true)]
+        name: Option<String>,
+
+        /// Passed to the page as globalThis.maypaper.properties. Values are read as JSON,
+        /// falling back to a plain string. Can be given multiple times
+        #[arg(long = "property", value_name = "KEY=VALUE", value_parser = parse_property)]
+        properties: Vec<(String, serde_json::Value)>,
+
+        /// Stretch one page across these monitors, laid out as the compositor places them.
+        /// Comma separated or given multiple times
+        #[arg(long, value_name = "MONITOR", value_delimiter = ',', conflicts_with_all = ["monitor", "name"])]
+        span: Vec<String>,
+    },
+
+    /// Change one property of the wallpaper already shown, without reloading it
+    Property {
+        #[arg(long)]
+        monitor: Option<String>,
+
+        /// Read the same way as --property on set
+        #[arg(value_name = "KEY=VALUE", value_parser = parse_property)]
+        property: (String, serde_json::Value),
+    },
+
+    /// Show the next entry of the playlist running on a monitor
+    Next {
+        #[arg(long)]
+        monitor: Option<String>,
+    },
+
+    /// Show the entry shown before the current one
+    Prev {
+        #[arg(long)]
+        monitor: Option<String>,
+    },
+
+    /// Rotate through wallpapers on a timer
+    Playlist {
+        #[command(subcommand)]
+        cmd: PlaylistCmd,
+    },
+
+    /// List the [[schedule]] windows of wallpapers.toml that start or end in the next week
+    Schedule,
+
+    /// Cap how often the wallpaper is rendered. Leave out FPS to go back to [defaults] frame_rate
+    FrameRate {
+        #[arg(long)]
+        monitor: Option<String>,
+
+        fps: Option<u32>,
+    },
+
+    /// Show each monitor's frame rate, and what the [[power]] rules are doing
+    State,
+
+    /// Print the daemon's events as they happen, one JSON object per line
+    Events,
+
+    /// Manage the wallpapers in the library, each a directory in the config directory's wallpapers/
+    Library {
+        #[command(subcommand)]
+        cmd: LibraryCmd,
+    },
+
+    /// Manage the daemon's wallpapers.toml
+    Config {
+        #[command(subcommand)]
+        cmd: ConfigCmd,
+    },
+
+    /// Manage the offline copies of remote URLs, when maypaper runs with --cache-urls
+    Cache {
+        #[command(subcommand)]
+        cmd: CacheCmd,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum LibraryCmd {
+    /// List every wallpaper in the library, with its title
+    List,
+
+    /// Print a wallpaper's location and manifest
+    Show { name: String },
+
+    /// Delete a wallpaper from the library
+    Remove { name: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum PlaylistCmd {
+    /// Start a [playlists] entry of wallpapers.toml, or one made of --entry
+    Start {
+        #[arg(long)]
+        monitor: Option<String>,
+
+        #[arg(required_unless_present = "entries", conflicts_with = "entries")]
+        name: Option<String>,
+
+        /// A URL, a path (starting with /, . or ~) or a wallpaper name. Can be given multiple times
+        #[arg(long = "entry", value_name = "WALLPAPER")]
+        entries: Vec<String>,
+
+        /// Seconds each entry is shown for
+        #[arg(long, default_value_t = 300, requires = "entries")]
+        interval: u64,
+
+        #[arg(long, requires = "entries")]
+        shuffle: bool,
+
+        /// Show the same entry on every monitor instead of stepping through each on its own
+        #[arg(long, requires = "entries")]
+        shared: bool,
+    },
+
+    /// Stop the playlist, the current wallpaper stays
+    Stop {
+        #[arg(long)]
+        monitor: Option<String>,
     },
 }
 
-fn send_msg(socket_path: &PathBuf, msg: &Ipc) -> io::Result<()> {
-    let mut stream = UnixStream::connect(socket_path)?;
+#[derive(Subcommand, Debug)]
+enum ConfigCmd {
+    /// Read wallpapers.toml again now, printing where it fails to parse.
+    /// It is also picked up automatically a moment after it changes
+    Reload,
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCmd {
+    /// Fetch cached pages again on their next load, the old copy is kept for when offline
+    Refresh {
+        /// Only refresh the site this URL belongs to
+        #[arg(long)]
+        url: Option<String>,
+    },
+}
 
-    // Delimiter is newline
-    let line =
-        serde_json::to_string(msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+fn parse_property(s: &str) -> Result<(String, serde_json::Value), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got {s}"))?;
+
+    let value = serde_json::from_str(value)
+        .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+    Ok((key.to_string(), value))
+}
+
+// The daemon has its own working directory, so paths are made absolute before sending
+fn absolute_path(path: &str) -> Result<String> {
+    let expanded = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let Some(home) = env::var_os("HOME") else {
+                bail!("Cannot expand ~, HOME is not set");
+            };
+            PathBuf::from(home).join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(path),
+    };
+
+    let absolute = std::path::absolute(&expanded)
+        .with_context(|| format!("Failed to make {} absolute", expanded.display()))?;
+
+    absolute
+        .to_str()
+        .map(str::to_string)
+        .with_context(|| format!("Path is not valid UTF-8: {}", absolute.display()))
+}
+
+// Takes everything after file://, an optional host followed by a percent encoded path
+fn file_url_path(rest: &str) -> Result<String> {
+    let path = match rest.find('/') {
+        Some(0) => rest,
+        Some(i) if &rest[..i] == "localhost" => &rest[i..],
+        _ => bail!("Only local file:// URLs are supported: file://{rest}"),
+    };
+
+    percent_decode_str(path)
+        .decode_utf8()
+        .map(|path| path.into_owned())
+        .context("file:// URL does not decode to valid UTF-8")
+}
+
+fn playlist_entry(entry: &str) -> Result<PlaylistEntry> {
+    let (wallpaper, path, url) = if entry.contains("://") {
+        (None, None, Some(entry.to_string()))
+    } else if entry.starts_with(['/', '.', '~']) {
+        (None, Some(absolute_path(entry)?), None)
+    } else {
+        return Ok(PlaylistEntry::Name(entry.to_string()));
+    };
+
+    Ok(PlaylistEntry::Item {
+        wallpaper,
+        path,
+        url,
+        weight: 1,
+        properties: Properties::new(),
+    })
+}
+
+fn events(socket_path: &Path) -> Result<()> {
+    let events = subscribe(socket_path)
+        .with_context(|| format!("Failed to subscribe through {}", socket_path.display()))?;
+    for event in events {
+        println!("{}", serde_json::to_string(&event?)?);
+    }
+    Ok(())
+}
+
+// Works on the files directly, the daemon doesn't need to be running
+fn library(cmd: LibraryCmd, config_dir: Option<PathBuf>) -> Result<()> {
+    let paths = Paths::get_dirs(config_dir)?;
+
+    match cmd {
+        LibraryCmd::List => {
+            for entry in library::list(&paths.wallpapers)? {
+                match (&entry.manifest.title, &entry.problem) {
+                    // The parse error spans several lines, show has it in full
+                    (_, Some(_)) => println!("{}\t(invalid manifest)", entry.name),
+                    (Some(title), None) => println!("{}\t{title}", entry.name),
+                    (None, None) => println!("{}", entry.name),
+                }
+            }
+        }
+        LibraryCmd::Show { name } => {
+            let entry = library::get(&paths.wallpapers, &name)?;
+            let manifest = &entry.manifest;
+
+            println!("name: {}", entry.name);
+            println!("path: {}", entry.path.display());
+            let fields = [
+                ("title", &manifest.title),
+                ("description", &manifest.description),
+                ("author", &manifest.author),
+                ("version", &manifest.version),
+                ("license", &manifest.license),
+                ("preview", &manifest.preview),
+            ];
+            for (field, value) in fields {
+                if let Some(value) = value {
+                    println!("{field}: {value}");
+                }
+            }
+            if !manifest.properties.is_empty() {
+                println!(
+                    "properties: {}",
+                    serde_json::to_string(&manifest.properties)?
+                );
+            }
+            if let Some(problem) = &entry.problem {
+                println!("invalid manifest: {problem}");
+            }
+        }
+        LibraryCmd::Remove { name } => library::remove(&paths.wallpapers, &name)?,
+    }
 
-    stream.write_all(line.as_bytes())?;
-    stream.write_all(b"\n")?;
-    stream.flush()?;
     Ok(())
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    //let paths = Paths::get_dirs(cli.config_dir)?;
-    //paths.ensure_dirs()?;
-    let socket_path = cli.socket.unwrap_or_else(get_default_socket_path);
 
     let msg = match cli.cmd {
-        Cmd::Set { monitor, url, path } => {
-            if path.is_some() {
-                Ipc::SetPath { monitor, path: path.unwrap() }
+        Cmd::Set {
+            monitor,
+            url,
+            path,
+            name,
+            properties,
+            span,
+        } => {
+            let properties: Properties = properties.into_iter().collect();
+            match (name, path, url) {
+                (Some(name), _, _) => Ipc::SetNamed {
+                    monitor,
+                    name,
+                    properties,
+                },
+                (None, Some(path), _) => Ipc::SetPath {
+                    monitor,
+                    path: absolute_path(&path)?,
+                    properties,
+                    span,
+                },
+                (None, None, Some(url)) => match url.strip_prefix("file://") {
+                    Some(file) => Ipc::SetPath {
+                        monitor,
+                        path: absolute_path(&file_url_path(file)?)?,
+                        properties,
+                        span,
+                    },
+                    None => Ipc::SetUrl {
+                        monitor,
+                        url,
+                        properties,
+                        span,
+                    },
+                },
+                (None, None, None) => unreachable!("clap requires one of --url, --path or --name"),
+            }
+        }
+        Cmd::Property {
+            monitor,
+            property: (key, value),
+        } => Ipc::SetProperty {
+            monitor,
+            key,
+            value,
+        },
+        Cmd::Next { monitor } => Ipc::Next { monitor },
+        Cmd::Prev { monitor } => Ipc::Prev { monitor },
+        Cmd::Playlist {
+            cmd:
+                PlaylistCmd::Start {
+                    monitor,
+                    name,
+                    entries,
+                    interval,
+                    shuffle,
+                    shared,
+                },
+        } => {
+            let playlist = if entries.is_empty() {
+                None
             } else {
-                Ipc::SetUrl { monitor, url: url.unwrap() }
+                Some(Playlist {
+                    interval,
+                    shuffle,
+                    cursor: if shared {
+                        CursorMode::Shared
+                    } else {
+                        CursorMode::PerMonitor
+                    },
+                    entries: entries
+                        .iter()
+                        .map(|e| playlist_entry(e))
+                        .collect::<Result<_>>()?,
+                })
+            };
+            Ipc::StartPlaylist {
+                monitor,
+                name,
+                playlist,
             }
         }
+        Cmd::Playlist {
+            cmd: PlaylistCmd::Stop { monitor },
+        } => Ipc::StopPlaylist { monitor },
+        Cmd::Schedule => Ipc::GetSchedule,
+        Cmd::FrameRate { monitor, fps } => Ipc::SetFrameRate { monitor, fps },
+        Cmd::State => Ipc::GetState,
+        Cmd::Events => return events(&cli.socket.unwrap_or_else(get_default_socket_path)),
+        Cmd::Library { cmd } => return library(cmd, cli.config_dir),
+        Cmd::Config {
+            cmd: ConfigCmd::Reload,
+        } => Ipc::ReloadConfig,
+        Cmd::Cache {
+            cmd: CacheCmd::Refresh { url },
+        } => Ipc::RefreshCache { url },
     };
 
-    if let Err(e) = send_msg(&socket_path, &msg) {
-        error!(
-            "mypctl: failed to send command to socket {:?}: {}",
-            socket_path, e
+    let socket_path = cli.socket.unwrap_or_else(get_default_socket_path);
+
+    match send_msg(&socket_path, &msg) {
+        Ok(IpcReply::Ok) => Ok(()),
+        Ok(IpcReply::Schedule { transitions }) => {
+            if transitions.is_empty() {
+                println!("Nothing scheduled in the next week");
+            }
+            for transition in transitions {
+                println!(
+                    "{}  {:<5}  {:<12}  {}",
+                    transition.local,
+                    if transition.starts { "start" } else { "end" },
+                    transition.monitor.as_deref().unwrap_or("all"),
+                    transition.target,
+                );
+            }
+            Ok(())
+        }
+        Ok(IpcReply::State { power, monitors }) => {
+            let power = match power {
+                PowerMode::Normal => "normal".to_string(),
+                PowerMode::Pause => "paused".to_string(),
+                PowerMode::FrameRate { fps } => format!("capped at {fps} fps"),
+                PowerMode::Fallback { .. } => "showing the fallback".to_string(),
+            };
+            println!("power: {power}");
+            for monitor in monitors {
+                let frame_rate = match monitor.frame_rate {
+                    Some(fps) => format!("{fps} fps"),
+                    None => "uncapped".to_string(),
+                };
+                println!(
+                    "{:<12}  {:>7.2} Hz  {}",
+                    monitor.connector, monitor.refresh_rate, frame_rate
+                );
+            }
+            Ok(())
+        }
+        Ok(IpcReply::Event { .. }) => bail!("maypaper sent an event instead of a reply"),
+        Ok(IpcReply::Error { message }) => bail!("maypaper rejected the command: {message}"),
+        Err(e) => {
+            error!(
+                "mypctl: failed to send command to socket {:?}: {}",
+                socket_path, e
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn properties_are_json_or_strings() {
+        assert_eq!(
+            parse_property("speed=2.5").unwrap(),
+            ("speed".to_string(), serde_json::json!(2.5))
         );
-        std::process::exit(1);
+        assert_eq!(
+            parse_property("theme=dark").unwrap(),
+            ("theme".to_string(), serde_json::json!("dark"))
+        );
+        assert_eq!(
+            parse_property("query=a=b").unwrap(),
+            ("query".to_string(), serde_json::json!("a=b"))
+        );
+        assert!(parse_property("speed").is_err());
     }
 
-    Ok(())
+    #[test]
+    fn paths_are_made_absolute() {
+        assert_eq!(absolute_path("/srv/wallpapers").unwrap(), "/srv/wallpapers");
+
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(
+            absolute_path("wallpapers").unwrap(),
+            cwd.join("wallpapers").to_str().unwrap()
+        );
+
+        if let Some(home) = env::var_os("HOME") {
+            let expected = PathBuf::from(home).join("wallpapers");
+            assert_eq!(
+                absolute_path("~/wallpapers").unwrap(),
+                expected.to_str().unwrap()
+            );
+        }
+        // Only a leading ~/ is the home directory
+        assert_eq!(absolute_path("/srv/~x").unwrap(), "/srv/~x");
+    }
+
+    #[test]
+    fn file_urls_decode_to_local_paths() {
+        assert_eq!(
+            file_url_path("/home/me/a%20b.html").unwrap(),
+            "/home/me/a b.html"
+        );
+        assert_eq!(file_url_path("localhost/srv/x").unwrap(), "/srv/x");
+        assert_eq!(file_url_path("/caf%C3%A9").unwrap(), "/caf\u{e9}");
+        // Not an escape, so left alone
+        assert_eq!(file_url_path("/100%").unwrap(), "/100%");
+
+        assert!(file_url_path("example.com/x").is_err());
+        assert!(file_url_path("relative").is_err());
+        assert!(file_url_path("/%FF").is_err());
+    }
+
+    #[test]
+    fn playlist_entries_are_urls_paths_or_names() {
+        assert!(matches!(
+            playlist_entry("https://example.com/").unwrap(),
+            PlaylistEntry::Item { url: Some(url), .. } if url == "https://example.com/"
+        ));
+        assert!(matches!(
+            playlist_entry("/srv/a").unwrap(),
+            PlaylistEntry::Item { path: Some(path), .. } if path == "/srv/a"
+        ));
+        assert_eq!(
+            playlist_entry("forest").unwrap(),
+            PlaylistEntry::Name("forest".to_string())
+        );
+    }
 }
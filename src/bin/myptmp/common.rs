@@ -1,12 +1,8 @@
 use anyhow::{Context, Result, bail};
-use rust_embed::Embed;
+use maypaper::templates::Templates;
 use std::fs;
 use std::path::{Path, PathBuf};
 
-#[derive(Embed)]
-#[folder = "templates/"]
-pub struct Templates;
-
 pub fn create_dest_dir(path: Option<&str>, default_name: &str) -> Result<PathBuf> {
     let final_path = match path {
         None => PathBuf::from(".").join(default_name),
@@ -0,0 +1,396 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use axum::{
+    Router,
+    body::Bytes,
+    extract::State,
+    http::{StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+
+#[derive(Debug, Clone)]
+pub struct CacheOptions {
+    pub dir: PathBuf,
+    pub ttl: Duration,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Meta {
+    content_type: Option<String>,
+    // Seconds since the epoch, 0 once manually expired
+    fetched: u64,
+}
+
+struct UrlCache {
+    origin: Url,
+    dir: PathBuf,
+    ttl: Duration,
+    client: reqwest::Client,
+}
+
+pub fn is_remote(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+// Each origin gets its own directory, so a refresh can target one site
+fn origin_dir(root: &Path, origin: &str) -> PathBuf {
+    root.join(hex_digest(origin))
+}
+
+fn hex_digest(s: &str) -> String {
+    Sha256::digest(s.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Returns a router proxying the remote page's origin, and the page's path relative to it
+pub async fn proxy_router(
+    url: &str,
+    options: &CacheOptions,
+    client: reqwest::Client,
+) -> Result<(Router, String)> {
+    let url = Url::parse(url).with_context(|| format!("Invalid URL: {url}"))?;
+    let origin = url.origin().ascii_serialization();
+    let dir = origin_dir(&options.dir, &origin);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .with_context(|| format!("Failed to create cache dir: {}", dir.display()))?;
+
+    let entry = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    };
+
+    let cache = UrlCache {
+        origin: url,
+        dir,
+        ttl: options.ttl,
+        client,
+    };
+
+    let router = Router::new()
+        .fallback(serve_cached)
+        .with_state(Arc::new(cache));
+
+    Ok((router, entry.trim_start_matches('/').to_string()))
+}
+
+async fn serve_cached(State(cache): State<Arc<UrlCache>>, uri: Uri) -> Response {
+    let path_and_query = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+    let key = hex_digest(path_and_query);
+    let body_path = cache.dir.join(format!("{key}.body"));
+    let meta_path = cache.dir.join(format!("{key}.json"));
+
+    // Through tokio::fs, a slow disk shouldn't hold up the runtime every page shares
+    let cached = tokio::fs::read_to_string(&meta_path)
+        .await
+        .ok()
+        .and_then(|m| serde_json::from_str::<Meta>(&m).ok());
+
+    if let Some(meta) = &cached
+        && now_secs().saturating_sub(meta.fetched) < cache.ttl.as_secs()
+        && let Ok(body) = tokio::fs::read(&body_path).await
+    {
+        debug!(target: "cache", path = %path_and_query, "Fresh cache hit");
+        return cached_response(meta, body);
+    }
+
+    let Ok(remote) = cache.origin.join(path_and_query) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    match fetch(&cache.client, remote.clone()).await {
+        Ok((status, content_type, body)) => {
+            if status.is_success() {
+                let meta = Meta {
+                    content_type: content_type.clone(),
+                    fetched: now_secs(),
+                };
+                let meta = serde_json::to_string(&meta).unwrap_or_default();
+                let stored = match tokio::fs::write(&body_path, &body).await {
+                    Ok(()) => tokio::fs::write(&meta_path, meta).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = stored {
+                    warn!(target: "cache", url = %remote, error = %e, "Failed to store response");
+                }
+            }
+
+            let mut res = (status, body).into_response();
+            if let Some(content_type) = content_type
+                && let Ok(value) = content_type.parse()
+            {
+                res.headers_mut().insert(header::CONTENT_TYPE, value);
+            }
+            res
+        }
+        Err(e) => match (&cached, tokio::fs::read(&body_path).await) {
+            (Some(meta), Ok(body)) => {
+                info!(target: "cache", url = %remote, error = %e, "Origin unreachable, serving cached copy");
+                cached_response(meta, body)
+            }
+            _ => {
+                warn!(target: "cache", url = %remote, error = %e, "Origin unreachable, nothing cached");
+                StatusCode::BAD_GATEWAY.into_response()
+            }
+        },
+    }
+}
+
+async fn fetch(client: &reqwest::Client, url: Url) -> Result<(StatusCode, Option<String>, Bytes)> {
+    let res = client.get(url).send().await?;
+
+    // Server errors count as the origin being down, so the cached copy wins
+    let status = res.status();
+    if status.is_server_error() {
+        bail!("Origin responded with {status}");
+    }
+
+    let content_type = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let body = res.bytes().await?;
+
+    Ok((status, content_type, body))
+}
+
+fn cached_response(meta: &Meta, body: Vec<u8>) -> Response {
+    let mut res = body.into_response();
+    if let Some(content_type) = &meta.content_type
+        && let Ok(value) = content_type.parse()
+    {
+        res.headers_mut().insert(header::CONTENT_TYPE, value);
+    }
+    res
+}
+
+// Marks cached responses as stale, so they are fetched again but still used while offline.
+// With no URL, every cached site is expired. Blocking, it walks the whole cache
+pub fn expire(root: &Path, url: Option<&str>) -> Result<usize> {
+    let dirs: Vec<PathBuf> = match url {
+        Some(url) => {
+            let url = Url::parse(url).with_context(|| format!("Invalid URL: {url}"))?;
+            vec![origin_dir(root, &url.origin().ascii_serialization())]
+        }
+        None => match fs::read_dir(root) {
+            Ok(entries) => entries.flatten().map(|e| e.path()).collect(),
+            Err(_) => Vec::new(),
+        },
+    };
+
+    let mut expired = 0;
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+
+            let Some(mut meta) = fs::read_to_string(&path)
+                .ok()
+                .and_then(|m| serde_json::from_str::<Meta>(&m).ok())
+            else {
+                continue;
+            };
+
+            meta.fetched = 0;
+            fs::write(&path, serde_json::to_string(&meta)?)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            expired += 1;
+        }
+    }
+
+    Ok(expired)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+
+    use axum::{body::Body, http::Request, routing::get};
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    // A fresh cache directory for one test
+    fn scratch(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("maypaper-cache-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    // Answers with its status and how many times it was asked, v1, v2 and so on
+    struct Origin {
+        url: String,
+        status: Arc<AtomicU16>,
+        server: tokio::task::JoinHandle<()>,
+    }
+
+    async fn origin() -> Origin {
+        let status = Arc::new(AtomicU16::new(200));
+        let hits = Arc::new(AtomicU32::new(0));
+        let status_for_route = status.clone();
+        let app = Router::new().route(
+            "/page",
+            get(move || async move {
+                let hit = hits.fetch_add(1, Ordering::SeqCst) + 1;
+                let status = StatusCode::from_u16(status_for_route.load(Ordering::SeqCst));
+                (status.unwrap(), format!("v{hit}"))
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/page", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        Origin {
+            url,
+            status,
+            server,
+        }
+    }
+
+    async fn get_page(router: &Router) -> (StatusCode, String) {
+        let request = Request::get("/page").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn options(dir: &Path, ttl: u64) -> CacheOptions {
+        CacheOptions {
+            dir: dir.to_path_buf(),
+            ttl: Duration::from_secs(ttl),
+        }
+    }
+
+    #[test]
+    fn fresh_copy_is_served_until_the_ttl() {
+        let dir = scratch("ttl");
+        block_on(async {
+            let origin = origin().await;
+            let client = reqwest::Client::new();
+
+            let (fresh, _) = proxy_router(&origin.url, &options(&dir, 3600), client.clone())
+                .await
+                .unwrap();
+            assert_eq!(get_page(&fresh).await.1, "v1");
+            assert_eq!(get_page(&fresh).await.1, "v1");
+
+            // The same entries, but already stale
+            let (stale, entry) = proxy_router(&origin.url, &options(&dir, 0), client)
+                .await
+                .unwrap();
+            assert_eq!(entry, "page");
+            assert_eq!(get_page(&stale).await.1, "v2");
+            assert_eq!(get_page(&stale).await.1, "v3");
+        });
+    }
+
+    #[test]
+    fn stale_copy_is_served_while_the_origin_is_down() {
+        let dir = scratch("offline");
+        block_on(async {
+            let origin = origin().await;
+            // No pooled connection outlives the server
+            let client = reqwest::Client::builder()
+                .pool_max_idle_per_host(0)
+                .build()
+                .unwrap();
+            let (router, _) = proxy_router(&origin.url, &options(&dir, 0), client)
+                .await
+                .unwrap();
+            assert_eq!(get_page(&router).await, (StatusCode::OK, "v1".to_string()));
+
+            origin.status.store(503, Ordering::SeqCst);
+            assert_eq!(get_page(&router).await, (StatusCode::OK, "v1".to_string()));
+
+            origin.server.abort();
+            let _ = origin.server.await;
+            assert_eq!(get_page(&router).await, (StatusCode::OK, "v1".to_string()));
+        });
+    }
+
+    #[test]
+    fn nothing_cached_is_a_bad_gateway() {
+        let dir = scratch("uncached");
+        block_on(async {
+            let origin = origin().await;
+            origin.status.store(500, Ordering::SeqCst);
+            let (router, _) = proxy_router(&origin.url, &options(&dir, 0), reqwest::Client::new())
+                .await
+                .unwrap();
+            assert_eq!(get_page(&router).await.0, StatusCode::BAD_GATEWAY);
+        });
+    }
+
+    #[test]
+    fn expire_marks_one_site_or_all() {
+        let root = scratch("expire");
+        let meta = |origin: &str| origin_dir(&root, origin).join("page.json");
+        let fetched = |origin: &str| {
+            let meta: Meta =
+                serde_json::from_str(&fs::read_to_string(meta(origin)).unwrap()).unwrap();
+            meta.fetched
+        };
+        for origin in ["https://a.example", "https://b.example"] {
+            fs::create_dir_all(origin_dir(&root, origin)).unwrap();
+            let fresh = Meta {
+                content_type: None,
+                fetched: now_secs(),
+            };
+            fs::write(meta(origin), serde_json::to_string(&fresh).unwrap()).unwrap();
+            fs::write(origin_dir(&root, origin).join("page.body"), "").unwrap();
+        }
+
+        assert_eq!(
+            expire(&root, Some("https://a.example/any/page")).unwrap(),
+            1
+        );
+        assert_eq!(fetched("https://a.example"), 0);
+        assert_ne!(fetched("https://b.example"), 0);
+
+        assert_eq!(expire(&root, None).unwrap(), 2);
+        assert_eq!(fetched("https://b.example"), 0);
+
+        assert!(expire(&root, Some("not a url")).is_err());
+        assert_eq!(expire(&root.join("missing"), None).unwrap(), 0);
+    }
+}
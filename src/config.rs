@@ -0,0 +1,855 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result, bail};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use toml::Spanned;
+use tracing::{debug, info, warn};
+
+use crate::event::{Defaults, Ipc, Playlist, PlaylistEntry, PowerMode, Properties, TokioEvent};
+use crate::library;
+use crate::playlist;
+use crate::power::{self, Condition};
+use crate::schedule::{Location, Rule, Schedule, When};
+
+// How often wallpapers.toml is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// Renderer crashes in a row that are reloaded before [fallback] is shown, unless it sets crashes
+const DEFAULT_CRASHES: u32 = 3;
+
+// How long a page has to load before it counts as failed, unless [fallback] sets timeout
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// What a monitor shows by default, one of the four
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Target {
+    // A [[wallpapers]] entry or a wallpaper in the library, by name
+    pub wallpaper: Option<String>,
+    pub path: Option<String>,
+    pub url: Option<String>,
+    // A [playlists] entry, by name
+    pub playlist: Option<String>,
+    #[serde(default)]
+    pub properties: Properties,
+}
+
+impl Target {
+    // Names are left for the IPC path to resolve, so this needs no lookups
+    pub fn to_ipc(&self, monitor: Option<String>) -> Ipc {
+        if let Some(name) = &self.playlist {
+            return Ipc::StartPlaylist {
+                monitor,
+                name: Some(name.clone()),
+                playlist: None,
+            };
+        }
+        if let Some(name) = &self.wallpaper {
+            return Ipc::SetNamed {
+                monitor,
+                name: name.clone(),
+                properties: self.properties.clone(),
+            };
+        }
+        named_ipc(
+            monitor,
+            self.path.clone(),
+            self.url.clone(),
+            self.properties.clone(),
+        )
+    }
+
+    pub fn describe(&self) -> String {
+        match (&self.wallpaper, &self.path, &self.url, &self.playlist) {
+            (Some(name), ..) => format!("wallpaper {name}"),
+            (_, Some(path), ..) => format!("path {path}"),
+            (_, _, Some(url), _) => format!("url {url}"),
+            (.., Some(name)) => format!("playlist {name}"),
+            _ => "nothing".to_string(),
+        }
+    }
+}
+
+// A [[schedule]] entry, the same fields as a monitor with a window and an optional monitor
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScheduleRule {
+    from: String,
+    to: String,
+    // A connector or alias, every monitor if left out
+    monitor: Option<String>,
+    wallpaper: Option<String>,
+    path: Option<String>,
+    url: Option<String>,
+    playlist: Option<String>,
+    #[serde(default)]
+    properties: Properties,
+}
+
+// A [[power]] entry
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PowerRule {
+    // battery, low or power_saver
+    on: String,
+    // With on = "low", the charge in percent
+    below: Option<u8>,
+    // pause, frame_rate or fallback
+    action: String,
+    fps: Option<u32>,
+    image: Option<String>,
+    color: Option<String>,
+}
+
+// The [fallback] table, one of wallpaper, image or color
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FallbackTable {
+    wallpaper: Option<String>,
+    image: Option<String>,
+    color: Option<String>,
+    crashes: Option<u32>,
+    // Seconds
+    timeout: Option<u64>,
+    retry: Option<u64>,
+}
+
+// What a monitor shows instead of a page that won't stay up
+#[derive(Debug, Clone, PartialEq)]
+pub enum Show {
+    // A [[wallpapers]] entry or library wallpaper
+    Wallpaper(String),
+    Image(String),
+    Color(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fallback {
+    pub show: Show,
+    // Renderer crashes in a row that are reloaded, the next one shows it instead
+    pub crashes: u32,
+    pub timeout: Duration,
+    // How often a remote page that failed to load is checked on, and shown again once it's up
+    pub retry: Option<Duration>,
+}
+
+// The frame around a monitor's panel, in logical pixels, hidden from spanned wallpapers
+// so lines stay straight from one monitor to the next
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Bezel {
+    pub left: i32,
+    pub right: i32,
+    pub top: i32,
+    pub bottom: i32,
+}
+
+// Entries without a path or url are the ones myppm installs into the wallpapers directory
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Named {
+    pub name: String,
+    pub path: Option<String>,
+    pub url: Option<String>,
+    #[serde(default)]
+    pub properties: Properties,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct File {
+    defaults: Defaults,
+    // Friendly name -> connector
+    aliases: BTreeMap<String, String>,
+    // Keyed by connector or alias
+    monitors: BTreeMap<String, Spanned<Target>>,
+    wallpapers: Vec<Spanned<Named>>,
+    playlists: BTreeMap<String, Spanned<Playlist>>,
+    // Keyed by connector or alias
+    bezels: BTreeMap<String, Bezel>,
+    // Where sunrise and sunset are worked out for
+    location: Option<Location>,
+    schedule: Vec<Spanned<ScheduleRule>>,
+    power: Vec<Spanned<PowerRule>>,
+    fallback: Option<Spanned<FallbackTable>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub defaults: Defaults,
+    pub aliases: BTreeMap<String, String>,
+    // Keyed by connector, aliases already resolved
+    pub monitors: BTreeMap<String, Target>,
+    pub wallpapers: BTreeMap<String, Named>,
+    pub playlists: BTreeMap<String, Playlist>,
+    pub schedule: Schedule,
+    pub power: Vec<power::Rule>,
+    pub fallback: Option<Fallback>,
+    // Keyed by connector, aliases already resolved
+    pub bezels: BTreeMap<String, Bezel>,
+    // The managed wallpapers directory, where names not in [[wallpapers]] are looked up
+    pub library: PathBuf,
+}
+
+impl Config {
+    pub fn empty(wallpapers_dir: &Path) -> Self {
+        Self {
+            library: wallpapers_dir.to_path_buf(),
+            ..Self::default()
+        }
+    }
+
+    // A missing file is the same as an empty one
+    pub fn load(file: &Path, wallpapers_dir: &Path) -> Result<Self> {
+        let contents = match fs::read_to_string(file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::empty(wallpapers_dir));
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", file.display())),
+        };
+
+        Self::parse(&contents, file, wallpapers_dir)
+    }
+
+    fn parse(contents: &str, file: &Path, wallpapers_dir: &Path) -> Result<Self> {
+        let located = |span: Option<Range<usize>>, message: &str| match span {
+            Some(span) => format!(
+                "{}:{}: {message}",
+                file.display(),
+                line_of(contents, span.start)
+            ),
+            None => format!("{}: {message}", file.display()),
+        };
+
+        let parsed: File = toml::from_str(contents)
+            .map_err(|e| anyhow::anyhow!(located(e.span(), e.message().trim())))?;
+
+        // Relative paths are relative to the config file
+        let dir = file.parent().unwrap_or(Path::new("."));
+
+        let mut wallpapers = BTreeMap::new();
+        for entry in parsed.wallpapers {
+            let span = entry.span();
+            let mut named = entry.into_inner();
+            match (&named.path, &named.url) {
+                (Some(_), Some(_)) => bail!(located(
+                    Some(span),
+                    "a wallpaper can't have both a path and a url"
+                )),
+                (Some(path), None) => named.path = Some(expand(path, dir)?),
+                (None, Some(_)) => {}
+                (None, None) => {
+                    named.path = Some(path_str(&wallpapers_dir.join(&named.name))?);
+                }
+            }
+            if wallpapers.contains_key(&named.name) {
+                bail!(located(
+                    Some(span),
+                    &format!("duplicate wallpaper name {:?}", named.name)
+                ));
+            }
+            wallpapers.insert(named.name.clone(), named);
+        }
+
+        let mut playlists = BTreeMap::new();
+        for (name, playlist) in parsed.playlists {
+            let span = playlist.span();
+            let mut playlist = playlist.into_inner();
+            if let Err(e) = playlist::validate(&playlist) {
+                bail!(located(Some(span), &format!("playlist {name:?}: {e}")));
+            }
+            for entry in playlist.entries.iter_mut() {
+                if let PlaylistEntry::Item {
+                    path: Some(path), ..
+                } = entry
+                {
+                    *path = expand(path, dir)?;
+                }
+            }
+            playlists.insert(name, playlist);
+        }
+
+        // Shared by monitors and schedule rules
+        let check_target = |what: &str, target: &mut Target| -> Result<(), String> {
+            let given = [
+                &target.wallpaper,
+                &target.path,
+                &target.url,
+                &target.playlist,
+            ]
+            .iter()
+            .filter(|t| t.is_some())
+            .count();
+            if given != 1 {
+                return Err(format!(
+                    "{what} needs exactly one of wallpaper, path, url or playlist"
+                ));
+            }
+            if let Some(playlist) = &target.playlist
+                && !playlists.contains_key(playlist)
+            {
+                return Err(format!("{what} uses unknown playlist {playlist:?}"));
+            }
+            if let Some(wallpaper) = &target.wallpaper
+                && !wallpapers.contains_key(wallpaper)
+                && library::get(wallpapers_dir, wallpaper).is_err()
+            {
+                return Err(format!("{what} uses unknown wallpaper {wallpaper:?}"));
+            }
+            if let Some(path) = &target.path {
+                target.path = Some(expand(path, dir).map_err(|e| format!("{e:#}"))?);
+            }
+            Ok(())
+        };
+
+        let mut monitors = BTreeMap::new();
+        for (name, target) in parsed.monitors {
+            let span = target.span();
+            let mut target = target.into_inner();
+            if let Err(e) = check_target(&format!("monitor {name:?}"), &mut target) {
+                bail!(located(Some(span), &e));
+            }
+
+            let connector = parsed.aliases.get(&name).cloned().unwrap_or(name);
+            monitors.insert(connector, target);
+        }
+
+        let mut rules = Vec::new();
+        for (i, rule) in parsed.schedule.into_iter().enumerate() {
+            let span = rule.span();
+            let rule = rule.into_inner();
+            let what = format!("schedule rule {}", i + 1);
+
+            let mut target = Target {
+                wallpaper: rule.wallpaper,
+                path: rule.path,
+                url: rule.url,
+                playlist: rule.playlist,
+                properties: rule.properties,
+            };
+            if let Err(e) = check_target(&what, &mut target) {
+                bail!(located(Some(span), &e));
+            }
+
+            let when = |s: &str| -> Result<When, String> {
+                let when = When::parse(s).map_err(|e| format!("{what}: {e:#}"))?;
+                if when.needs_location() && parsed.location.is_none() {
+                    return Err(format!(
+                        "{what} uses {s:?}, which needs a [location] with latitude and longitude"
+                    ));
+                }
+                Ok(when)
+            };
+            let (from, to) = match (when(&rule.from), when(&rule.to)) {
+                (Ok(from), Ok(to)) => (from, to),
+                (Err(e), _) | (_, Err(e)) => bail!(located(Some(span), &e)),
+            };
+
+            rules.push(Rule {
+                from,
+                to,
+                monitor: rule
+                    .monitor
+                    .map(|m| parsed.aliases.get(&m).cloned().unwrap_or(m)),
+                target,
+            });
+        }
+
+        let mut power = Vec::new();
+        for (i, rule) in parsed.power.into_iter().enumerate() {
+            let span = rule.span();
+            let rule = rule.into_inner();
+            let what = format!("power rule {}", i + 1);
+            let fail =
+                |e: &str| anyhow::anyhow!(located(Some(span.clone()), &format!("{what} {e}")));
+
+            if rule.below.is_some() && rule.on != "low" {
+                return Err(fail("sets below, which only applies to on = \"low\""));
+            }
+            let on = match rule.on.as_str() {
+                "battery" => Condition::Battery,
+                "low" => match rule.below {
+                    Some(below @ 1..=100) => Condition::Low(below),
+                    Some(_) => return Err(fail("needs below to be a percentage from 1 to 100")),
+                    None => Condition::Low(power::DEFAULT_LOW_CHARGE),
+                },
+                "power_saver" => Condition::PowerSaver,
+                other => {
+                    return Err(fail(&format!(
+                        "has unknown condition {other:?}, expected battery, low or power_saver"
+                    )));
+                }
+            };
+
+            if rule.fps.is_some() && rule.action != "frame_rate" {
+                return Err(fail(
+                    "sets fps, which only applies to action = \"frame_rate\"",
+                ));
+            }
+            if (rule.image.is_some() || rule.color.is_some()) && rule.action != "fallback" {
+                return Err(fail(
+                    "sets image or color, which only apply to action = \"fallback\"",
+                ));
+            }
+            let mode = match rule.action.as_str() {
+                "pause" => PowerMode::Pause,
+                "frame_rate" => match rule.fps {
+                    Some(fps) if fps > 0 => PowerMode::FrameRate { fps },
+                    _ => return Err(fail("needs fps of at least 1")),
+                },
+                "fallback" => {
+                    let image = match rule.image {
+                        Some(image) => {
+                            let image = expand(&image, dir)?;
+                            if !Path::new(&image).is_file() {
+                                return Err(fail(&format!("uses missing image {image:?}")));
+                            }
+                            Some(image)
+                        }
+                        None => None,
+                    };
+                    PowerMode::Fallback {
+                        image,
+                        color: rule.color,
+                    }
+                }
+                other => {
+                    return Err(fail(&format!(
+                        "has unknown action {other:?}, expected pause, frame_rate or fallback"
+                    )));
+                }
+            };
+
+            power.push(power::Rule { on, mode });
+        }
+
+        let fallback = match parsed.fallback {
+            Some(table) => {
+                let span = table.span();
+                let table = table.into_inner();
+                let fail = |e: &str| {
+                    anyhow::anyhow!(located(Some(span.clone()), &format!("[fallback] {e}")))
+                };
+
+                let show = match (table.wallpaper, table.image, table.color) {
+                    (Some(wallpaper), None, None) => {
+                        if !wallpapers.contains_key(&wallpaper)
+                            && library::get(wallpapers_dir, &wallpaper).is_err()
+                        {
+                            return Err(fail(&format!("uses unknown wallpaper {wallpaper:?}")));
+                        }
+                        Show::Wallpaper(wallpaper)
+                    }
+                    (None, Some(image), None) => {
+                        let image = expand(&image, dir)?;
+                        if !Path::new(&image).is_file() {
+                            return Err(fail(&format!("uses missing image {image:?}")));
+                        }
+                        Show::Image(image)
+                    }
+                    (None, None, Some(color)) => Show::Color(color),
+                    _ => return Err(fail("needs exactly one of wallpaper, image or color")),
+                };
+                let crashes = match table.crashes {
+                    Some(0) => return Err(fail("needs crashes to be at least 1")),
+                    Some(crashes) => crashes,
+                    None => DEFAULT_CRASHES,
+                };
+                let timeout = match table.timeout {
+                    Some(0) => return Err(fail("needs timeout to be at least 1")),
+                    Some(timeout) => Duration::from_secs(timeout),
+                    None => DEFAULT_TIMEOUT,
+                };
+                let retry = match table.retry {
+                    Some(0) => return Err(fail("needs retry to be at least 1")),
+                    retry => retry.map(Duration::from_secs),
+                };
+                Some(Fallback {
+                    show,
+                    crashes,
+                    timeout,
+                    retry,
+                })
+            }
+            None => None,
+        };
+
+        let bezels = parsed
+            .bezels
+            .into_iter()
+            .map(|(name, bezel)| (parsed.aliases.get(&name).cloned().unwrap_or(name), bezel))
+            .collect();
+
+        Ok(Self {
+            defaults: parsed.defaults,
+            aliases: parsed.aliases,
+            monitors,
+            wallpapers,
+            playlists,
+            schedule: Schedule {
+                location: parsed.location,
+                rules,
+            },
+            power,
+            fallback,
+            bezels,
+            library: wallpapers_dir.to_path_buf(),
+        })
+    }
+
+    // Lets IPC clients use an alias wherever a connector is expected
+    pub fn connector(&self, monitor: &str) -> String {
+        self.aliases
+            .get(monitor)
+            .cloned()
+            .unwrap_or_else(|| monitor.to_string())
+    }
+
+    // The same for a list of them, dropping repeats
+    pub fn connectors(&self, monitors: Vec<String>) -> Vec<String> {
+        let mut connectors: Vec<String> = Vec::new();
+        for monitor in monitors {
+            let connector = self.connector(&monitor);
+            if !connectors.contains(&connector) {
+                connectors.push(connector);
+            }
+        }
+        connectors
+    }
+
+    // A [[wallpapers]] entry wins over a library wallpaper of the same name.
+    // Either's own properties are defaults, the given ones override them
+    pub fn named(
+        &self,
+        monitor: Option<String>,
+        name: &str,
+        properties: Properties,
+    ) -> Result<Ipc> {
+        if let Some(named) = self.wallpapers.get(name) {
+            let mut merged = named.properties.clone();
+            merged.extend(properties);
+            return Ok(named_ipc(
+                monitor,
+                named.path.clone(),
+                named.url.clone(),
+                merged,
+            ));
+        }
+
+        let entry = library::get(&self.library, name)?;
+        let mut merged = entry.manifest.properties;
+        merged.extend(properties);
+        Ok(named_ipc(
+            monitor,
+            Some(path_str(&entry.path)?),
+            None,
+            merged,
+        ))
+    }
+
+    // What to send, as if through IPC, to show this monitor's default wallpaper
+    pub fn monitor_ipc(&self, connector: &str) -> Option<Ipc> {
+        let target = self.monitors.get(connector)?;
+        let monitor = Some(connector.to_string());
+
+        if let Some(name) = &target.playlist {
+            return Some(Ipc::StartPlaylist {
+                monitor,
+                name: Some(name.clone()),
+                playlist: None,
+            });
+        }
+
+        if let Some(name) = &target.wallpaper {
+            return match self.named(monitor, name, target.properties.clone()) {
+                Ok(msg) => Some(msg),
+                Err(e) => {
+                    warn!(target: "config", connector, error = %format!("{e:#}"), "Default wallpaper is unavailable");
+                    None
+                }
+            };
+        }
+
+        Some(named_ipc(
+            monitor,
+            target.path.clone(),
+            target.url.clone(),
+            target.properties.clone(),
+        ))
+    }
+
+    pub fn load_timeout(&self) -> Duration {
+        self.fallback
+            .as_ref()
+            .map_or(DEFAULT_TIMEOUT, |f| f.timeout)
+    }
+
+    // What to send to show [fallback] on this monitor
+    pub fn fallback_ipc(&self, connector: &str) -> Option<Ipc> {
+        let monitor = Some(connector.to_string());
+        match &self.fallback.as_ref()?.show {
+            Show::Wallpaper(name) => match self.named(monitor, name, Properties::default()) {
+                Ok(msg) => Some(msg),
+                Err(e) => {
+                    warn!(target: "config", connector, error = %format!("{e:#}"), "Fallback wallpaper is unavailable");
+                    None
+                }
+            },
+            Show::Image(image) => Some(named_ipc(
+                monitor,
+                Some(image.clone()),
+                None,
+                Properties::default(),
+            )),
+            Show::Color(color) => {
+                let page =
+                    format!("<html><body style='margin:0;background:{color}'></body></html>");
+                let url = format!(
+                    "data:text/html,{}",
+                    utf8_percent_encode(&page, NON_ALPHANUMERIC)
+                );
+                Some(named_ipc(monitor, None, Some(url), Properties::default()))
+            }
+        }
+    }
+}
+
+fn named_ipc(
+    monitor: Option<String>,
+    path: Option<String>,
+    url: Option<String>,
+    properties: Properties,
+) -> Ipc {
+    match (path, url) {
+        (Some(path), _) => Ipc::SetPath {
+            monitor,
+            path,
+            properties,
+            span: Vec::new(),
+        },
+        (None, url) => Ipc::SetUrl {
+            monitor,
+            url: url.unwrap_or_default(),
+            properties,
+            span: Vec::new(),
+        },
+    }
+}
+
+fn line_of(contents: &str, offset: usize) -> usize {
+    contents[..offset.min(contents.len())].matches('\n').count() + 1
+}
+
+fn expand(path: &str, dir: &Path) -> Result<String> {
+    let expanded = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let home = std::env::var_os("HOME").context("Cannot expand ~, HOME is not set")?;
+            PathBuf::from(home).join(rest.trim_start_matches('/'))
+        }
+        _ => dir.join(path),
+    };
+    path_str(&expanded)
+}
+
+fn path_str(path: &Path) -> Result<String> {
+    path.to_str()
+        .map(str::to_string)
+        .with_context(|| format!("Path is not valid UTF-8: {}", path.display()))
+}
+
+fn modified(file: &Path) -> Option<SystemTime> {
+    fs::metadata(file).and_then(|m| m.modified()).ok()
+}
+
+// Polled rather than watched, editors replace the file in too many different ways
+pub async fn watch(file: PathBuf, tx: mpsc::UnboundedSender<TokioEvent>) {
+    info!(target: "config", file = %file.display(), "Watching");
+    let mut last = modified(&file);
+
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        let current = modified(&file);
+        if current == last {
+            continue;
+        }
+        last = current;
+
+        debug!(target: "config", "Changed on disk");
+        if tx.send(TokioEvent::ConfigChanged).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh directory for one test's config, with an empty library
+    fn scratch(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("maypaper-config-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("wallpapers")).unwrap();
+        dir
+    }
+
+    fn parse(dir: &Path, contents: &str) -> Result<Config> {
+        Config::parse(
+            contents,
+            &dir.join("wallpapers.toml"),
+            &dir.join("wallpapers"),
+        )
+    }
+
+    // The error's file:line: prefix, which is what editors jump to
+    fn error_line(dir: &Path, contents: &str) -> String {
+        let e = parse(dir, contents).unwrap_err().to_string();
+        let file = dir.join("wallpapers.toml");
+        let rest = e
+            .strip_prefix(&format!("{}:", file.display()))
+            .unwrap_or_else(|| panic!("unlocated error: {e}"));
+        rest.split_once(':').unwrap().0.to_string()
+    }
+
+    #[test]
+    fn valid_file_is_loaded() {
+        let dir = scratch("valid");
+        let config = parse(
+            &dir,
+            r#"
+[aliases]
+left = "DP-1"
+
+[monitors.left]
+playlist = "day"
+
+[monitors.HDMI-A-1]
+wallpaper = "clock"
+
+[[wallpapers]]
+name = "clock"
+path = "sites/clock"
+
+[[wallpapers]]
+name = "news"
+url = "https://example.org/"
+
+[playlists.day]
+interval = 60
+entries = [{ wallpaper = "clock" }, { wallpaper = "news" }]
+
+[[schedule]]
+from = "08:00"
+to = "18:00"
+monitor = "left"
+wallpaper = "news"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.connector("left"), "DP-1");
+        assert_eq!(config.monitors["DP-1"].describe(), "playlist day");
+        let clock = dir.join("sites/clock").to_string_lossy().into_owned();
+        assert_eq!(config.wallpapers["clock"].path.as_ref(), Some(&clock));
+        assert_eq!(config.playlists["day"].entries.len(), 2);
+        assert_eq!(config.schedule.rules[0].monitor.as_deref(), Some("DP-1"));
+        assert!(matches!(
+            config.monitor_ipc("HDMI-A-1"),
+            Some(Ipc::SetPath { path, .. }) if path == clock
+        ));
+    }
+
+    #[test]
+    fn unknown_keys_are_errors_on_their_line() {
+        let dir = scratch("unknown");
+        assert_eq!(
+            error_line(&dir, "[defaults]\nlayer = \"background\"\nbogus = 1\n"),
+            "3"
+        );
+        assert_eq!(
+            error_line(&dir, "[[wallpapers]]\nname = \"a\"\npaht = \"sites/a\"\n"),
+            "3"
+        );
+    }
+
+    #[test]
+    fn bad_playlist_is_reported_on_its_line() {
+        let dir = scratch("playlist");
+        let contents = "[defaults]\n\n[playlists.day]\ninterval = 0\nentries = [{ url = \"https://example.org/\" }]\n";
+        assert_eq!(error_line(&dir, contents), "3");
+        assert!(
+            parse(&dir, contents)
+                .unwrap_err()
+                .to_string()
+                .contains("playlist \"day\"")
+        );
+    }
+
+    #[test]
+    fn bad_schedule_is_reported_on_its_line() {
+        let dir = scratch("schedule");
+        let contents = "[[schedule]]\nfrom = \"08:00\"\nto = \"18:00\"\nurl = \"https://example.org/\"\n\n[[schedule]]\nfrom = \"sunset\"\nto = \"sunrise\"\nurl = \"https://example.org/\"\n";
+        assert_eq!(error_line(&dir, contents), "6");
+        assert!(
+            parse(&dir, contents)
+                .unwrap_err()
+                .to_string()
+                .contains("[location]")
+        );
+    }
+
+    #[test]
+    fn color_fallback_is_a_page_of_that_color() {
+        let dir = scratch("color");
+        let config = parse(&dir, "[fallback]\ncolor = \"#123456\"\n").unwrap();
+        let Some(Ipc::SetUrl { monitor, url, .. }) = config.fallback_ipc("DP-1") else {
+            panic!("not a url");
+        };
+        assert_eq!(monitor.as_deref(), Some("DP-1"));
+        let page = url.strip_prefix("data:text/html,").unwrap();
+        let page = percent_encoding::percent_decode_str(page)
+            .decode_utf8()
+            .unwrap();
+        assert!(page.contains("background:#123456"));
+        assert_eq!(config.fallback.unwrap().crashes, DEFAULT_CRASHES);
+    }
+
+    #[test]
+    fn image_fallback_must_exist() {
+        let dir = scratch("image");
+        assert!(parse(&dir, "[fallback]\nimage = \"still.png\"\n").is_err());
+
+        fs::write(dir.join("still.png"), b"").unwrap();
+        let config = parse(&dir, "[fallback]\nimage = \"still.png\"\n").unwrap();
+        let still = dir.join("still.png").to_string_lossy().into_owned();
+        assert!(matches!(
+            config.fallback_ipc("DP-1"),
+            Some(Ipc::SetPath { path, .. }) if path == still
+        ));
+    }
+
+    #[test]
+    fn named_fallback_goes_through_wallpapers() {
+        let dir = scratch("named");
+        assert!(parse(&dir, "[fallback]\nwallpaper = \"calm\"\n").is_err());
+
+        let config = parse(
+            &dir,
+            "[fallback]\nwallpaper = \"calm\"\n\n[[wallpapers]]\nname = \"calm\"\nurl = \"https://example.org/\"\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            config.fallback_ipc("DP-1"),
+            Some(Ipc::SetUrl { url, .. }) if url == "https://example.org/"
+        ));
+    }
+}
@@ -0,0 +1,245 @@
+use std::fs::File;
+use std::io::Read;
+
+use anyhow::{Context, Result};
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::event::{Ipc, IpcReply, Properties, TokioEvent};
+use crate::inject::Assignments;
+use crate::ipc;
+
+pub const TOKEN_HEADER: &str = "x-maypaper-token";
+
+// What a page may ask of the daemon, always about the monitor it is shown on
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Control {
+    SetProperty {
+        key: String,
+        value: serde_json::Value,
+    },
+    SetNamed {
+        name: String,
+        #[serde(default)]
+        properties: Properties,
+    },
+    // Steps the playlist running on the page's monitor
+    Next,
+}
+
+impl Control {
+    fn into_ipc(self, connector: String) -> Ipc {
+        match self {
+            Control::SetProperty { key, value } => Ipc::SetProperty {
+                monitor: Some(connector),
+                key,
+                value,
+            },
+            Control::SetNamed { name, properties } => Ipc::SetNamed {
+                monitor: Some(connector),
+                name,
+                properties,
+            },
+            Control::Next => Ipc::Next {
+                monitor: Some(connector),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ControlRequest {
+    connector: String,
+    #[serde(flatten)]
+    control: Control,
+}
+
+#[derive(Clone)]
+struct ControlState {
+    path: String,
+    token: String,
+    assignments: Assignments,
+    tx: mpsc::UnboundedSender<TokioEvent>,
+}
+
+pub fn new_token() -> Result<String> {
+    let mut bytes = [0u8; 16];
+    File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .context("Failed to read /dev/urandom")?;
+
+    Ok(bytes.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+pub fn control_router(
+    path: &str,
+    token: &str,
+    assignments: Assignments,
+    tx: mpsc::UnboundedSender<TokioEvent>,
+) -> Router {
+    let state = ControlState {
+        path: path.to_string(),
+        token: token.to_string(),
+        assignments,
+        tx,
+    };
+
+    Router::new()
+        .route("/api/control", post(control))
+        .with_state(state)
+}
+
+async fn control(
+    State(state): State<ControlState>,
+    headers: HeaderMap,
+    Json(req): Json<ControlRequest>,
+) -> Response {
+    let authorized = headers
+        .get(TOKEN_HEADER)
+        .is_some_and(|t| t.as_bytes() == state.token.as_bytes());
+    if !authorized {
+        warn!(target: "control", path = %state.path, "Rejected control request with a bad token");
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    // A page may only act on a monitor that is actually showing it
+    let shown_here = state
+        .assignments
+        .read()
+        .unwrap()
+        .get(&req.connector)
+        .is_some_and(|a| a.path == state.path);
+    if !shown_here {
+        warn!(target: "control", path = %state.path, connector = %req.connector, "Rejected control request for another monitor");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    info!(target: "control", connector = %req.connector, control = ?req.control, "Received");
+    let reply = ipc::handle_msg_by_hand(req.control.into_ipc(req.connector), &state.tx).await;
+    let status = match reply {
+        IpcReply::Error { .. } => StatusCode::BAD_REQUEST,
+        _ => StatusCode::OK,
+    };
+
+    (status, Json(reply)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::event::{IpcEvent, PlaylistRequest};
+    use crate::inject::Assignment;
+
+    const TOKEN: &str = "0123456789abcdef";
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    // A router for /site, shown on DP-1 while /other is on DP-2
+    fn router() -> (Router, mpsc::UnboundedReceiver<TokioEvent>) {
+        let assignments = Assignments::default();
+        for (connector, path) in [("DP-1", "/site"), ("DP-2", "/other")] {
+            let assignment = Assignment {
+                path: path.to_string(),
+                properties: Properties::default(),
+            };
+            assignments
+                .write()
+                .unwrap()
+                .insert(connector.to_string(), assignment);
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        (control_router("/site", TOKEN, assignments, tx), rx)
+    }
+
+    fn request(token: Option<&str>, connector: &str) -> Request<Body> {
+        let mut request = Request::post("/api/control").header("content-type", "application/json");
+        if let Some(token) = token {
+            request = request.header(TOKEN_HEADER, token);
+        }
+        let body = format!(r#"{{"connector":"{connector}","type":"next"}}"#);
+        request.body(Body::from(body)).unwrap()
+    }
+
+    fn status(token: Option<&str>, connector: &str) -> StatusCode {
+        let (router, _rx) = router();
+        block_on(router.oneshot(request(token, connector)))
+            .unwrap()
+            .status()
+    }
+
+    #[test]
+    fn missing_or_wrong_token_is_unauthorized() {
+        assert_eq!(status(None, "DP-1"), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            status(Some("fedcba9876543210"), "DP-1"),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(status(Some(""), "DP-1"), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn other_monitors_are_forbidden() {
+        assert_eq!(status(Some(TOKEN), "DP-2"), StatusCode::FORBIDDEN);
+        assert_eq!(status(Some(TOKEN), "HDMI-A-1"), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn accepted_request_reaches_the_daemon() {
+        let (router, mut rx) = router();
+        block_on(async move {
+            let response = tokio::spawn(router.oneshot(request(Some(TOKEN), "DP-1")));
+            let Some(TokioEvent::IpcEvent(IpcEvent::RequestPlaylist(request))) = rx.recv().await
+            else {
+                panic!("not a playlist request");
+            };
+            assert_eq!(request.connector.as_deref(), Some("DP-1"));
+            assert!(matches!(request.request, PlaylistRequest::Next));
+            let _ = request.reply.send(Ok(()));
+            assert_eq!(response.await.unwrap().unwrap().status(), StatusCode::OK);
+        });
+    }
+
+    #[test]
+    fn controls_act_on_the_page_monitor() {
+        let parse = |json: &str| {
+            let req: ControlRequest = serde_json::from_str(json).unwrap();
+            req.control.into_ipc(req.connector)
+        };
+
+        let msg = parse(r#"{"connector":"DP-1","type":"set_property","key":"speed","value":2}"#);
+        assert!(matches!(
+            msg,
+            Ipc::SetProperty { monitor: Some(m), key, value }
+                if m == "DP-1" && key == "speed" && value == 2
+        ));
+
+        let msg = parse(r#"{"connector":"DP-1","type":"set_named","name":"clock"}"#);
+        assert!(matches!(
+            msg,
+            Ipc::SetNamed { monitor: Some(m), name, properties }
+                if m == "DP-1" && name == "clock" && properties.is_empty()
+        ));
+
+        let msg = parse(r#"{"connector":"DP-1","type":"next"}"#);
+        assert!(matches!(msg, Ipc::Next { monitor: Some(m) } if m == "DP-1"));
+    }
+}
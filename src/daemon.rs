@@ -0,0 +1,845 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::time::{Instant, sleep_until};
+use tracing::{debug, error, info, warn};
+
+use crate::Paths;
+use crate::config::{self, Bezel, Config};
+use crate::event::{
+    AcquireServer, Defaults, Event, Ipc, IpcEvent, IpcReply, MonitorState, PlaylistAction,
+    PlaylistCmd, PlaylistRequest, PowerMode, Properties, ReleaseServer, RequestPlaylist,
+    RequestProperty, RequestServer, RequestWebview, SetFrameRate, SetProperty, SetWebview, Span,
+    SyncData, TokioEvent, UiCmd, UiEvent, WebCmd, WebEvent,
+};
+use crate::loads::{Failed, Loads};
+use crate::pending::{self, Held, Pending};
+use crate::recovery::Recovery;
+use crate::state;
+use crate::webserver::{self, WebOptions};
+use crate::{ipc, metrics, playlist, power, schedule, span};
+
+// Events a slow subscriber can fall behind by before it misses some
+const EVENTS_BUFFER: usize = 64;
+
+// Everything start_tokio needs besides its channels
+pub struct Options {
+    pub web: WebOptions,
+    pub metrics_port: Option<u16>,
+    pub paths: Paths,
+    pub socket: PathBuf,
+}
+
+// --- Tokio runtime thread ---
+// Runs until the UI side drops its end of ui_event_rx
+pub fn start_tokio(
+    ui_tx: mpsc::UnboundedSender<UiCmd>,
+    mut ui_event_rx: mpsc::UnboundedReceiver<UiEvent>,
+    synx_rx: watch::Receiver<Arc<SyncData>>,
+    options: Options,
+    restore: state::Assignments,
+) {
+    let Options {
+        web: web_options,
+        metrics_port,
+        paths,
+        socket,
+    } = options;
+
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to build tokio runtime");
+
+        rt.block_on(async move {
+            let cache_urls = web_options.cache.is_some();
+            let (tokio_tx, mut tokio_rx) = mpsc::unbounded_channel::<TokioEvent>();
+            let (web_tx, web_rx) = mpsc::unbounded_channel::<WebCmd>();
+
+            // Nobody needs to be subscribed, so events sent without a receiver are dropped
+            let (events_tx, _) = broadcast::channel::<Event>(EVENTS_BUFFER);
+
+            tokio::spawn(ipc::ipc_server(tokio_tx.clone(), socket, events_tx.clone()));
+            info!(target: "tokio", "Started ipc_server");
+
+            tokio::spawn(webserver::web_manager(
+                tokio_tx.clone(),
+                web_rx,
+                web_options,
+                synx_rx.clone(),
+            ));
+            info!(target: "tokio", "Started web_manager");
+
+            if let Some(port) = metrics_port {
+                tokio::spawn(metrics::serve(port));
+                info!(target: "tokio", "Started metrics server");
+            }
+
+            let (playlist_tx, playlist_rx) = mpsc::unbounded_channel::<PlaylistCmd>();
+            tokio::spawn(playlist::playlist_manager(tokio_tx.clone(), playlist_rx));
+            info!(target: "tokio", "Started playlist_manager");
+
+            let mut config = match Config::load(&paths.config, &paths.wallpapers) {
+                Ok(config) => config,
+                Err(e) => {
+                    error!(target: "config", error = %format!("{e:#}"), "Failed to load, using defaults");
+                    Config::empty(&paths.wallpapers)
+                }
+            };
+            let _ = ui_tx.send(UiCmd::ApplyDefaults(config.defaults.clone()));
+            tokio::spawn(config::watch(paths.config.clone(), tokio_tx.clone()));
+            info!(target: "tokio", "Started config watcher");
+
+            let (config_tx, config_rx) = watch::channel(Arc::new(config.clone()));
+            tokio::spawn(schedule::scheduler(tokio_tx.clone(), config_rx.clone(), synx_rx.clone()));
+            info!(target: "tokio", "Started scheduler");
+
+            let (power_tx, power_rx) = watch::channel(PowerMode::Normal);
+            tokio::spawn(power::power_manager(ui_tx.clone(), config_rx, power_tx));
+            info!(target: "tokio", "Started power manager");
+
+            // Replayed once QML first reports the screens
+            let mut monitors_rx = synx_rx.clone();
+            let mut restore = Some(restore);
+            // Requests for monitors not reported yet
+            let mut pending = Pending::default();
+            // As last reported, to tell which monitors came and went
+            let mut known: Vec<String> = Vec::new();
+            // Set with SetFrameRate, in place of [defaults] frame_rate
+            let mut frame_rates: HashMap<String, u32> = HashMap::new();
+            // Renderer crashes of each monitor's page
+            let mut recovery = Recovery::default();
+            // Pages being loaded, and remote ones that failed to
+            let mut loads = Loads::default();
+            // Checks on remote pages for [fallback] retry
+            let client = reqwest::Client::new();
+
+            loop {
+                let next_load = loads.next_deadline();
+                tokio::select! {
+                    Ok(()) = monitors_rx.changed() => {
+                        let sync = monitors_rx.borrow_and_update().clone();
+
+                        // What was asked for before the monitors were known wins over what's restored
+                        let held = pending.take(&sync.connectors);
+                        let held_all = held.iter().any(|h| h.connector().is_none());
+                        let held_connectors: Vec<String> = held
+                            .iter()
+                            .filter_map(|h| h.connector().map(str::to_string))
+                            .collect();
+                        for held in held {
+                            info!(target: "tokio", connector = ?held.connector(), "Applying held request");
+                            match held {
+                                Held::Server(request_server) => {
+                                    handle_request_server(request_server, &synx_rx, &web_tx, &mut pending, &config, &mut loads);
+                                }
+                                Held::Webview(request_webview) => {
+                                    handle_request_webview(request_webview, &synx_rx, &web_tx, &ui_tx, &mut pending, &config, &mut loads);
+                                }
+                                Held::Playlist { connector, name, playlist } => {
+                                    // Whoever asked was answered when it was held
+                                    let (reply, _) = oneshot::channel();
+                                    let _ = playlist_tx.send(PlaylistCmd {
+                                        action: PlaylistAction::Start { name, playlist },
+                                        connectors: connector.map_or_else(|| sync.connectors.clone(), |c| vec![c]),
+                                        reply,
+                                    });
+                                }
+                            }
+                        }
+
+                        let removed: Vec<String> = known.iter().filter(|c| !sync.connectors.contains(c)).cloned().collect();
+                        let added: Vec<String> = sync.connectors.iter().filter(|c| !known.contains(c)).cloned().collect();
+                        known = sync.connectors.clone();
+
+                        // Unplugged, so its window is gone and the server it watched can go too
+                        for connector in removed {
+                            info!(target: "tokio", connector = %connector, "Monitor removed");
+                            recovery.forget(&connector);
+                            loads.forget(&connector);
+                            let _ = web_tx.send(WebCmd::ForgetConnector(connector));
+                        }
+
+                        if sync.connectors.is_empty() {
+                            continue;
+                        }
+                        if held_all {
+                            restore = None;
+                            continue;
+                        }
+
+                        // Monitors plugged back in start on the placeholder, the UI side knows what they showed
+                        let Some(restore) = restore.take() else {
+                            for connector in added {
+                                if held_connectors.contains(&connector) {
+                                    continue;
+                                }
+                                info!(target: "tokio", connector = %connector, "Monitor added");
+                                match schedule::active(&config, &connector) {
+                                    Some(msg) => replay(msg, &tokio_tx),
+                                    None => {
+                                        let _ = ui_tx.send(UiCmd::RecallMonitor(connector));
+                                    }
+                                }
+                            }
+                            continue;
+                        };
+
+                        // An active schedule window wins over what was last shown, which wins over the default.
+                        // A spanned wallpaper is replayed once, for all of its monitors
+                        let mut restored: Vec<String> = Vec::new();
+                        for connector in sync.connectors.iter() {
+                            if held_connectors.contains(connector) || restored.contains(connector) {
+                                continue;
+                            }
+                            let scheduled = schedule::active(&config, connector);
+                            let msg = match (scheduled, restore.get(connector)) {
+                                (Some(msg), _) => msg,
+                                (None, Some(saved)) => saved.to_ipc(connector),
+                                (None, None) => match config.monitor_ipc(connector) {
+                                    Some(msg) => msg,
+                                    None => continue,
+                                },
+                            };
+                            info!(target: "tokio", connector = %connector, "Restoring wallpaper");
+                            restored.extend(msg.span().iter().cloned());
+                            replay(msg, &tokio_tx);
+                        }
+                    }
+
+                    ui_evt = ui_event_rx.recv() => {
+                        match ui_evt {
+                            Some(UiEvent::RestoreMonitor(restore_monitor)) => {
+                                debug!(target: "tokio", restore_monitor=?restore_monitor, "Received from UI");
+                                let connector = restore_monitor.connector;
+                                let msg = restore_monitor.msg.or_else(|| config.monitor_ipc(&connector));
+                                if let Some(msg) = msg {
+                                    info!(target: "tokio", connector = %connector, "Restoring wallpaper of reconnected monitor");
+                                    replay(msg, &tokio_tx);
+                                }
+                            }
+                            Some(UiEvent::RenderProcessTerminated(terminated)) => {
+                                debug!(target: "tokio", terminated=?terminated, "Received from UI");
+                                let connector = terminated.connector;
+                                let limit = config.fallback.as_ref().map(|f| f.crashes);
+                                let crash = recovery.crashed(&connector, limit);
+                                warn!(target: "tokio", connector = %connector, reason = ?terminated.reason, exit_code = terminated.exit_code, crashes = crash.count, "Render process terminated");
+
+                                // Without a way to show the fallback, it keeps reloading
+                                let fallback = if crash.fall_back { config.fallback_ipc(&connector) } else { None };
+                                let _ = events_tx.send(Event::RenderProcessTerminated {
+                                    connector: connector.clone(),
+                                    reason: terminated.reason,
+                                    exit_code: terminated.exit_code,
+                                    crashes: crash.count,
+                                    reload_in_ms: fallback.is_none().then_some(crash.reload_in.as_millis() as u64),
+                                });
+
+                                match fallback {
+                                    Some(msg) => {
+                                        info!(target: "tokio", connector = %connector, crashes = crash.count, "Showing the fallback of a page that keeps crashing");
+                                        let reason = format!("its renderer crashed {} times in a row", crash.count);
+                                        show_fallback(connector, msg, reason, &mut loads, &events_tx, &tokio_tx);
+                                    }
+                                    None => {
+                                        info!(target: "tokio", connector = %connector, delay = ?crash.reload_in, "Reloading crashed page");
+                                        let ui_tx = ui_tx.clone();
+                                        tokio::spawn(async move {
+                                            tokio::time::sleep(crash.reload_in).await;
+                                            let _ = ui_tx.send(UiCmd::Reload(connector));
+                                        });
+                                    }
+                                }
+                            }
+                            Some(UiEvent::LoadFinished(load_finished)) => {
+                                debug!(target: "tokio", load_finished=?load_finished, "Received from UI");
+                                if let Some(failed) = loads.finished(&load_finished.connector, &load_finished.url, load_finished.error) {
+                                    load_failed(failed, &config, &mut loads, &events_tx, &tokio_tx);
+                                }
+                            }
+                            None => break,
+                        }
+                    }
+
+                    _ = sleep_until(next_load.unwrap_or_else(Instant::now)), if next_load.is_some() => {
+                        let now = Instant::now();
+                        for failed in loads.expired(now) {
+                            load_failed(failed, &config, &mut loads, &events_tx, &tokio_tx);
+                        }
+                        for (url, msg) in loads.due_retries(now) {
+                            retry(url, msg, config.load_timeout(), &client, &tokio_tx);
+                        }
+                    }
+
+                    tk = tokio_rx.recv() => {
+                        match tk {
+                            Some(event) => match event {
+                                TokioEvent::IpcEvent(ipc_event) => match ipc_event {
+                                    IpcEvent::RequestServer(mut request_server) => {
+                                        debug!(target: "tokio", request_server=?request_server, "Received");
+                                        request_server.connector = request_server.connector.map(|m| config.connector(&m));
+                                        request_server.span = config.connectors(request_server.span);
+                                        if request_server.by_hand {
+                                            stop_playlists(request_server.connector.as_ref(), &request_server.span, &playlist_tx);
+                                        }
+                                        handle_request_server(request_server, &synx_rx, &web_tx, &mut pending, &config, &mut loads);
+                                    }
+                                    IpcEvent::RequestWebview(mut request_webview) => {
+                                        debug!(target: "tokio", request_webview=?request_webview, "Received");
+                                        request_webview.connector = request_webview.connector.map(|m| config.connector(&m));
+                                        request_webview.span = config.connectors(request_webview.span);
+                                        if request_webview.by_hand {
+                                            stop_playlists(request_webview.connector.as_ref(), &request_webview.span, &playlist_tx);
+                                        }
+                                        if cache_urls && remote(&request_webview.url) {
+                                            // Goes through a local proxy server, like a path would
+                                            let request_server = RequestServer {
+                                                path: request_webview.url,
+                                                connector: request_webview.connector,
+                                                properties: request_webview.properties,
+                                                span: request_webview.span,
+                                                by_hand: request_webview.by_hand,
+                                                reply: request_webview.reply,
+                                            };
+                                            handle_request_server(request_server, &synx_rx, &web_tx, &mut pending, &config, &mut loads);
+                                        } else {
+                                            handle_request_webview(request_webview, &synx_rx, &web_tx, &ui_tx, &mut pending, &config, &mut loads);
+                                        }
+                                    }
+                                    IpcEvent::RefreshCache(refresh_cache) => {
+                                        debug!(target: "tokio", refresh_cache=?refresh_cache, "Received");
+                                        let _ = web_tx.send(WebCmd::RefreshCache(refresh_cache));
+                                    }
+                                    IpcEvent::RequestProperty(mut request_property) => {
+                                        debug!(target: "tokio", request_property=?request_property, "Received");
+                                        request_property.connector = request_property.connector.map(|m| config.connector(&m));
+                                        handle_request_property(request_property, &synx_rx, &web_tx, &ui_tx);
+                                    }
+                                    IpcEvent::RequestNamed(request_named) => {
+                                        debug!(target: "tokio", request_named=?request_named, "Received");
+                                        let connector = request_named.connector.map(|m| config.connector(&m));
+                                        match config.named(connector, &request_named.name, request_named.properties) {
+                                            // Through the IPC path, so the wallpaper it names is checked the same way.
+                                            // Spawned, as its pages' loads are answered by this loop
+                                            Ok(msg) => {
+                                                let tx = tokio_tx.clone();
+                                                tokio::spawn(async move {
+                                                    let reply = match request_named.by_hand {
+                                                        true => ipc::handle_msg_by_hand(msg, &tx).await,
+                                                        false => ipc::handle_msg(msg, &tx).await,
+                                                    };
+                                                    let result = match reply {
+                                                        IpcReply::Error { message } => Err(message),
+                                                        _ => Ok(()),
+                                                    };
+                                                    let _ = request_named.reply.send(result);
+                                                });
+                                            }
+                                            Err(e) => {
+                                                let _ = request_named.reply.send(Err(format!("{e:#}")));
+                                            }
+                                        }
+                                    }
+                                    IpcEvent::RequestPlaylist(request_playlist) => {
+                                        debug!(target: "tokio", request_playlist=?request_playlist, "Received");
+                                        handle_request_playlist(request_playlist, &config, &synx_rx, &playlist_tx, &mut pending);
+                                    }
+                                    IpcEvent::RequestSchedule(request_schedule) => {
+                                        debug!(target: "tokio", request_schedule=?request_schedule, "Received");
+                                        let _ = request_schedule.reply.send(schedule::upcoming(&config));
+                                    }
+                                    IpcEvent::RequestFrameRate(request_frame_rate) => {
+                                        debug!(target: "tokio", request_frame_rate=?request_frame_rate, "Received");
+                                        let connectors = match request_frame_rate.connector {
+                                            Some(m) => vec![config.connector(&m)],
+                                            None => synx_rx.borrow().connectors.clone(),
+                                        };
+                                        for connector in connectors {
+                                            match request_frame_rate.fps {
+                                                Some(fps) => frame_rates.insert(connector.clone(), fps),
+                                                None => frame_rates.remove(&connector),
+                                            };
+                                            let _ = ui_tx.send(UiCmd::SetFrameRate(SetFrameRate {
+                                                connector,
+                                                fps: request_frame_rate.fps,
+                                            }));
+                                        }
+                                    }
+                                    IpcEvent::RequestState(request_state) => {
+                                        debug!(target: "tokio", request_state=?request_state, "Received");
+                                        let power = power_rx.borrow().clone();
+                                        let monitors = monitor_states(&synx_rx.borrow(), &config.defaults, &frame_rates, &power);
+                                        let _ = request_state.reply.send((power, monitors));
+                                    }
+                                    IpcEvent::ReloadConfig(reload_config) => {
+                                        debug!(target: "tokio", reload_config=?reload_config, "Received");
+                                        let result = reload(&mut config, &paths, &synx_rx, &ui_tx, &tokio_tx);
+                                        let _ = config_tx.send(Arc::new(config.clone()));
+                                        if let Some(reply) = reload_config.reply {
+                                            let _ = reply.send(result);
+                                        }
+                                    }
+                                },
+
+                                TokioEvent::ConfigChanged => {
+                                    let _ = reload(&mut config, &paths, &synx_rx, &ui_tx, &tokio_tx);
+                                    let _ = config_tx.send(Arc::new(config.clone()));
+                                }
+
+                                TokioEvent::WebEvent(web_event) => match web_event {
+                                    WebEvent::SetWebview(mut set_webview) => {
+                                        debug!(target: "tokio", set_webview=?set_webview, "WebEvent -> UI");
+                                        set_webview.persist = !loads.showing(&set_webview.connector, &set_webview.url);
+                                        let _ = ui_tx.send(UiCmd::SetWebview(set_webview));
+                                    }
+                                    WebEvent::AcquireFailed(acquire_failed) => {
+                                        debug!(target: "tokio", acquire_failed=?acquire_failed, "Received from web");
+                                        if let Some(failed) = loads.unserved(&acquire_failed.connector, acquire_failed.error) {
+                                            load_failed(failed, &config, &mut loads, &events_tx, &tokio_tx);
+                                        }
+                                    }
+                                },
+                            },
+                            None => break,
+                        }
+                    }
+                }
+            }
+        });
+    });
+}
+
+// Goes through the IPC path, so a wallpaper deleted since is rejected the same way.
+// Spawned, as some messages are answered by the tokio loop that calls this
+fn replay(msg: Ipc, tx: &mpsc::UnboundedSender<TokioEvent>) {
+    let tx = tx.clone();
+    tokio::spawn(async move {
+        let kind = msg.kind();
+        if let IpcReply::Error { message } = ipc::handle_msg(msg, &tx).await {
+            warn!(target: "tokio", kind, error = %message, "Failed to apply wallpaper");
+        }
+    });
+}
+
+fn remote(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+// Tells subscribers, and shows [fallback] in place of the page, unless the fallback is what failed
+fn load_failed(
+    failed: Failed,
+    config: &Config,
+    loads: &mut Loads,
+    events_tx: &broadcast::Sender<Event>,
+    tokio_tx: &mpsc::UnboundedSender<TokioEvent>,
+) {
+    let Failed {
+        connector,
+        url,
+        error,
+        fallback,
+        retry,
+    } = failed;
+    warn!(target: "tokio", connector = %connector, url = ?url, error = %error, "Page failed to load");
+    let _ = events_tx.send(Event::LoadFailed {
+        connector: connector.clone(),
+        url,
+        error: error.clone(),
+    });
+    if fallback {
+        warn!(target: "tokio", connector = %connector, "The fallback failed to load too, leaving it");
+        return;
+    }
+
+    if let (Some(msg), Some(every)) = (retry, config.fallback.as_ref().and_then(|f| f.retry)) {
+        loads.retry_later(&connector, msg, every);
+    }
+    let Some(msg) = config.fallback_ipc(&connector) else {
+        return;
+    };
+    info!(target: "tokio", connector = %connector, "Showing the fallback of a page that failed to load");
+    let reason = format!("its page failed to load: {error}");
+    show_fallback(connector, msg, reason, loads, events_tx, tokio_tx);
+}
+
+// Through the IPC path like any wallpaper, but marked so it isn't saved as the monitor's own.
+// A restart or replug brings back what it stands in for
+fn show_fallback(
+    connector: String,
+    msg: Ipc,
+    reason: String,
+    loads: &mut Loads,
+    events_tx: &broadcast::Sender<Event>,
+    tokio_tx: &mpsc::UnboundedSender<TokioEvent>,
+) {
+    loads.fall_back(&connector);
+    replay(msg, tokio_tx);
+    let _ = events_tx.send(Event::FallbackShown { connector, reason });
+}
+
+// Shows a remote page that failed again once it answers. Its load ends the retries,
+// unless it fails again
+fn retry(
+    url: String,
+    msg: Ipc,
+    timeout: Duration,
+    client: &reqwest::Client,
+    tx: &mpsc::UnboundedSender<TokioEvent>,
+) {
+    let client = client.clone();
+    let tx = tx.clone();
+    tokio::spawn(async move {
+        match client.get(&url).timeout(timeout).send().await {
+            Ok(response) if response.status().is_success() => {
+                info!(target: "tokio", url = %url, "Remote page is back, showing it again");
+                if let IpcReply::Error { message } = ipc::handle_msg(msg, &tx).await {
+                    warn!(target: "tokio", url = %url, error = %message, "Failed to show the remote page again");
+                }
+            }
+            Ok(response) => {
+                debug!(target: "tokio", url = %url, status = %response.status(), "Remote page still down");
+            }
+            Err(e) => debug!(target: "tokio", url = %url, error = %e, "Remote page still down"),
+        }
+    });
+}
+
+// What [fallback] retry sends again while a remote page is down.
+// A spanned page is retried once, from its first monitor
+fn retry_msg(
+    config: &Config,
+    url: &str,
+    connector: &str,
+    properties: &Properties,
+    span: &[String],
+) -> Option<Ipc> {
+    config.fallback.as_ref()?.retry?;
+    if !remote(url) || span.first().is_some_and(|first| first != connector) {
+        return None;
+    }
+    Some(Ipc::SetUrl {
+        monitor: Some(connector.to_string()),
+        url: url.to_string(),
+        properties: properties.clone(),
+        span: span.to_vec(),
+    })
+}
+
+// Swaps in the new config, and shows the new default on monitors whose default changed.
+// A config that fails to parse is reported, and the old one kept
+fn reload(
+    config: &mut Config,
+    paths: &Paths,
+    synx_rx: &watch::Receiver<Arc<SyncData>>,
+    ui_tx: &mpsc::UnboundedSender<UiCmd>,
+    tokio_tx: &mpsc::UnboundedSender<TokioEvent>,
+) -> Result<(), String> {
+    let new = match Config::load(&paths.config, &paths.wallpapers) {
+        Ok(new) => new,
+        Err(e) => {
+            let message = format!("{e:#}");
+            error!(target: "config", error = %message, "Failed to reload, keeping the previous config");
+            return Err(message);
+        }
+    };
+    info!(target: "config", "Reloaded");
+
+    if new.defaults != config.defaults {
+        let _ = ui_tx.send(UiCmd::ApplyDefaults(new.defaults.clone()));
+    }
+
+    let connectors = synx_rx.borrow().connectors.clone();
+    let old = std::mem::replace(config, new);
+    for connector in connectors.iter() {
+        // The scheduler shows the default itself once the window ends
+        if schedule::active(config, connector).is_some() {
+            continue;
+        }
+        let before = old
+            .monitor_ipc(connector)
+            .and_then(|m| serde_json::to_value(m).ok());
+        let Some(msg) = config.monitor_ipc(connector) else {
+            continue;
+        };
+        if serde_json::to_value(&msg).ok() == before {
+            continue;
+        }
+        info!(target: "config", connector = %connector, "Default wallpaper changed");
+        replay(msg, tokio_tx);
+    }
+
+    Ok(())
+}
+
+fn handle_request_playlist(
+    request_playlist: RequestPlaylist,
+    config: &Config,
+    synx_rx: &watch::Receiver<Arc<SyncData>>,
+    playlist_tx: &mpsc::UnboundedSender<PlaylistCmd>,
+    pending: &mut Pending,
+) {
+    let RequestPlaylist {
+        connector,
+        request,
+        reply,
+    } = request_playlist;
+    let connector = connector.map(|m| config.connector(&m));
+
+    let action = match request {
+        PlaylistRequest::Start { name, playlist } => {
+            let resolved = match (name, playlist) {
+                (_, Some(playlist)) => playlist::validate(&playlist)
+                    .map(|_| ("inline".to_string(), playlist))
+                    .map_err(|e| format!("{e:#}")),
+                (Some(name), None) => match config.playlists.get(&name) {
+                    Some(playlist) => Ok((name, playlist.clone())),
+                    None => Err(format!("No playlist named {name} in wallpapers.toml")),
+                },
+                (None, None) => Err("Either a playlist name or a playlist is needed".to_string()),
+            };
+            match resolved {
+                Ok((name, playlist)) => PlaylistAction::Start { name, playlist },
+                Err(message) => {
+                    let _ = reply.send(Err(message));
+                    return;
+                }
+            }
+        }
+        PlaylistRequest::Stop => PlaylistAction::Stop,
+        PlaylistRequest::Next => PlaylistAction::Next,
+        PlaylistRequest::Prev => PlaylistAction::Prev,
+    };
+
+    // Starting on every monitor means the ones known now, the others only act on running playlists
+    let known = synx_rx.borrow().connectors.clone();
+    let action = match action {
+        PlaylistAction::Start { name, playlist }
+            if !pending::ready(connector.as_deref(), &[], &known) =>
+        {
+            info!(target: "tokio", connector = ?connector, "Holding playlist until the monitor appears");
+            pending.hold(Held::Playlist {
+                connector,
+                name,
+                playlist,
+            });
+            let _ = reply.send(Ok(()));
+            return;
+        }
+        action => action,
+    };
+    let connectors = match (connector, &action) {
+        (Some(connector), _) => vec![connector],
+        (None, PlaylistAction::Start { .. }) => known,
+        (None, _) => Vec::new(),
+    };
+
+    let _ = playlist_tx.send(PlaylistCmd {
+        action,
+        connectors,
+        reply,
+    });
+}
+
+// A wallpaper set by hand, which the playlists of its monitors would only cover up again.
+// No monitor stops every playlist
+fn stop_playlists(
+    connector: Option<&String>,
+    span: &[String],
+    playlist_tx: &mpsc::UnboundedSender<PlaylistCmd>,
+) {
+    let connectors: Vec<String> = connector.into_iter().chain(span).cloned().collect();
+    // Most monitors have no playlist to stop, which isn't worth answering
+    let (reply, _) = oneshot::channel();
+    let _ = playlist_tx.send(PlaylistCmd {
+        action: PlaylistAction::Stop,
+        connectors,
+        reply,
+    });
+}
+
+// Which monitors a wallpaper goes to, and for a spanned one the part each of them shows
+fn targets(
+    connector: Option<String>,
+    span: &[String],
+    sync: &SyncData,
+    bezels: &BTreeMap<String, Bezel>,
+) -> Vec<(String, Option<Span>)> {
+    if !span.is_empty() {
+        return match span::layout(span, &sync.monitors, bezels) {
+            Ok(spanned) => spanned.into_iter().map(|(c, s)| (c, Some(s))).collect(),
+            Err(e) => {
+                warn!(target: "tokio", error = %e, "Cannot span, showing the whole page on each monitor");
+                span.iter().map(|c| (c.clone(), None)).collect()
+            }
+        };
+    }
+
+    match connector {
+        Some(connector) => vec![(connector, None)],
+        None => sync.connectors.iter().map(|c| (c.clone(), None)).collect(),
+    }
+}
+
+fn handle_request_server(
+    mut request_server: RequestServer,
+    synx_rx: &watch::Receiver<Arc<SyncData>>,
+    web_tx: &mpsc::UnboundedSender<WebCmd>,
+    pending: &mut Pending,
+    config: &Config,
+    loads: &mut Loads,
+) {
+    let sync: Arc<SyncData> = synx_rx.borrow().clone();
+    if !pending::ready(
+        request_server.connector.as_deref(),
+        &request_server.span,
+        &sync.connectors,
+    ) {
+        info!(target: "tokio", connector = ?request_server.connector, span = ?request_server.span, "Holding wallpaper until the monitor appears");
+        if let Some(reply) = request_server.reply.take() {
+            let _ = reply.send(Ok(()));
+        }
+        pending.hold(Held::Server(request_server));
+        return;
+    }
+
+    let targets = targets(
+        request_server.connector,
+        &request_server.span,
+        &sync,
+        &config.bezels,
+    );
+    let request = loads.wait(request_server.reply, targets.len());
+    for (connector, span) in targets {
+        let retry = retry_msg(
+            config,
+            &request_server.path,
+            &connector,
+            &request_server.properties,
+            &request_server.span,
+        );
+        loads.start(&connector, request, config.load_timeout(), retry);
+        let acquire = AcquireServer {
+            path: request_server.path.clone(),
+            connector,
+            properties: request_server.properties.clone(),
+            span,
+        };
+        let _ = web_tx.send(WebCmd::AcquireServer(acquire));
+    }
+}
+
+fn handle_request_webview(
+    mut request_webview: RequestWebview,
+    synx_rx: &watch::Receiver<Arc<SyncData>>,
+    web_tx: &mpsc::UnboundedSender<WebCmd>,
+    ui_tx: &mpsc::UnboundedSender<UiCmd>,
+    pending: &mut Pending,
+    config: &Config,
+    loads: &mut Loads,
+) {
+    let sync: Arc<SyncData> = synx_rx.borrow().clone();
+    if !pending::ready(
+        request_webview.connector.as_deref(),
+        &request_webview.span,
+        &sync.connectors,
+    ) {
+        info!(target: "tokio", connector = ?request_webview.connector, span = ?request_webview.span, "Holding wallpaper until the monitor appears");
+        if let Some(reply) = request_webview.reply.take() {
+            let _ = reply.send(Ok(()));
+        }
+        pending.hold(Held::Webview(request_webview));
+        return;
+    }
+
+    let targets = targets(
+        request_webview.connector,
+        &request_webview.span,
+        &sync,
+        &config.bezels,
+    );
+    let request = loads.wait(request_webview.reply, targets.len());
+    for (connector, span) in targets {
+        let retry = retry_msg(
+            config,
+            &request_webview.url,
+            &connector,
+            &request_webview.properties,
+            &request_webview.span,
+        );
+        loads.start(&connector, request, config.load_timeout(), retry);
+        let fallback = loads.showing(&connector, &request_webview.url);
+        // Whatever server it showed before is no longer its to keep
+        let _ = web_tx.send(WebCmd::ReleaseServer(ReleaseServer {
+            connector: connector.clone(),
+        }));
+        let set_webview = SetWebview {
+            url: request_webview.url.clone(),
+            path: None,
+            connector,
+            properties: request_webview.properties.clone(),
+            span,
+            persist: !fallback,
+        };
+        let _ = ui_tx.send(UiCmd::SetWebview(set_webview));
+    }
+}
+
+// What each reported monitor's page renders at
+fn monitor_states(
+    sync: &SyncData,
+    defaults: &Defaults,
+    frame_rates: &HashMap<String, u32>,
+    power: &PowerMode,
+) -> Vec<MonitorState> {
+    let power_cap = match power {
+        PowerMode::FrameRate { fps } => Some(*fps),
+        _ => None,
+    };
+
+    sync.monitors
+        .iter()
+        .map(|monitor| {
+            let cap = frame_rates
+                .get(&monitor.name)
+                .copied()
+                .or((defaults.frame_rate > 0).then_some(defaults.frame_rate));
+            let refresh = (monitor.refresh_rate > 0.0).then(|| monitor.refresh_rate.round() as u32);
+            let frame_rate = match power {
+                PowerMode::Fallback { .. } => Some(0),
+                _ => [cap, power_cap, refresh].into_iter().flatten().min(),
+            };
+            MonitorState {
+                connector: monitor.name.clone(),
+                refresh_rate: monitor.refresh_rate,
+                frame_rate,
+            }
+        })
+        .collect()
+}
+
+fn handle_request_property(
+    request_property: RequestProperty,
+    synx_rx: &watch::Receiver<Arc<SyncData>>,
+    web_tx: &mpsc::UnboundedSender<WebCmd>,
+    ui_tx: &mpsc::UnboundedSender<UiCmd>,
+) {
+    let connectors = match request_property.connector {
+        Some(connector) => vec![connector],
+        None => synx_rx.borrow().connectors.clone(),
+    };
+
+    for connector in connectors {
+        let set_property = SetProperty {
+            connector,
+            key: request_property.key.clone(),
+            value: request_property.value.clone(),
+        };
+        // The server remembers it for reloads, the page gets it live
+        let _ = web_tx.send(WebCmd::SetProperty(set_property.clone()));
+        let _ = ui_tx.send(UiCmd::SetProperty(set_property));
+    }
+}
@@ -1,37 +1,548 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
 
-#[derive(Debug, Serialize, Deserialize)]
+// Arbitrary values handed to the wallpaper page, as globalThis.maypaper.properties
+pub type Properties = BTreeMap<String, serde_json::Value>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Ipc {
-    SetPath { monitor: Option<String>, path: String },
-    SetUrl { monitor: Option<String>, url: String },
+    SetPath {
+        monitor: Option<String>,
+        path: String,
+        #[serde(default, skip_serializing_if = "Properties::is_empty")]
+        properties: Properties,
+        // Monitors to stretch the one page across, instead of monitor
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        span: Vec<String>,
+    },
+    SetUrl {
+        monitor: Option<String>,
+        url: String,
+        #[serde(default, skip_serializing_if = "Properties::is_empty")]
+        properties: Properties,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        span: Vec<String>,
+    },
+    RefreshCache {
+        url: Option<String>,
+    },
+    SetProperty {
+        monitor: Option<String>,
+        key: String,
+        value: serde_json::Value,
+    },
+    ReloadConfig,
+    // A [[wallpapers]] entry of wallpapers.toml, or a wallpaper in the library
+    SetNamed {
+        monitor: Option<String>,
+        name: String,
+        #[serde(default, skip_serializing_if = "Properties::is_empty")]
+        properties: Properties,
+    },
+    // Either a [playlists] entry of wallpapers.toml by name, or a playlist given inline
+    StartPlaylist {
+        monitor: Option<String>,
+        name: Option<String>,
+        playlist: Option<Playlist>,
+    },
+    StopPlaylist {
+        monitor: Option<String>,
+    },
+    Next {
+        monitor: Option<String>,
+    },
+    Prev {
+        monitor: Option<String>,
+    },
+    // The schedule's window starts and ends over the next week
+    GetSchedule,
+    // Caps how often the page is rendered, None goes back to [defaults] frame_rate
+    SetFrameRate {
+        monitor: Option<String>,
+        fps: Option<u32>,
+    },
+    GetState,
+    // Answered with Ok, after which the connection carries every Event as it happens
+    Subscribe,
+}
+
+impl Ipc {
+    // Matches the serialized type tag
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Ipc::SetPath { .. } => "set_path",
+            Ipc::SetUrl { .. } => "set_url",
+            Ipc::RefreshCache { .. } => "refresh_cache",
+            Ipc::SetProperty { .. } => "set_property",
+            Ipc::ReloadConfig => "reload_config",
+            Ipc::SetNamed { .. } => "set_named",
+            Ipc::StartPlaylist { .. } => "start_playlist",
+            Ipc::StopPlaylist { .. } => "stop_playlist",
+            Ipc::Next { .. } => "next",
+            Ipc::Prev { .. } => "prev",
+            Ipc::GetSchedule => "get_schedule",
+            Ipc::SetFrameRate { .. } => "set_frame_rate",
+            Ipc::GetState => "get_state",
+            Ipc::Subscribe => "subscribe",
+        }
+    }
+
+    pub fn span(&self) -> &[String] {
+        match self {
+            Ipc::SetPath { span, .. } | Ipc::SetUrl { span, .. } => span,
+            _ => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Playlist {
+    // Seconds each entry is shown for
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    #[serde(default)]
+    pub shuffle: bool,
+    #[serde(default)]
+    pub cursor: CursorMode,
+    pub entries: Vec<PlaylistEntry>,
+}
+
+fn default_interval() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CursorMode {
+    // Every monitor steps through the playlist on its own
+    #[default]
+    PerMonitor,
+    // Every monitor shows the same entry
+    Shared,
+}
+
+// A bare string is a wallpaper name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PlaylistEntry {
+    Name(String),
+    Item {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        wallpaper: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        url: Option<String>,
+        // Shuffled playlists show it this many times as often, ordered ones this many times as long
+        #[serde(default = "default_weight")]
+        weight: u32,
+        #[serde(default, skip_serializing_if = "Properties::is_empty")]
+        properties: Properties,
+    },
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl PlaylistEntry {
+    pub fn weight(&self) -> u32 {
+        match self {
+            PlaylistEntry::Name(_) => 1,
+            PlaylistEntry::Item { weight, .. } => *weight,
+        }
+    }
+
+    pub fn to_ipc(&self, monitor: Option<String>) -> Ipc {
+        match self.clone() {
+            PlaylistEntry::Name(name) => Ipc::SetNamed {
+                monitor,
+                name,
+                properties: Properties::new(),
+            },
+            PlaylistEntry::Item {
+                wallpaper,
+                path,
+                url,
+                properties,
+                ..
+            } => match (wallpaper, path, url) {
+                (Some(name), _, _) => Ipc::SetNamed {
+                    monitor,
+                    name,
+                    properties,
+                },
+                (None, Some(path), _) => Ipc::SetPath {
+                    monitor,
+                    path,
+                    properties,
+                    span: Vec::new(),
+                },
+                (None, None, url) => Ipc::SetUrl {
+                    monitor,
+                    url: url.unwrap_or_default(),
+                    properties,
+                    span: Vec::new(),
+                },
+            },
+        }
+    }
+}
+
+// Sent back over the socket, one per received message
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IpcReply {
+    Ok,
+    Error {
+        message: String,
+    },
+    Schedule {
+        transitions: Vec<Transition>,
+    },
+    State {
+        power: PowerMode,
+        monitors: Vec<MonitorState>,
+    },
+    Event {
+        event: Event,
+    },
+}
+
+// What subscribers are told about, see Ipc::Subscribe
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    // None for reload_in_ms when the fallback is shown instead
+    RenderProcessTerminated {
+        connector: String,
+        reason: Termination,
+        exit_code: i32,
+        crashes: u32,
+        reload_in_ms: Option<u64>,
+    },
+    FallbackShown {
+        connector: String,
+        reason: String,
+    },
+    // Failed or timed out, the fallback is shown next if there is one.
+    // url is None when the page's server never answered
+    LoadFailed {
+        connector: String,
+        url: Option<String>,
+        error: String,
+    },
+}
+
+// QtWebEngine's terminationStatus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Termination {
+    Normal,
+    Abnormal,
+    Crashed,
+    Killed,
+}
+
+impl Termination {
+    pub fn from_status(status: i32) -> Self {
+        match status {
+            0 => Termination::Normal,
+            2 => Termination::Crashed,
+            3 => Termination::Killed,
+            _ => Termination::Abnormal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transition {
+    // Unix seconds
+    pub at: i64,
+    // The daemon's local time, as YYYY-MM-DD HH:MM
+    pub local: String,
+    // Every monitor when None
+    pub monitor: Option<String>,
+    // Whether the window starts or ends
+    pub starts: bool,
+    pub target: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonitorState {
+    pub connector: String,
+    pub refresh_rate: f64,
+    // What the page can render at, after every cap and the refresh rate. 0 while hidden to save
+    // power, None when nothing caps it and the refresh rate is unknown
+    pub frame_rate: Option<u32>,
+}
+
+// As reported by the QML side, in the compositor's logical coordinates
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Monitor {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub scale: f64,
+    pub refresh_rate: f64,
+}
+
+// The [defaults] table of wallpapers.toml, applied by the QML side
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Defaults {
+    // Shown until a monitor gets a wallpaper, any CSS colour
+    pub placeholder: String,
+    pub layer: Layer,
+    pub audio: AudioPolicy,
+    // Caps how often pages are rendered, 0 is uncapped
+    pub frame_rate: u32,
+}
+
+impl Default for Defaults {
+    fn default() -> Self {
+        Self {
+            placeholder: "#222222".to_string(),
+            layer: Layer::default(),
+            audio: AudioPolicy::default(),
+            frame_rate: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layer {
+    #[default]
+    Background,
+    Bottom,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioPolicy {
+    Muted,
+    // Only audible while the wallpaper's window is active
+    #[default]
+    Focused,
+    Always,
+}
+
+// What the UI does to save power, as the [[power]] rules decide
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PowerMode {
+    #[default]
+    Normal,
+    // Pages are frozen behind a still of their last frame, and told through the SDK first
+    Pause,
+    FrameRate {
+        fps: u32,
+    },
+    // Every page is hidden, which stops Chromium rendering it, and a still shown instead.
+    // The placeholder colour is used when neither is given
+    Fallback {
+        image: Option<String>,
+        color: Option<String>,
+    },
+}
+
+// Where a window's part of a spanned page is, in the page's coordinates.
+// The page is laid out at width x height, and the window shows it from x, y
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Viewport {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub connectors: Vec<String>,
+    pub viewport: Viewport,
+}
+
+// --- Shared state from UI -> tokio
+#[derive(Clone, Default)]
+pub struct SyncData {
+    pub connectors: Vec<String>,
+    pub monitors: Vec<Monitor>,
 }
 
 /*
 * BASES
 */
 
-#[derive(Debug, Clone)]
+// Answered once its pages have loaded, with the errors of those that didn't.
+// None, or a request that's held, is answered when it's sent
+#[derive(Debug)]
 pub struct RequestServer {
     pub path: String,
     pub connector: Option<String>,
+    pub properties: Properties,
+    pub span: Vec<String>,
+    // From a client or a page, which stops the playlists of its monitors
+    pub by_hand: bool,
+    pub reply: Option<oneshot::Sender<Result<(), String>>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct AcquireServer {
     pub path: String,
     pub connector: String,
+    pub properties: Properties,
+    pub span: Option<Span>,
 }
 
+// The monitor shows something other than a served path now
 #[derive(Debug)]
 pub struct ReleaseServer {
-    pub path: String,
+    pub connector: String,
+}
+
+// A monitor that came back, and what it showed before it went away
+#[derive(Debug)]
+pub struct RestoreMonitor {
+    pub connector: String,
+    pub msg: Option<Ipc>,
+}
+
+// A page's renderer process died, its window is blank until it is reloaded
+#[derive(Debug)]
+pub struct RenderProcessTerminated {
+    pub connector: String,
+    pub reason: Termination,
+    pub exit_code: i32,
+}
+
+// A page finished loading, url as the UI was given it. error is None when it loaded
+#[derive(Debug)]
+pub struct LoadFinished {
+    pub connector: String,
+    pub url: String,
+    pub error: Option<String>,
 }
 
+// The web manager couldn't serve a path it was asked for, so the UI never gets a page
+#[derive(Debug)]
+pub struct AcquireFailed {
+    pub connector: String,
+    pub error: String,
+}
+
+// Answered by the web manager, which knows whether caching is on
+#[derive(Debug)]
+pub struct RefreshCache {
+    pub url: Option<String>,
+    pub reply: oneshot::Sender<Result<(), String>>,
+}
+
+#[derive(Debug)]
+pub struct RequestProperty {
+    pub connector: Option<String>,
+    pub key: String,
+    pub value: serde_json::Value,
+}
+
+#[derive(Debug, Clone)]
+pub struct SetProperty {
+    pub connector: String,
+    pub key: String,
+    pub value: serde_json::Value,
+}
+
+// Answered once the file has been read, with the error if it didn't parse
+#[derive(Debug)]
+pub struct ReloadConfig {
+    pub reply: Option<oneshot::Sender<Result<(), String>>>,
+}
+
+// Answered once the name is resolved and the wallpaper it names accepted
+#[derive(Debug)]
+pub struct RequestNamed {
+    pub name: String,
+    pub connector: Option<String>,
+    pub properties: Properties,
+    // Passed on to the wallpaper it names
+    pub by_hand: bool,
+    pub reply: oneshot::Sender<Result<(), String>>,
+}
+
+#[derive(Debug)]
+pub enum PlaylistRequest {
+    Start {
+        name: Option<String>,
+        playlist: Option<Playlist>,
+    },
+    Stop,
+    Next,
+    Prev,
+}
+
+// Answered by the playlist task, with an error if nothing was running there
+#[derive(Debug)]
+pub struct RequestPlaylist {
+    pub connector: Option<String>,
+    pub request: PlaylistRequest,
+    pub reply: oneshot::Sender<Result<(), String>>,
+}
+
+#[derive(Debug)]
+pub enum PlaylistAction {
+    Start { name: String, playlist: Playlist },
+    Stop,
+    Next,
+    Prev,
+}
+
+#[derive(Debug)]
+pub struct PlaylistCmd {
+    pub action: PlaylistAction,
+    // Empty means every monitor a playlist runs on
+    pub connectors: Vec<String>,
+    pub reply: oneshot::Sender<Result<(), String>>,
+}
+
+#[derive(Debug)]
+pub struct RequestSchedule {
+    pub reply: oneshot::Sender<Vec<Transition>>,
+}
+
+#[derive(Debug)]
+pub struct RequestFrameRate {
+    pub connector: Option<String>,
+    pub fps: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct RequestState {
+    pub reply: oneshot::Sender<(PowerMode, Vec<MonitorState>)>,
+}
+
+#[derive(Debug)]
+pub struct SetFrameRate {
+    pub connector: String,
+    pub fps: Option<u32>,
+}
+
+// Answered like RequestServer
 #[derive(Debug)]
 pub struct RequestWebview {
     pub url: String,
-    pub connector: Option<String>, 
+    pub connector: Option<String>,
+    pub properties: Properties,
+    pub span: Vec<String>,
+    // From a client or a page, which stops the playlists of its monitors
+    pub by_hand: bool,
+    pub reply: Option<oneshot::Sender<Result<(), String>>>,
 }
 
 #[derive(Debug)]
@@ -39,6 +550,10 @@ pub struct SetWebview {
     pub url: String,
     pub path: Option<String>,
     pub connector: String,
+    pub properties: Properties,
+    pub span: Option<Span>,
+    // False for [fallback], so what it stands in for is what's restored
+    pub persist: bool,
 }
 
 /*
@@ -48,31 +563,53 @@ pub struct SetWebview {
 pub enum TokioEvent {
     IpcEvent(IpcEvent),
     WebEvent(WebEvent),
+    ConfigChanged,
 }
 
 pub enum IpcEvent {
     RequestServer(RequestServer),
-    RequestWebview(RequestWebview)
+    RequestWebview(RequestWebview),
+    RefreshCache(RefreshCache),
+    RequestProperty(RequestProperty),
+    ReloadConfig(ReloadConfig),
+    RequestNamed(RequestNamed),
+    RequestPlaylist(RequestPlaylist),
+    RequestSchedule(RequestSchedule),
+    RequestFrameRate(RequestFrameRate),
+    RequestState(RequestState),
 }
 
 pub enum WebEvent {
     SetWebview(SetWebview),
+    AcquireFailed(AcquireFailed),
 }
 
 pub enum UiEvent {
-    ReleaseServer(ReleaseServer),
+    RestoreMonitor(RestoreMonitor),
+    RenderProcessTerminated(RenderProcessTerminated),
+    LoadFinished(LoadFinished),
 }
 
-
 /*
 * CMDS
 */
 
 pub enum UiCmd {
     SetWebview(SetWebview),
+    SetProperty(SetProperty),
+    ApplyDefaults(Defaults),
+    // Answered with UiEvent::RestoreMonitor
+    RecallMonitor(String),
+    SetPower(PowerMode),
+    SetFrameRate(SetFrameRate),
+    // Loads the monitor's page again
+    Reload(String),
 }
 
 pub enum WebCmd {
     AcquireServer(AcquireServer),
-    ReleaseServer(ReleaseServer)
+    ReleaseServer(ReleaseServer),
+    RefreshCache(RefreshCache),
+    SetProperty(SetProperty),
+    ForgetConnector(String),
 }
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, mpsc as std_mpsc};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use tokio::sync::{mpsc, watch};
+
+use crate::daemon::{self, Options};
+use crate::event::{
+    Defaults, Ipc, IpcReply, LoadFinished, Monitor, PowerMode, RenderProcessTerminated, SyncData,
+    Termination, UiCmd, UiEvent, Viewport,
+};
+use crate::state::State;
+use crate::ui::{self, Ui};
+use crate::webserver::WebOptions;
+use crate::{Paths, send_msg};
+
+// How long send waits for the daemon to bind its socket
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// What the daemon asked of the UI, in order
+#[derive(Debug, Clone, PartialEq)]
+pub enum UiCall {
+    SetWallpaper {
+        connector: String,
+        url: String,
+        viewport: Option<Viewport>,
+    },
+    SetProperty {
+        connector: String,
+        key: String,
+        value: serde_json::Value,
+    },
+    ApplyDefaults(Defaults),
+    SetPower(PowerMode),
+    SetFrameRate {
+        connector: String,
+        fps: Option<u32>,
+    },
+    Reload(String),
+}
+
+// How set_wallpaper's page goes, by URL. Pages left out load, None never finishes
+type Outcomes = Arc<Mutex<HashMap<String, Option<String>>>>;
+
+struct Recorder {
+    calls: std_mpsc::Sender<UiCall>,
+    ui_event_tx: mpsc::UnboundedSender<UiEvent>,
+    outcomes: Outcomes,
+}
+
+impl Recorder {
+    fn send(&self, call: UiCall) {
+        let _ = self.calls.send(call);
+    }
+}
+
+impl Ui for Recorder {
+    fn set_wallpaper(&mut self, connector: &str, url: &str, viewport: Option<Viewport>) {
+        self.send(UiCall::SetWallpaper {
+            connector: connector.to_string(),
+            url: url.to_string(),
+            viewport,
+        });
+
+        let error = match self.outcomes.lock().unwrap().get(url) {
+            Some(None) => return,
+            Some(Some(error)) => Some(error.clone()),
+            None => None,
+        };
+        let _ = self.ui_event_tx.send(UiEvent::LoadFinished(LoadFinished {
+            connector: connector.to_string(),
+            url: url.to_string(),
+            error,
+        }));
+    }
+
+    fn set_property(&mut self, connector: &str, key: &str, value: &serde_json::Value) {
+        self.send(UiCall::SetProperty {
+            connector: connector.to_string(),
+            key: key.to_string(),
+            value: value.clone(),
+        });
+    }
+
+    fn apply_defaults(&mut self, defaults: &Defaults) {
+        self.send(UiCall::ApplyDefaults(defaults.clone()));
+    }
+
+    fn set_power(&mut self, mode: &PowerMode) {
+        self.send(UiCall::SetPower(mode.clone()));
+    }
+
+    fn set_frame_rate(&mut self, connector: &str, fps: Option<u32>) {
+        self.send(UiCall::SetFrameRate {
+            connector: connector.to_string(),
+            fps,
+        });
+    }
+
+    fn reload(&mut self, connector: &str) {
+        self.send(UiCall::Reload(connector.to_string()));
+    }
+}
+
+// The whole daemon without Qt: monitors are reported by hand, and UI calls are recorded
+pub struct Headless {
+    sync_tx: watch::Sender<Arc<SyncData>>,
+    ui_event_tx: mpsc::UnboundedSender<UiEvent>,
+    calls: std_mpsc::Receiver<UiCall>,
+    outcomes: Outcomes,
+    socket: PathBuf,
+}
+
+impl Headless {
+    // Listens on maypaper.sock in the config directory
+    pub fn start(paths: Paths, web: WebOptions) -> Result<Self> {
+        paths.ensure_dirs()?;
+        let state = State::load(paths.state.clone());
+        let restore = state.assignments().clone();
+        let socket = paths.base.join("maypaper.sock");
+
+        let (ui_tx, ui_rx) = mpsc::unbounded_channel::<UiCmd>();
+        let (ui_event_tx, ui_event_rx) = mpsc::unbounded_channel::<UiEvent>();
+        let (sync_tx, sync_rx) = watch::channel(Arc::new(SyncData::default()));
+        let (calls_tx, calls) = std_mpsc::channel();
+
+        let outcomes = Outcomes::default();
+        let recorder = Recorder {
+            calls: calls_tx,
+            ui_event_tx: ui_event_tx.clone(),
+            outcomes: outcomes.clone(),
+        };
+        ui::spawn_adapter(recorder, ui_rx, ui_event_tx.clone(), state);
+        daemon::start_tokio(
+            ui_tx,
+            ui_event_rx,
+            sync_rx,
+            Options {
+                web,
+                metrics_port: None,
+                paths,
+                socket: socket.clone(),
+            },
+            restore,
+        );
+
+        Ok(Self {
+            sync_tx,
+            ui_event_tx,
+            calls,
+            outcomes,
+            socket,
+        })
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    // Like QML's setMonitorNames, an empty list is every monitor unplugged
+    pub fn set_monitors(&self, monitors: Vec<Monitor>) {
+        let connectors = monitors.iter().map(|m| m.name.clone()).collect();
+        let _ = self.sync_tx.send(Arc::new(SyncData {
+            connectors,
+            monitors,
+        }));
+    }
+
+    // 1920x1080 monitors side by side, in the order given
+    pub fn set_connectors(&self, connectors: &[&str]) {
+        let monitors = connectors
+            .iter()
+            .zip(0..)
+            .map(|(name, i)| Monitor {
+                name: name.to_string(),
+                x: i * 1920,
+                y: 0,
+                width: 1920,
+                height: 1080,
+                scale: 1.0,
+                refresh_rate: 60.0,
+            })
+            .collect();
+        self.set_monitors(monitors);
+    }
+
+    // As QML reports a page's renderer dying
+    pub fn terminate_render_process(&self, connector: &str, reason: Termination) {
+        let _ = self
+            .ui_event_tx
+            .send(UiEvent::RenderProcessTerminated(RenderProcessTerminated {
+                connector: connector.to_string(),
+                reason,
+                exit_code: 1,
+            }));
+    }
+
+    // Pages at url fail to load with error from now on, as QML reports a bad one
+    pub fn fail_load(&self, url: &str, error: &str) {
+        self.outcomes
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), Some(error.to_string()));
+    }
+
+    // Pages at url never finish loading
+    pub fn stall_load(&self, url: &str) {
+        self.outcomes.lock().unwrap().insert(url.to_string(), None);
+    }
+
+    // The same as mypctl, retrying while the socket isn't bound yet
+    pub fn send(&self, msg: &Ipc) -> io::Result<IpcReply> {
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        loop {
+            match send_msg(&self.socket, msg) {
+                Err(e)
+                    if Instant::now() < deadline
+                        && matches!(
+                            e.kind(),
+                            io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+                        ) =>
+                {
+                    std::thread::sleep(Duration::from_millis(20));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub fn next_call(&self, timeout: Duration) -> Option<UiCall> {
+        self.calls.recv_timeout(timeout).ok()
+    }
+
+    // Skips defaults and properties
+    pub fn next_wallpaper(&self, timeout: Duration) -> Option<(String, String)> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.next_call(left)? {
+                UiCall::SetWallpaper { connector, url, .. } => return Some((connector, url)),
+                _ => continue,
+            }
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{Request, State},
+    http::{Method, StatusCode, Uri, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http_body::Frame;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use serde_json::json;
+use tokio::sync::watch;
+use tracing::{error, warn};
+
+use crate::event::{Properties, SyncData};
+
+// Added to every local wallpaper URL, so the page can be told which monitor it is on
+pub const CONNECTOR_PARAM: &str = "maypaper_connector";
+
+// Unreserved characters stay readable, connectors are things like DP-1
+const QUERY_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+// Pages are buffered to inject into them, larger ones are passed on as they are
+const MAX_HTML_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub path: String,
+    pub properties: Properties,
+}
+
+// Set by the shared server on requests it forwards, the prefix the site is mounted under
+#[derive(Debug, Clone)]
+pub struct MountBase(pub String);
+
+pub type Assignments = Arc<RwLock<HashMap<String, Assignment>>>;
+
+// Everything needed to build the bootstrap script for a page
+#[derive(Clone)]
+pub struct PageContext {
+    pub sync_rx: watch::Receiver<Arc<SyncData>>,
+    // Whatever each connector was last assigned, keyed by connector
+    pub assignments: Assignments,
+    // Lets the page call /api/control, unique to each server. None where other
+    // scripts could read it: remote pages, and mounts sharing one origin
+    pub token: Option<String>,
+}
+
+pub fn with_connector(url: &str, connector: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let connector = utf8_percent_encode(connector, QUERY_VALUE);
+    format!("{url}{separator}{CONNECTOR_PARAM}={connector}")
+}
+
+// Pulls our parameter back out of the request, so neither the site nor an origin sees it
+fn take_connector(uri: &Uri) -> (Option<String>, Option<Uri>) {
+    let Some(query) = uri.query() else {
+        return (None, None);
+    };
+
+    let mut connector = None;
+    let rest: Vec<&str> = query
+        .split('&')
+        .filter(|pair| {
+            match pair
+                .strip_prefix(CONNECTOR_PARAM)
+                .and_then(|v| v.strip_prefix('='))
+            {
+                Some(value) => {
+                    connector = Some(percent_decode_str(value).decode_utf8_lossy().into_owned());
+                    false
+                }
+                None => true,
+            }
+        })
+        .collect();
+
+    if connector.is_none() {
+        return (None, None);
+    }
+
+    let path_and_query = if rest.is_empty() {
+        uri.path().to_string()
+    } else {
+        format!("{}?{}", uri.path(), rest.join("&"))
+    };
+
+    (connector, path_and_query.parse().ok())
+}
+
+pub async fn inject_context(
+    State(ctx): State<PageContext>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let (connector, stripped) = take_connector(req.uri());
+    if let Some(uri) = stripped {
+        *req.uri_mut() = uri;
+    }
+    let base = req
+        .extensions()
+        .get::<MountBase>()
+        .map_or_else(|| "/".to_string(), |b| b.0.clone());
+
+    let is_get = req.method() == Method::GET;
+    let res = next.run(req).await;
+
+    let is_html = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/html"));
+
+    if !is_get || res.status() != StatusCode::OK || !is_html {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let bytes = match buffer(body, MAX_HTML_BYTES).await {
+        Ok(Buffered::Whole(bytes)) => bytes,
+        Ok(Buffered::TooLarge(body)) => {
+            warn!(target: "web", limit = MAX_HTML_BYTES, "Page too large to inject into");
+            return Response::from_parts(parts, body);
+        }
+        Err(e) => {
+            error!(target: "web", error = %e, "Failed to read page for injection");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let Ok(html) = std::str::from_utf8(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    let html = insert_script(html, &bootstrap_script(&ctx, connector.as_deref(), &base));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(html))
+}
+
+enum Buffered {
+    Whole(Bytes),
+    // Over the limit, what was read is put back in front of the rest
+    TooLarge(Body),
+}
+
+async fn buffer(mut body: Body, limit: usize) -> Result<Buffered, axum::Error> {
+    let mut read = Vec::new();
+    while let Some(frame) = std::future::poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await {
+        // Trailers are dropped, a page has no use for them
+        let Ok(data) = frame?.into_data() else {
+            continue;
+        };
+        read.extend_from_slice(&data);
+        if read.len() > limit {
+            let read = Some(Bytes::from(read));
+            return Ok(Buffered::TooLarge(Body::new(Resumed { read, rest: body })));
+        }
+    }
+    Ok(Buffered::Whole(Bytes::from(read)))
+}
+
+struct Resumed {
+    read: Option<Bytes>,
+    rest: Body,
+}
+
+impl HttpBody for Resumed {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        if let Some(read) = self.read.take() {
+            return Poll::Ready(Some(Ok(Frame::data(read))));
+        }
+        Pin::new(&mut self.rest).poll_frame(cx)
+    }
+}
+
+fn bootstrap_script(ctx: &PageContext, connector: Option<&str>, base: &str) -> String {
+    let sync = ctx.sync_rx.borrow().clone();
+    let monitor = connector.and_then(|c| sync.monitors.iter().find(|m| m.name == c));
+    let properties = connector
+        .and_then(|c| ctx.assignments.read().unwrap().get(c).cloned())
+        .map(|a| a.properties)
+        .unwrap_or_default();
+
+    let context = json!({
+        "connector": connector,
+        "monitor": monitor,
+        "monitors": sync.monitors,
+        "version": env!("CARGO_PKG_VERSION"),
+        "properties": properties,
+        "controlToken": ctx.token,
+        "base": base,
+    });
+
+    // A property containing </script> must not end the tag early
+    let context = context.to_string().replace('<', "\\u003c");
+    format!("<script>Object.assign(globalThis.maypaper ??= {{}}, {context});</script>")
+}
+
+// As early as possible, so it runs before any of the page's own scripts
+fn insert_script(html: &str, script: &str) -> String {
+    let lower = html.to_ascii_lowercase();
+
+    let at = ["<head", "<html"]
+        .iter()
+        .find_map(|tag| {
+            // Skip lookalikes such as <header>
+            let start = lower.match_indices(tag).map(|(i, _)| i).find(|i| {
+                lower[i + tag.len()..].starts_with(|c: char| c == '>' || c.is_ascii_whitespace())
+            })?;
+            lower[start..].find('>').map(|end| start + end + 1)
+        })
+        .unwrap_or(0);
+
+    let mut out = String::with_capacity(html.len() + script.len());
+    out.push_str(&html[..at]);
+    out.push_str(script);
+    out.push_str(&html[at..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, body::to_bytes, middleware, response::Html, routing::get};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn context() -> PageContext {
+        let (_, sync_rx) = watch::channel(Arc::default());
+        PageContext {
+            sync_rx,
+            assignments: Assignments::default(),
+            token: None,
+        }
+    }
+
+    fn take(uri: &str) -> (Option<String>, Option<String>) {
+        let (connector, rest) = take_connector(&uri.parse().unwrap());
+        (connector, rest.map(|uri| uri.to_string()))
+    }
+
+    #[test]
+    fn connector_is_taken_from_anywhere_in_the_query() {
+        let connector = Some("DP-1".to_string());
+        assert_eq!(
+            take("/a?maypaper_connector=DP-1&x=1"),
+            (connector.clone(), Some("/a?x=1".to_string()))
+        );
+        assert_eq!(
+            take("/a?x=1&maypaper_connector=DP-1&y=2"),
+            (connector.clone(), Some("/a?x=1&y=2".to_string()))
+        );
+        assert_eq!(
+            take("/a?x=1&maypaper_connector=DP-1"),
+            (connector.clone(), Some("/a?x=1".to_string()))
+        );
+        assert_eq!(
+            take("/a?maypaper_connector=DP-1"),
+            (connector, Some("/a".to_string()))
+        );
+        assert_eq!(take("/a?x=1&maypaper_connectors=DP-1"), (None, None));
+        assert_eq!(take("/a"), (None, None));
+    }
+
+    #[test]
+    fn connector_is_percent_decoded() {
+        let url = with_connector("/a?x=1", "HDMI A/1");
+        assert_eq!(url, "/a?x=1&maypaper_connector=HDMI%20A%2F1");
+        assert_eq!(take(&url).0.as_deref(), Some("HDMI A/1"));
+    }
+
+    #[test]
+    fn script_goes_in_the_head() {
+        let script = "<script></script>";
+        assert_eq!(
+            insert_script("<html><header></header><HEAD lang=en><title>", script),
+            "<html><header></header><HEAD lang=en><script></script><title>"
+        );
+        assert_eq!(
+            insert_script("<!doctype html><html>\n<body>", script),
+            "<!doctype html><html><script></script>\n<body>"
+        );
+        assert_eq!(
+            insert_script("<headline><p>hi", script),
+            "<script></script><headline><p>hi"
+        );
+    }
+
+    #[test]
+    fn properties_cannot_end_the_script() {
+        let ctx = context();
+        let assignment = Assignment {
+            path: "/site".to_string(),
+            properties: [("title".to_string(), "</script><script>alert(1)".into())].into(),
+        };
+        ctx.assignments
+            .write()
+            .unwrap()
+            .insert("DP-1".to_string(), assignment);
+
+        let script = bootstrap_script(&ctx, Some("DP-1"), "/");
+        assert_eq!(script.matches('<').count(), 2, "{script}");
+        assert!(script.contains("\\u003c/script>\\u003cscript>alert(1)"));
+    }
+
+    #[test]
+    fn oversized_page_is_passed_on_as_it_is() {
+        let page = format!("<html><head>{}", "x".repeat(MAX_HTML_BYTES));
+        let router = Router::new()
+            .route("/", get(move || async move { Html(page) }))
+            .layer(middleware::from_fn_with_state(context(), inject_context));
+
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        let body = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let response = router.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                to_bytes(response.into_body(), usize::MAX).await.unwrap()
+            });
+        assert_eq!(body.len(), "<html><head>".len() + MAX_HTML_BYTES);
+        assert!(!body.windows(7).any(|w| w == b"<script"));
+    }
+}
@@ -1,16 +1,26 @@
-use maypaper::get_default_socket_path;
-use tokio::sync::mpsc;
+use std::path::PathBuf;
+
+use tokio::sync::{broadcast, mpsc, oneshot};
 
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
-    net::UnixListener,
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, unix::OwnedWriteHalf},
 };
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
-use crate::event::{Ipc, IpcEvent, RequestServer, RequestWebview, TokioEvent};
+use crate::event::{
+    Event, Ipc, IpcEvent, IpcReply, PlaylistRequest, RefreshCache, ReloadConfig, RequestFrameRate,
+    RequestNamed, RequestPlaylist, RequestProperty, RequestSchedule, RequestServer, RequestState,
+    RequestWebview, TokioEvent,
+};
+use crate::metrics::METRICS;
+use crate::source;
 
-pub async fn ipc_server(tx: mpsc::UnboundedSender<TokioEvent>) {
-    let socket_path = get_default_socket_path();
+pub async fn ipc_server(
+    tx: mpsc::UnboundedSender<TokioEvent>,
+    socket_path: PathBuf,
+    events: broadcast::Sender<Event>,
+) {
     let _ = std::fs::remove_file(&socket_path);
 
     let listener = match UnixListener::bind(&socket_path) {
@@ -36,8 +46,10 @@ pub async fn ipc_server(tx: mpsc::UnboundedSender<TokioEvent>) {
         };
 
         let tx = tx.clone();
+        let events = events.clone();
         tokio::spawn(async move {
-            let mut lines = BufReader::new(stream).lines();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
 
             while let Ok(Some(line)) = lines.next_line().await {
                 let line = line.trim();
@@ -45,40 +57,324 @@ pub async fn ipc_server(tx: mpsc::UnboundedSender<TokioEvent>) {
                     continue;
                 }
 
-                match serde_json::from_str::<Ipc>(line) {
-                    Ok(msg) => match &msg {
-                        Ipc::SetPath { monitor, path } => {
-                            info!(target: "ipc", "Received SetPath");
-
-                            let request_server = RequestServer {
-                                path: path.clone(),
-                                connector: monitor.clone(),
-                            };
-                            debug!(target: "ipc", request_server = ?request_server, "Sending");
-                            let _ = tx.send(TokioEvent::IpcEvent(IpcEvent::RequestServer(
-                                request_server,
-                            )));
-                            debug!(target: "ipc", "Sent");
+                let reply = match serde_json::from_str::<Ipc>(line) {
+                    Ok(Ipc::Subscribe) => {
+                        METRICS.ipc_message(Ipc::Subscribe.kind());
+                        info!(target: "ipc", "Received Subscribe");
+                        stream_events(&mut writer, events.subscribe()).await;
+                        break;
+                    }
+                    Ok(msg) => handle_msg_by_hand(msg, &tx).await,
+                    Err(e) => {
+                        METRICS.ipc_message("invalid");
+                        error!(target: "ipc", line = %line, error = %e, "bad JSON");
+                        IpcReply::Error {
+                            message: format!("bad JSON: {e}"),
                         }
+                    }
+                };
 
-                        Ipc::SetUrl { monitor, url } => {
-                            info!(target: "ipc", "Received SetUrl");
+                if let Err(e) = write_reply(&mut writer, &reply).await {
+                    debug!(target: "ipc", error = %e, "Client went away before the reply");
+                    break;
+                }
+            }
+        });
+    }
+}
 
-                            let request_webview = RequestWebview {
-                                url: url.clone(),
-                                connector: monitor.clone(),
-                            };
+async fn write_reply(writer: &mut OwnedWriteHalf, reply: &IpcReply) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(reply)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
 
-                            debug!(target: "ipc", request_webview = ?request_webview, "Sending");
-                            let _ = tx.send(TokioEvent::IpcEvent(IpcEvent::RequestWebview(
-                                request_webview,
-                            )));
-                            debug!(target: "ipc", "Sent");
-                        }
-                    },
-                    Err(e) => error!(target: "ipc", line = %line, error = %e, "bad JSON"),
+// Answers Ok, then writes every event as it happens until the client goes away
+async fn stream_events(writer: &mut OwnedWriteHalf, mut events: broadcast::Receiver<Event>) {
+    if write_reply(writer, &IpcReply::Ok).await.is_err() {
+        return;
+    }
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!(target: "ipc", missed, "Subscriber fell behind, events were dropped");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        if write_reply(writer, &IpcReply::Event { event })
+            .await
+            .is_err()
+        {
+            debug!(target: "ipc", "Subscriber went away");
+            return;
+        }
+    }
+}
+
+pub async fn handle_msg(msg: Ipc, tx: &mpsc::UnboundedSender<TokioEvent>) -> IpcReply {
+    dispatch(msg, tx, false).await
+}
+
+// For clients and pages. A wallpaper they set stops the playlists of its monitors once it's
+// accepted, the schedule and playlists themselves go through handle_msg
+pub async fn handle_msg_by_hand(msg: Ipc, tx: &mpsc::UnboundedSender<TokioEvent>) -> IpcReply {
+    dispatch(msg, tx, true).await
+}
+
+async fn dispatch(msg: Ipc, tx: &mpsc::UnboundedSender<TokioEvent>, by_hand: bool) -> IpcReply {
+    METRICS.ipc_message(msg.kind());
+
+    match msg {
+        Ipc::SetPath {
+            monitor,
+            path,
+            properties,
+            span,
+        } => {
+            info!(target: "ipc", "Received SetPath");
+
+            // Lists an archive's files, which the runtime shouldn't wait on
+            let resolved = tokio::task::spawn_blocking(move || source::resolve(&path))
+                .await
+                .unwrap_or_else(|e| Err(anyhow::anyhow!("Resolving panicked: {e}")));
+            let path = match resolved {
+                Ok(p) => p,
+                Err(e) => {
+                    error!(target: "ipc", error = %format!("{e:#}"), "Rejected SetPath");
+                    return IpcReply::Error {
+                        message: format!("{e:#}"),
+                    };
                 }
+            };
+
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let request_server = RequestServer {
+                path,
+                connector: monitor,
+                properties,
+                span,
+                by_hand,
+                reply: Some(reply_tx),
+            };
+            debug!(target: "ipc", request_server = ?request_server, "Sending");
+            let _ = tx.send(TokioEvent::IpcEvent(IpcEvent::RequestServer(
+                request_server,
+            )));
+            debug!(target: "ipc", "Sent");
+
+            return await_reply(reply_rx).await;
+        }
+
+        Ipc::SetUrl {
+            monitor,
+            url,
+            properties,
+            span,
+        } => {
+            info!(target: "ipc", "Received SetUrl");
+
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let request_webview = RequestWebview {
+                url,
+                connector: monitor,
+                properties,
+                span,
+                by_hand,
+                reply: Some(reply_tx),
+            };
+
+            debug!(target: "ipc", request_webview = ?request_webview, "Sending");
+            let _ = tx.send(TokioEvent::IpcEvent(IpcEvent::RequestWebview(
+                request_webview,
+            )));
+            debug!(target: "ipc", "Sent");
+
+            return await_reply(reply_rx).await;
+        }
+
+        Ipc::RefreshCache { url } => {
+            info!(target: "ipc", "Received RefreshCache");
+
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let refresh_cache = RefreshCache {
+                url,
+                reply: reply_tx,
+            };
+            debug!(target: "ipc", refresh_cache = ?refresh_cache, "Sending");
+            let _ = tx.send(TokioEvent::IpcEvent(IpcEvent::RefreshCache(refresh_cache)));
+            debug!(target: "ipc", "Sent");
+
+            return await_reply(reply_rx).await;
+        }
+
+        Ipc::SetProperty {
+            monitor,
+            key,
+            value,
+        } => {
+            info!(target: "ipc", "Received SetProperty");
+
+            let request_property = RequestProperty {
+                connector: monitor,
+                key,
+                value,
+            };
+            debug!(target: "ipc", request_property = ?request_property, "Sending");
+            let _ = tx.send(TokioEvent::IpcEvent(IpcEvent::RequestProperty(
+                request_property,
+            )));
+            debug!(target: "ipc", "Sent");
+        }
+
+        Ipc::ReloadConfig => {
+            info!(target: "ipc", "Received ReloadConfig");
+
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let reload_config = ReloadConfig {
+                reply: Some(reply_tx),
+            };
+            let _ = tx.send(TokioEvent::IpcEvent(IpcEvent::ReloadConfig(reload_config)));
+
+            return await_reply(reply_rx).await;
+        }
+
+        Ipc::SetNamed {
+            monitor,
+            name,
+            properties,
+        } => {
+            info!(target: "ipc", "Received SetNamed");
+
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let request_named = RequestNamed {
+                name,
+                connector: monitor,
+                properties,
+                by_hand,
+                reply: reply_tx,
+            };
+            debug!(target: "ipc", request_named = ?request_named, "Sending");
+            let _ = tx.send(TokioEvent::IpcEvent(IpcEvent::RequestNamed(request_named)));
+            debug!(target: "ipc", "Sent");
+
+            return await_reply(reply_rx).await;
+        }
+
+        Ipc::StartPlaylist {
+            monitor,
+            name,
+            playlist,
+        } => {
+            info!(target: "ipc", "Received StartPlaylist");
+            return request_playlist(monitor, PlaylistRequest::Start { name, playlist }, tx).await;
+        }
+
+        Ipc::StopPlaylist { monitor } => {
+            info!(target: "ipc", "Received StopPlaylist");
+            return request_playlist(monitor, PlaylistRequest::Stop, tx).await;
+        }
+
+        Ipc::Next { monitor } => {
+            info!(target: "ipc", "Received Next");
+            return request_playlist(monitor, PlaylistRequest::Next, tx).await;
+        }
+
+        Ipc::Prev { monitor } => {
+            info!(target: "ipc", "Received Prev");
+            return request_playlist(monitor, PlaylistRequest::Prev, tx).await;
+        }
+
+        Ipc::GetSchedule => {
+            info!(target: "ipc", "Received GetSchedule");
+
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let request_schedule = RequestSchedule { reply: reply_tx };
+            let _ = tx.send(TokioEvent::IpcEvent(IpcEvent::RequestSchedule(
+                request_schedule,
+            )));
+
+            return match reply_rx.await {
+                Ok(transitions) => IpcReply::Schedule { transitions },
+                Err(_) => IpcReply::Error {
+                    message: "maypaper is shutting down".to_string(),
+                },
+            };
+        }
+
+        Ipc::SetFrameRate { monitor, fps } => {
+            info!(target: "ipc", "Received SetFrameRate");
+
+            if fps == Some(0) {
+                return IpcReply::Error {
+                    message: "fps must be at least 1, leave it out to go back to the default"
+                        .to_string(),
+                };
             }
-        });
+            let request_frame_rate = RequestFrameRate {
+                connector: monitor,
+                fps,
+            };
+            let _ = tx.send(TokioEvent::IpcEvent(IpcEvent::RequestFrameRate(
+                request_frame_rate,
+            )));
+        }
+
+        // Only a connection of its own can carry the events, see ipc_server
+        Ipc::Subscribe => {
+            return IpcReply::Error {
+                message: "subscribe is only accepted over the socket".to_string(),
+            };
+        }
+
+        Ipc::GetState => {
+            info!(target: "ipc", "Received GetState");
+
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let request_state = RequestState { reply: reply_tx };
+            let _ = tx.send(TokioEvent::IpcEvent(IpcEvent::RequestState(request_state)));
+
+            return match reply_rx.await {
+                Ok((power, monitors)) => IpcReply::State { power, monitors },
+                Err(_) => IpcReply::Error {
+                    message: "maypaper is shutting down".to_string(),
+                },
+            };
+        }
+    }
+
+    IpcReply::Ok
+}
+
+async fn request_playlist(
+    monitor: Option<String>,
+    request: PlaylistRequest,
+    tx: &mpsc::UnboundedSender<TokioEvent>,
+) -> IpcReply {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let request_playlist = RequestPlaylist {
+        connector: monitor,
+        request,
+        reply: reply_tx,
+    };
+    debug!(target: "ipc", request_playlist = ?request_playlist, "Sending");
+    let _ = tx.send(TokioEvent::IpcEvent(IpcEvent::RequestPlaylist(
+        request_playlist,
+    )));
+    debug!(target: "ipc", "Sent");
+
+    await_reply(reply_rx).await
+}
+
+// For messages the tokio loop has to answer itself
+async fn await_reply(reply_rx: oneshot::Receiver<Result<(), String>>) -> IpcReply {
+    match reply_rx.await {
+        Ok(Ok(())) => IpcReply::Ok,
+        Ok(Err(message)) => IpcReply::Error { message },
+        Err(_) => IpcReply::Error {
+            message: "maypaper is shutting down".to_string(),
+        },
     }
 }
@@ -1,10 +1,37 @@
 use anyhow::Result;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
 use std::{fs, path::PathBuf};
 
 use tracing::error;
 
-pub mod event;
+use crate::event::{Event, Ipc, IpcReply};
 
+pub mod cache;
+mod config;
+mod control;
+pub mod daemon;
+pub mod event;
+pub mod headless;
+mod inject;
+mod ipc;
+pub mod library;
+mod loads;
+pub mod metrics;
+mod owners;
+mod pending;
+mod playlist;
+mod power;
+mod recovery;
+mod schedule;
+mod sdk;
+mod source;
+mod span;
+pub mod state;
+pub mod templates;
+pub mod ui;
+pub mod webserver;
 
 pub fn get_default_socket_path() -> PathBuf {
     if let Some(dir) = env::var_os("XDG_RUNTIME_DIR") {
@@ -15,23 +42,83 @@ pub fn get_default_socket_path() -> PathBuf {
     }
 }
 
+// Sends one message to a running daemon and waits for its reply
+pub fn send_msg(socket_path: &Path, msg: &Ipc) -> io::Result<IpcReply> {
+    let mut stream = UnixStream::connect(socket_path)?;
+
+    // Delimiter is newline
+    let line =
+        serde_json::to_string(msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    stream.write_all(line.as_bytes())?;
+    stream.write_all(b"\n")?;
+    stream.flush()?;
+
+    // The daemon answers every message with a single line
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    serde_json::from_str(&reply).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Every event of a running daemon as it happens, until the daemon goes away
+pub fn subscribe(socket_path: &Path) -> io::Result<impl Iterator<Item = io::Result<Event>>> {
+    let mut stream = UnixStream::connect(socket_path)?;
+
+    let line = serde_json::to_string(&Ipc::Subscribe)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    stream.write_all(line.as_bytes())?;
+    stream.write_all(b"\n")?;
+    stream.flush()?;
+
+    // The first line answers the subscribe, each one after it is an event
+    let mut lines = BufReader::new(stream).lines();
+    let first = lines.next().ok_or(io::ErrorKind::UnexpectedEof)??;
+    let reply =
+        serde_json::from_str(&first).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if let IpcReply::Error { message } = reply {
+        return Err(io::Error::other(message));
+    }
+
+    Ok(lines.map(|line| match serde_json::from_str(&line?) {
+        Ok(IpcReply::Event { event }) => Ok(event),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "expected an event",
+        )),
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+    }))
+}
+
+pub fn get_default_cache_dir() -> Result<PathBuf> {
+    let base = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var("HOME")?).join(".cache"),
+    };
+    Ok(base.join("maypaper"))
+}
+
 #[derive(Debug, Clone)]
 pub struct Paths {
     pub base: PathBuf,       // ~/.config/maypaper (or override)
     pub config: PathBuf,     // ~/.config/maypaper/wallpapers.toml
     pub wallpapers: PathBuf, // ~/.config/maypaper/wallpapers/
+    pub state: PathBuf,      // ~/.config/maypaper/state.json
 }
 
 impl Paths {
     pub fn get_dirs(config_dir_override: Option<PathBuf>) -> Result<Self> {
         let base = match config_dir_override {
             Some(p) => p,
-            None => PathBuf::from(env::var("XDG_CONFIG_HOME")?).join("maypaper"),
+            None => match env::var_os("XDG_CONFIG_HOME") {
+                Some(dir) => PathBuf::from(dir).join("maypaper"),
+                None => PathBuf::from(env::var("HOME")?).join(".config/maypaper"),
+            },
         };
 
         Ok(Self {
             config: base.join("wallpapers.toml"),
             wallpapers: base.join("wallpapers"),
+            state: base.join("state.json"),
             base,
         })
     }
@@ -0,0 +1,116 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::event::Properties;
+
+// Optional, at the root of each wallpaper in the library
+pub const MANIFEST_FILE: &str = "manifest.toml";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Manifest {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub version: Option<String>,
+    pub license: Option<String>,
+    // Relative to the wallpaper's directory
+    pub preview: Option<String>,
+    // Defaults for globalThis.maypaper.properties, overridden by those given when setting it
+    pub properties: Properties,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub name: String,
+    pub path: PathBuf,
+    pub manifest: Manifest,
+    // Set when the manifest exists but couldn't be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problem: Option<String>,
+}
+
+// Same rules myppm uses for the directories it installs
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        bail!("Wallpaper name is empty");
+    }
+    if name.contains('/') || name.contains('\\') {
+        bail!("Wallpaper name contains a path separator ('/' or '\\'): {name}");
+    }
+    if name == "." || name == ".." || name.contains("..") {
+        bail!("Wallpaper name contains '.' or '..' segments: {name}");
+    }
+    Ok(())
+}
+
+fn read_entry(path: PathBuf, name: String) -> Entry {
+    let (manifest, problem) = match read_manifest(&path) {
+        Ok(manifest) => (manifest, None),
+        Err(e) => (Manifest::default(), Some(format!("{e:#}"))),
+    };
+
+    Entry {
+        name,
+        path,
+        manifest,
+        problem,
+    }
+}
+
+fn read_manifest(dir: &Path) -> Result<Manifest> {
+    let file = dir.join(MANIFEST_FILE);
+    let contents = match fs::read_to_string(&file) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Manifest::default()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", file.display())),
+    };
+
+    toml::from_str(&contents).with_context(|| format!("Failed to parse {}", file.display()))
+}
+
+// Every subdirectory is a wallpaper, hidden ones are left alone
+pub fn list(library: &Path) -> Result<Vec<Entry>> {
+    let dirs = match fs::read_dir(library) {
+        Ok(dirs) => dirs,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", library.display()));
+        }
+    };
+
+    let mut entries: Vec<Entry> = dirs
+        .flatten()
+        .filter(|e| e.path().is_dir())
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            if name.starts_with('.') {
+                return None;
+            }
+            Some(read_entry(e.path(), name))
+        })
+        .collect();
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+pub fn get(library: &Path, name: &str) -> Result<Entry> {
+    validate_name(name)?;
+
+    let path = library.join(name);
+    if !path.is_dir() {
+        bail!("No wallpaper named {name} in {}", library.display());
+    }
+
+    Ok(read_entry(path, name.to_string()))
+}
+
+pub fn remove(library: &Path, name: &str) -> Result<()> {
+    let entry = get(library, name)?;
+    fs::remove_dir_all(&entry.path)
+        .with_context(|| format!("Failed to remove {}", entry.path.display()))
+}
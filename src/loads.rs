@@ -0,0 +1,447 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+use crate::event::Ipc;
+
+// Answered once every monitor of the request has loaded its page, or failed to
+pub type Reply = oneshot::Sender<Result<(), String>>;
+
+#[derive(Debug)]
+struct Load {
+    request: Option<u64>,
+    // What the UI was told to show, None while its server starts
+    url: Option<String>,
+    timeout: Duration,
+    deadline: Instant,
+    // It shows the fallback, which isn't fallen back from
+    fallback: bool,
+    // Sent again by the retries of [fallback] if the page fails
+    retry: Option<Ipc>,
+}
+
+#[derive(Debug)]
+struct Waiting {
+    left: usize,
+    errors: Vec<String>,
+    reply: Reply,
+}
+
+#[derive(Debug)]
+struct Retry {
+    url: String,
+    msg: Ipc,
+    every: Duration,
+    at: Instant,
+}
+
+#[derive(Debug)]
+pub struct Failed {
+    pub connector: String,
+    pub url: Option<String>,
+    pub error: String,
+    // The fallback itself failed
+    pub fallback: bool,
+    pub retry: Option<Ipc>,
+}
+
+// The page each monitor is loading, and who to tell how it went
+#[derive(Debug, Default)]
+pub struct Loads {
+    loading: HashMap<String, Load>,
+    waiting: HashMap<u64, Waiting>,
+    next_id: u64,
+    // The next load of these monitors shows their fallback
+    falling_back: HashSet<String>,
+    // Remote pages that failed, by the monitor they are for
+    retries: HashMap<String, Retry>,
+}
+
+impl Loads {
+    // Waits for this many monitors before answering. Nothing to wait for is answered right away
+    pub fn wait(&mut self, reply: Option<Reply>, monitors: usize) -> Option<u64> {
+        let reply = reply?;
+        if monitors == 0 {
+            let _ = reply.send(Ok(()));
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.waiting.insert(
+            id,
+            Waiting {
+                left: monitors,
+                errors: Vec::new(),
+                reply,
+            },
+        );
+        Some(id)
+    }
+
+    // The monitor is about to show something new. A load it replaces counts as done
+    pub fn start(
+        &mut self,
+        connector: &str,
+        request: Option<u64>,
+        timeout: Duration,
+        retry: Option<Ipc>,
+    ) {
+        let fallback = self.falling_back.remove(connector);
+        if !fallback {
+            self.retries.remove(connector);
+        }
+        let load = Load {
+            request,
+            url: None,
+            timeout,
+            deadline: Instant::now() + timeout,
+            fallback,
+            retry,
+        };
+        if let Some(old) = self.loading.insert(connector.to_string(), load) {
+            self.resolve(old.request, None);
+        }
+    }
+
+    // The UI was told to show url. Returns whether it's the fallback
+    pub fn showing(&mut self, connector: &str, url: &str) -> bool {
+        let Some(load) = self.loading.get_mut(connector) else {
+            return false;
+        };
+        load.url = Some(url.to_string());
+        load.fallback
+    }
+
+    // Reports for pages nobody waits on, or that were replaced since, are ignored
+    pub fn finished(
+        &mut self,
+        connector: &str,
+        url: &str,
+        error: Option<String>,
+    ) -> Option<Failed> {
+        if self.loading.get(connector)?.url.as_deref() != Some(url) {
+            return None;
+        }
+        let load = self.loading.remove(connector)?;
+        self.resolve(
+            load.request,
+            error.as_ref().map(|e| format!("{connector}: {e}")),
+        );
+        Some(Failed {
+            connector: connector.to_string(),
+            url: load.url,
+            error: error?,
+            fallback: load.fallback,
+            retry: load.retry,
+        })
+    }
+
+    // The page couldn't be served, so the UI has nothing to report. Ignored once the UI
+    // was given a page, that load is a newer one
+    pub fn unserved(&mut self, connector: &str, error: String) -> Option<Failed> {
+        if self.loading.get(connector)?.url.is_some() {
+            return None;
+        }
+        let load = self.loading.remove(connector)?;
+        self.resolve(load.request, Some(format!("{connector}: {error}")));
+        Some(Failed {
+            connector: connector.to_string(),
+            url: None,
+            error,
+            fallback: load.fallback,
+            retry: load.retry,
+        })
+    }
+
+    // Loads past their deadline fail
+    pub fn expired(&mut self, now: Instant) -> Vec<Failed> {
+        let connectors: Vec<String> = self
+            .loading
+            .iter()
+            .filter(|(_, load)| load.deadline <= now)
+            .map(|(connector, _)| connector.clone())
+            .collect();
+
+        let mut failed = Vec::new();
+        for connector in connectors {
+            let Some(load) = self.loading.remove(&connector) else {
+                continue;
+            };
+            let error = format!("did not load within {}s", load.timeout.as_secs());
+            self.resolve(load.request, Some(format!("{connector}: {error}")));
+            failed.push(Failed {
+                connector,
+                url: load.url,
+                error,
+                fallback: load.fallback,
+                retry: load.retry,
+            });
+        }
+        failed
+    }
+
+    pub fn fall_back(&mut self, connector: &str) {
+        self.falling_back.insert(connector.to_string());
+    }
+
+    // Until the monitor shows something else. Only SetUrl has a page worth checking on
+    pub fn retry_later(&mut self, connector: &str, msg: Ipc, every: Duration) {
+        let Ipc::SetUrl { url, .. } = &msg else {
+            return;
+        };
+        self.retries.insert(
+            connector.to_string(),
+            Retry {
+                url: url.clone(),
+                msg,
+                every,
+                at: Instant::now() + every,
+            },
+        );
+    }
+
+    // The URL to check and what to send if it's back, for each retry that's due
+    pub fn due_retries(&mut self, now: Instant) -> Vec<(String, Ipc)> {
+        self.retries
+            .values_mut()
+            .filter(|retry| retry.at <= now)
+            .map(|retry| {
+                retry.at = now + retry.every;
+                (retry.url.clone(), retry.msg.clone())
+            })
+            .collect()
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let loads = self.loading.values().map(|load| load.deadline);
+        let retries = self.retries.values().map(|retry| retry.at);
+        loads.chain(retries).min()
+    }
+
+    // The monitor is gone, so whoever waits on it hears so
+    pub fn forget(&mut self, connector: &str) {
+        self.falling_back.remove(connector);
+        self.retries.remove(connector);
+        if let Some(load) = self.loading.remove(connector) {
+            self.resolve(load.request, Some(format!("{connector} was unplugged")));
+        }
+    }
+
+    fn resolve(&mut self, request: Option<u64>, error: Option<String>) {
+        let Some(id) = request else {
+            return;
+        };
+        let Some(waiting) = self.waiting.get_mut(&id) else {
+            return;
+        };
+        waiting.errors.extend(error);
+        waiting.left -= 1;
+        if waiting.left > 0 {
+            return;
+        }
+        if let Some(waiting) = self.waiting.remove(&id) {
+            let result = match waiting.errors.is_empty() {
+                true => Ok(()),
+                false => Err(waiting.errors.join("; ")),
+            };
+            let _ = waiting.reply.send(result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    fn set_url(url: &str) -> Ipc {
+        Ipc::SetUrl {
+            monitor: Some("DP-1".to_string()),
+            url: url.to_string(),
+            properties: Default::default(),
+            span: Vec::new(),
+        }
+    }
+
+    fn request(
+        loads: &mut Loads,
+        monitors: usize,
+    ) -> (Option<u64>, oneshot::Receiver<Result<(), String>>) {
+        let (reply, rx) = oneshot::channel();
+        (loads.wait(Some(reply), monitors), rx)
+    }
+
+    #[test]
+    fn answers_once_every_monitor_loaded() {
+        let mut loads = Loads::default();
+        let (id, mut rx) = request(&mut loads, 2);
+        for connector in ["DP-1", "DP-2"] {
+            loads.start(connector, id, TIMEOUT, None);
+            loads.showing(connector, "http://a/");
+        }
+
+        assert!(loads.finished("DP-1", "http://a/", None).is_none());
+        assert!(rx.try_recv().is_err());
+        assert!(loads.finished("DP-2", "http://a/", None).is_none());
+        assert_eq!(rx.try_recv().unwrap(), Ok(()));
+    }
+
+    #[test]
+    fn a_failure_is_reported_with_its_monitor() {
+        let mut loads = Loads::default();
+        let (id, mut rx) = request(&mut loads, 2);
+        for connector in ["DP-1", "DP-2"] {
+            loads.start(connector, id, TIMEOUT, None);
+            loads.showing(connector, "http://a/");
+        }
+
+        let failed = loads
+            .finished("DP-1", "http://a/", Some("Host not found".to_string()))
+            .unwrap();
+        assert_eq!(failed.connector, "DP-1");
+        assert!(!failed.fallback);
+        loads.finished("DP-2", "http://a/", None);
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            Err("DP-1: Host not found".to_string())
+        );
+    }
+
+    #[test]
+    fn nothing_to_wait_for_is_answered_right_away() {
+        let mut loads = Loads::default();
+        let (id, mut rx) = request(&mut loads, 0);
+        assert_eq!(id, None);
+        assert_eq!(rx.try_recv().unwrap(), Ok(()));
+    }
+
+    // The UI reports back the URL as it was requested, not as Qt normalized it
+    #[test]
+    fn reports_match_the_requested_url_exactly() {
+        for (requested, normalized) in [
+            ("example.org", "http://example.org"),
+            (
+                "data:text/html,%3Chtml%3E%3Cbody%20style%3D%22background%3A%23000%22%3E",
+                "data:text/html,<html><body style=\"background:%23000\">",
+            ),
+        ] {
+            let mut loads = Loads::default();
+            let (id, mut rx) = request(&mut loads, 1);
+            loads.start("DP-1", id, TIMEOUT, None);
+            loads.showing("DP-1", requested);
+
+            assert!(loads.finished("DP-1", normalized, None).is_none());
+            assert!(rx.try_recv().is_err());
+            loads.finished("DP-1", requested, None);
+            assert_eq!(rx.try_recv().unwrap(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn stale_reports_are_ignored() {
+        let mut loads = Loads::default();
+        let (id, mut rx) = request(&mut loads, 1);
+        loads.start("DP-1", id, TIMEOUT, None);
+        loads.showing("DP-1", "http://b/");
+
+        assert!(
+            loads
+                .finished("DP-1", "http://a/", Some("Aborted".to_string()))
+                .is_none()
+        );
+        assert!(loads.finished("DP-2", "http://b/", None).is_none());
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn a_replaced_load_counts_as_done() {
+        let mut loads = Loads::default();
+        let (first, mut first_rx) = request(&mut loads, 1);
+        loads.start("DP-1", first, TIMEOUT, None);
+        let (second, mut second_rx) = request(&mut loads, 1);
+        loads.start("DP-1", second, TIMEOUT, None);
+
+        assert_eq!(first_rx.try_recv().unwrap(), Ok(()));
+        assert!(second_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn loads_past_their_deadline_fail() {
+        let mut loads = Loads::default();
+        let (id, mut rx) = request(&mut loads, 1);
+        loads.start("DP-1", id, TIMEOUT, None);
+
+        assert!(loads.expired(Instant::now()).is_empty());
+        let failed = loads.expired(Instant::now() + TIMEOUT);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].error, "did not load within 30s");
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            Err("DP-1: did not load within 30s".to_string())
+        );
+        assert_eq!(loads.next_deadline(), None);
+    }
+
+    #[test]
+    fn the_fallback_is_marked_and_keeps_the_retry() {
+        let mut loads = Loads::default();
+        loads.start("DP-1", None, TIMEOUT, Some(set_url("http://a/")));
+        loads.showing("DP-1", "http://a/");
+        let failed = loads
+            .finished("DP-1", "http://a/", Some("Host not found".to_string()))
+            .unwrap();
+        loads.retry_later("DP-1", failed.retry.unwrap(), TIMEOUT);
+
+        loads.fall_back("DP-1");
+        loads.start("DP-1", None, TIMEOUT, None);
+        assert!(loads.showing("DP-1", "data:text/html,"));
+        let failed = loads
+            .finished("DP-1", "data:text/html,", Some("Broken".to_string()))
+            .unwrap();
+        assert!(failed.fallback);
+
+        let due = loads.due_retries(Instant::now() + TIMEOUT);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].0, "http://a/");
+    }
+
+    #[test]
+    fn showing_something_else_stops_the_retry() {
+        let mut loads = Loads::default();
+        loads.retry_later("DP-1", set_url("http://a/"), TIMEOUT);
+        loads.start("DP-1", None, TIMEOUT, None);
+        assert!(loads.due_retries(Instant::now() + TIMEOUT).is_empty());
+    }
+
+    #[test]
+    fn an_unserved_page_fails_without_waiting() {
+        let mut loads = Loads::default();
+        let (id, mut rx) = request(&mut loads, 1);
+        loads.start("DP-1", id, TIMEOUT, None);
+
+        let failed = loads
+            .unserved("DP-1", "Not an archive".to_string())
+            .unwrap();
+        assert_eq!(failed.url, None);
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            Err("DP-1: Not an archive".to_string())
+        );
+        assert_eq!(loads.next_deadline(), None);
+    }
+
+    #[test]
+    fn unplugging_fails_the_wait() {
+        let mut loads = Loads::default();
+        let (id, mut rx) = request(&mut loads, 1);
+        loads.start("DP-1", id, TIMEOUT, None);
+        loads.forget("DP-1");
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            Err("DP-1 was unplugged".to_string())
+        );
+    }
+}
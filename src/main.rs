@@ -1,27 +1,111 @@
-use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
-
+use std::time::Duration;
+
+use anyhow::Result;
+use clap::Parser;
+use maypaper::cache::CacheOptions;
+use maypaper::daemon::{self, Options};
+use maypaper::event::{Defaults, Monitor, PowerMode, SyncData, UiCmd, UiEvent, Viewport};
+use maypaper::state::State;
+use maypaper::ui::{self, Ui};
+use maypaper::webserver::WebOptions;
+use maypaper::{Paths, get_default_socket_path};
 use qmetaobject::prelude::*;
 use qmetaobject::{QObjectBox, QString, QStringList, QUrl, QVariant, queued_callback};
 
 use tokio::sync::{mpsc, watch};
-use tracing::{debug, info};
+use tracing::{info, warn};
+
+const QML: &str = include_str!("webview.qml");
 
-use crate::event::{
-    AcquireServer, IpcEvent, ReleaseServer, RequestServer, RequestWebview, SetWebview, TokioEvent,
-    UiCmd, UiEvent, WebCmd, WebEvent,
-};
+// Calls into webview.qml, through queued callbacks so they run on the Qt thread
+struct QmlUi {
+    set_wallpaper: Box<dyn Fn((QString, QString, QString)) + Send>,
+    set_property: Box<dyn Fn((QString, QString, QString)) + Send>,
+    set_defaults: Box<dyn Fn(QString) + Send>,
+    set_power: Box<dyn Fn(QString) + Send>,
+    set_frame_rate: Box<dyn Fn((QString, i32)) + Send>,
+    reload: Box<dyn Fn(QString) + Send>,
+}
 
-mod event;
-mod ipc;
-mod webserver;
+impl Ui for QmlUi {
+    fn set_wallpaper(&mut self, connector: &str, url: &str, viewport: Option<Viewport>) {
+        let viewport = serde_json::to_string(&viewport).unwrap_or_default();
+        (self.set_wallpaper)((
+            QString::from(connector),
+            QString::from(url),
+            QString::from(viewport),
+        ));
+    }
 
-const QML: &str = include_str!("webview.qml");
+    fn set_property(&mut self, connector: &str, key: &str, value: &serde_json::Value) {
+        (self.set_property)((
+            QString::from(connector),
+            QString::from(key),
+            QString::from(value.to_string()),
+        ));
+    }
+
+    fn apply_defaults(&mut self, defaults: &Defaults) {
+        let Ok(json) = serde_json::to_string(defaults) else {
+            return;
+        };
+        (self.set_defaults)(QString::from(json));
+    }
+
+    fn set_power(&mut self, mode: &PowerMode) {
+        let Ok(json) = serde_json::to_string(mode) else {
+            return;
+        };
+        (self.set_power)(QString::from(json));
+    }
+
+    fn set_frame_rate(&mut self, connector: &str, fps: Option<u32>) {
+        let fps = fps.map_or(0, |fps| fps.min(i32::MAX as u32) as i32);
+        (self.set_frame_rate)((QString::from(connector), fps));
+    }
 
-// --- Shared state from UI -> tokio
-#[derive(Clone, Default)]
-struct SyncData {
-    connectors: Vec<String>,
+    fn reload(&mut self, connector: &str) {
+        (self.reload)(QString::from(connector));
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "maypaper", version, about = "A webpage as a wallpaper")]
+struct Cli {
+    /// Serve all local wallpapers from one shared webserver, each mounted under /w/<id>/.
+    /// They share one origin then, so no page gets a token for the control API
+    #[arg(long)]
+    shared_server: bool,
+
+    /// Seconds to keep a webserver alive after its last wallpaper is released
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    linger: u64,
+
+    /// Maximum number of lingering webservers, the least recently used are shut down first
+    #[arg(long, value_name = "N", default_value_t = 4)]
+    max_idle: usize,
+
+    /// Serve remote URLs through a local caching proxy, so they keep working offline
+    #[arg(long)]
+    cache_urls: bool,
+
+    /// Seconds before a cached response is fetched again from its origin
+    #[arg(long, value_name = "SECS", default_value_t = 3600)]
+    cache_ttl: u64,
+
+    /// Where cached responses are stored. If left unspecified, XDG_CACHE_HOME/maypaper/urls is used
+    #[arg(long, value_name = "PATH")]
+    cache_dir: Option<PathBuf>,
+
+    /// The config directory. If left unspecified, XDG_CONFIG_HOME/maypaper is used
+    #[arg(long, value_name = "PATH")]
+    config_dir: Option<PathBuf>,
+
+    /// Serve Prometheus metrics on this loopback port, at /metrics
+    #[arg(long, value_name = "PORT")]
+    metrics_port: Option<u16>,
 }
 
 #[allow(non_snake_case)]
@@ -30,16 +114,20 @@ struct Bridge {
     base: qt_base_class!(trait QObject),
 
     sync_tx: Option<watch::Sender<Arc<SyncData>>>,
+    ui_event_tx: Option<mpsc::UnboundedSender<UiEvent>>,
 
-    // Called from QML, it gives us the connector names whenever they update
+    // Called from QML, it gives us the connector names whenever they update,
+    // along with a JSON array describing each monitor's geometry
     setMonitorNames: qt_method!(
-        fn setMonitorNames(&self, names: QStringList) {
+        fn setMonitorNames(&self, names: QStringList, info: QString) {
             let qs: Vec<QString> = names.into();
             let connectors: Vec<String> = qs.into_iter().map(|q| q.to_string()).collect();
+            let monitors = parse_monitors(&info.to_string());
 
             if let Some(sync_tx) = &self.sync_tx {
                 let _ = sync_tx.send(Arc::new(SyncData {
                     connectors: connectors.clone(),
+                    monitors,
                 }));
             }
 
@@ -49,121 +137,72 @@ struct Bridge {
             }
         }
     ),
-}
 
-// --- Tokio runtime thread ---
-fn start_tokio(
-    ui_tx: mpsc::UnboundedSender<UiCmd>,
-    mut ui_event_rx: mpsc::UnboundedReceiver<UiEvent>,
-    synx_rx: watch::Receiver<Arc<SyncData>>,
-) {
-    std::thread::spawn(move || {
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .expect("failed to build tokio runtime");
-
-        rt.block_on(async move {
-            let (tokio_tx, mut tokio_rx) = mpsc::unbounded_channel::<TokioEvent>();
-            let (web_tx, web_rx) = mpsc::unbounded_channel::<WebCmd>();
-
-            tokio::spawn(ipc::ipc_server(tokio_tx.clone()));
-            info!(target: "tokio", "Started ipc_server");
-
-            tokio::spawn(webserver::web_manager(tokio_tx.clone(), web_rx));
-            info!(target: "tokio", "Started web_manager");
-
-            loop {
-                tokio::select! {
-                    ui_evt = ui_event_rx.recv() => {
-                        match ui_evt {
-                            Some(UiEvent::ReleaseServer(release_server)) => {
-                                debug!(target: "tokio", release_server=?release_server, "Received from UI");
-                                let _ = web_tx.send(WebCmd::ReleaseServer(release_server));
-                            }
-                            None => break,
-                        }
-                    }
-
-                    tk = tokio_rx.recv() => {
-                        match tk {
-                            Some(event) => match event {
-                                TokioEvent::IpcEvent(ipc_event) => match ipc_event {
-                                    IpcEvent::RequestServer(request_server) => {
-                                        debug!(target: "tokio", request_server=?request_server, "Received");
-                                        handle_request_server(request_server, &synx_rx, &web_tx);
-                                    }
-                                    IpcEvent::RequestWebview(request_webview) => {
-                                        debug!(target: "tokio", request_webview=?request_webview, "Received");
-                                        handle_request_webview(request_webview, &synx_rx, &ui_tx);
-                                    }
-                                },
-
-                                TokioEvent::WebEvent(web_event) => match web_event {
-                                    WebEvent::SetWebview(set_webview) => {
-                                        debug!(target: "tokio", set_webview=?set_webview, "WebEvent -> UI");
-                                        let _ = ui_tx.send(UiCmd::SetWebview(set_webview));
-                                    }
-                                },
-                            },
-                            None => break,
-                        }
-                    }
-                }
+    // Called from QML when a page loads, url as Rust gave it
+    reportLoadSucceeded: qt_method!(
+        fn reportLoadSucceeded(&self, connector: QString, url: QString) {
+            if let Some(ui_event_tx) = &self.ui_event_tx {
+                let load_finished = maypaper::event::LoadFinished {
+                    connector: connector.to_string(),
+                    url: url.to_string(),
+                    error: None,
+                };
+                let _ = ui_event_tx.send(UiEvent::LoadFinished(load_finished));
             }
-        });
-    });
-}
+        }
+    ),
 
-fn handle_request_server(
-    request_server: RequestServer,
-    synx_rx: &watch::Receiver<Arc<SyncData>>,
-    web_tx: &mpsc::UnboundedSender<WebCmd>,
-) {
-    if let Some(connector) = request_server.connector.clone() {
-        let acquire = AcquireServer {
-            path: request_server.path,
-            connector,
-        };
-        let _ = web_tx.send(WebCmd::AcquireServer(acquire));
-    } else {
-        let sync: Arc<SyncData> = synx_rx.borrow().clone();
-        for connector in sync.connectors.iter() {
-            let acquire = AcquireServer {
-                path: request_server.path.clone(),
-                connector: connector.clone(),
-            };
-            let _ = web_tx.send(WebCmd::AcquireServer(acquire));
+    // Called from QML when a page fails to load, the daemon decides what to show instead
+    reportLoadFailed: qt_method!(
+        fn reportLoadFailed(&self, connector: QString, url: QString, error: QString) {
+            let connector = connector.to_string();
+            warn!(target: "qml", connector = %connector, url = %url, error = %error, "Page failed to load");
+            maypaper::metrics::METRICS.page_load_failed(&connector);
+
+            if let Some(ui_event_tx) = &self.ui_event_tx {
+                let load_finished = maypaper::event::LoadFinished {
+                    connector,
+                    url: url.to_string(),
+                    error: Some(error.to_string()),
+                };
+                let _ = ui_event_tx.send(UiEvent::LoadFinished(load_finished));
+            }
         }
-    }
+    ),
+
+    // Called from QML when a page's renderer process dies, the daemon decides when to reload it
+    reportRenderProcessTerminated: qt_method!(
+        fn reportRenderProcessTerminated(&self, connector: QString, status: i32, exit_code: i32) {
+            let connector = connector.to_string();
+            let reason = maypaper::event::Termination::from_status(status);
+            warn!(target: "qml", connector = %connector, reason = ?reason, exit_code, "Render process terminated");
+            maypaper::metrics::METRICS.render_process_restarted(&connector);
+
+            if let Some(ui_event_tx) = &self.ui_event_tx {
+                let terminated = maypaper::event::RenderProcessTerminated {
+                    connector,
+                    reason,
+                    exit_code,
+                };
+                let _ = ui_event_tx.send(UiEvent::RenderProcessTerminated(terminated));
+            }
+        }
+    ),
 }
 
-fn handle_request_webview(
-    request_webview: RequestWebview,
-    synx_rx: &watch::Receiver<Arc<SyncData>>,
-    ui_tx: &mpsc::UnboundedSender<UiCmd>,
-) {
-    if let Some(connector) = request_webview.connector.clone() {
-        let set_webview = SetWebview {
-            url: request_webview.url,
-            path: None,
-            connector,
-        };
-        let _ = ui_tx.send(UiCmd::SetWebview(set_webview));
-    } else {
-        let sync: Arc<SyncData> = synx_rx.borrow().clone();
-        for connector in sync.connectors.iter() {
-            let set_webview = SetWebview {
-                url: request_webview.url.clone(),
-                path: None,
-                connector: connector.clone(),
-            };
-            let _ = ui_tx.send(UiCmd::SetWebview(set_webview));
+fn parse_monitors(info: &str) -> Vec<Monitor> {
+    match serde_json::from_str(info) {
+        Ok(monitors) => monitors,
+        Err(e) => {
+            warn!(target: "main", error = %e, "Bad monitor info from QML");
+            Vec::new()
         }
     }
 }
 
-fn main() {
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
     if cfg!(debug_assertions) {
         tracing_subscriber::fmt()
             .with_max_level(tracing::Level::INFO)
@@ -173,12 +212,46 @@ fn main() {
     // unsafe { std::env::set_var("QT_WAYLAND_SHELL_INTEGRATION", "layer-shell") };
     // unsafe { std::env::set_var("QT_QPA_PLATFORM", "wayland") };
 
-    let (ui_tx, mut ui_rx) = mpsc::unbounded_channel::<UiCmd>();
+    let (ui_tx, ui_rx) = mpsc::unbounded_channel::<UiCmd>();
     let (ui_event_tx, ui_event_rx) = mpsc::unbounded_channel::<UiEvent>();
     let (sync_tx, sync_rx) = watch::channel(Arc::new(SyncData::default()));
 
+    let paths = Paths::get_dirs(cli.config_dir)?;
+    paths.ensure_dirs()?;
+    let state = State::load(paths.state.clone());
+
     info!(target: "main", "Starting tokio thread");
-    start_tokio(ui_tx.clone(), ui_event_rx, sync_rx.clone());
+    let cache = if cli.cache_urls {
+        let dir = match cli.cache_dir {
+            Some(dir) => dir,
+            None => maypaper::get_default_cache_dir()?.join("urls"),
+        };
+        Some(CacheOptions {
+            dir,
+            ttl: Duration::from_secs(cli.cache_ttl),
+        })
+    } else {
+        None
+    };
+
+    let web_options = WebOptions {
+        shared: cli.shared_server,
+        linger: Duration::from_secs(cli.linger),
+        max_idle: cli.max_idle,
+        cache,
+    };
+    daemon::start_tokio(
+        ui_tx.clone(),
+        ui_event_rx,
+        sync_rx.clone(),
+        Options {
+            web: web_options,
+            metrics_port: cli.metrics_port,
+            paths: paths.clone(),
+            socket: get_default_socket_path(),
+        },
+        state.assignments().clone(),
+    );
 
     // The following QT stuff is quite unrusty, but we'll migrate to QT
     // BRIDGES whenever that releases
@@ -187,50 +260,70 @@ fn main() {
     let bridge = QObjectBox::new(Bridge::default());
     let bridge_pinned = bridge.pinned();
     bridge_pinned.borrow_mut().sync_tx = Some(sync_tx.clone());
+    bridge_pinned.borrow_mut().ui_event_tx = Some(ui_event_tx.clone());
     engine.set_object_property("bridge".into(), bridge_pinned);
 
     engine.load_data(QML.into());
 
     let engine_ptr: *mut QmlEngine = &mut engine;
-    let qt_set_wallpaper = queued_callback(move |(connector, url): (QString, QString)| unsafe {
-        let qurl = QUrl::from_user_input(url);
-        let args = [QVariant::from(connector), QVariant::from(qurl)];
-        (&mut *engine_ptr).invoke_method_noreturn(QByteArray::from("setWallpaper"), &args);
+    // The viewport is JSON, null unless the wallpaper is spanned
+    let set_wallpaper = queued_callback(
+        move |(connector, url, viewport): (QString, QString, QString)| unsafe {
+            // Qt normalizes the URL, the string is passed along too so loads are reported
+            // under the URL they were requested with
+            let qurl = QUrl::from_user_input(url.clone());
+            let args = [
+                QVariant::from(connector),
+                QVariant::from(qurl),
+                QVariant::from(viewport),
+                QVariant::from(url),
+            ];
+            (&mut *engine_ptr).invoke_method_noreturn(QByteArray::from("setWallpaper"), &args);
+        },
+    );
+
+    let set_property = queued_callback(
+        move |(connector, key, value): (QString, QString, QString)| unsafe {
+            let args = [
+                QVariant::from(connector),
+                QVariant::from(key),
+                QVariant::from(value),
+            ];
+            (&mut *engine_ptr).invoke_method_noreturn(QByteArray::from("setProperty"), &args);
+        },
+    );
+
+    let set_defaults = queued_callback(move |defaults: QString| unsafe {
+        let args = [QVariant::from(defaults)];
+        (&mut *engine_ptr).invoke_method_noreturn(QByteArray::from("setDefaults"), &args);
     });
 
-    std::thread::spawn(move || {
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .expect("failed to build ui adapter runtime");
-
-        rt.block_on(async move {
-            let mut last_paths: HashMap<String, Option<String>> = HashMap::new();
-
-            while let Some(cmd) = ui_rx.recv().await {
-                match cmd {
-                    UiCmd::SetWebview(set_webview) => {
-                        let entry = last_paths
-                            .entry(set_webview.connector.clone())
-                            .or_insert(None);
-
-                        let old_path = entry.clone();
-                        *entry = set_webview.path.clone();
-
-                        if let Some(old) = old_path {
-                            let _ = ui_event_tx
-                                .send(UiEvent::ReleaseServer(ReleaseServer { path: old }));
-                        }
-
-                        qt_set_wallpaper((
-                            QString::from(set_webview.connector),
-                            QString::from(set_webview.url),
-                        ));
-                    }
-                }
-            }
-        });
+    let set_power = queued_callback(move |mode: QString| unsafe {
+        let args = [QVariant::from(mode)];
+        (&mut *engine_ptr).invoke_method_noreturn(QByteArray::from("setPower"), &args);
     });
 
+    // 0 goes back to the default
+    let set_frame_rate = queued_callback(move |(connector, fps): (QString, i32)| unsafe {
+        let args = [QVariant::from(connector), QVariant::from(fps)];
+        (&mut *engine_ptr).invoke_method_noreturn(QByteArray::from("setFrameRate"), &args);
+    });
+
+    let reload = queued_callback(move |connector: QString| unsafe {
+        let args = [QVariant::from(connector)];
+        (&mut *engine_ptr).invoke_method_noreturn(QByteArray::from("reloadWallpaper"), &args);
+    });
+
+    let qml = QmlUi {
+        set_wallpaper: Box::new(set_wallpaper),
+        set_property: Box::new(set_property),
+        set_defaults: Box::new(set_defaults),
+        set_power: Box::new(set_power),
+        set_frame_rate: Box::new(set_frame_rate),
+        reload: Box::new(reload),
+    };
+    ui::spawn_adapter(qml, ui_rx, ui_event_tx, state);
+
     engine.exec();
+    Ok(())
 }
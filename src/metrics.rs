@@ -0,0 +1,341 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    Router,
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+// Upper bounds, in seconds, of the request latency histogram
+const BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+struct Latency {
+    // One count per bucket, plus the implicit +Inf which is `count`
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+#[derive(Default)]
+struct Instance {
+    watchers: usize,
+    lingering: bool,
+}
+
+#[derive(Default)]
+struct Inner {
+    // Keyed by instance path, then status code
+    requests: HashMap<String, BTreeMap<u16, u64>>,
+    latency: HashMap<String, Latency>,
+    instances: HashMap<String, Instance>,
+    ipc_messages: BTreeMap<&'static str, u64>,
+    // Keyed by connector
+    page_load_failures: BTreeMap<String, u64>,
+    render_process_restarts: BTreeMap<String, u64>,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    inner: Mutex<Inner>,
+}
+
+impl Metrics {
+    fn request(&self, path: &str, status: u16, elapsed: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        *inner
+            .requests
+            .entry(path.to_string())
+            .or_default()
+            .entry(status)
+            .or_default() += 1;
+
+        let seconds = elapsed.as_secs_f64();
+        let latency = inner.latency.entry(path.to_string()).or_default();
+        for (bucket, bound) in latency.buckets.iter_mut().zip(BUCKETS) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        latency.count += 1;
+        latency.sum += seconds;
+    }
+
+    // Replaces the instance table wholesale, web_manager owns the real one. Series of
+    // instances that shut down go with them, or every wallpaper ever shown would keep one
+    pub fn instances(&self, instances: impl Iterator<Item = (String, usize, bool)>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.instances = instances
+            .map(|(path, watchers, lingering)| {
+                (
+                    path,
+                    Instance {
+                        watchers,
+                        lingering,
+                    },
+                )
+            })
+            .collect();
+
+        let Inner {
+            requests,
+            latency,
+            instances,
+            ..
+        } = &mut *inner;
+        requests.retain(|path, _| instances.contains_key(path));
+        latency.retain(|path, _| instances.contains_key(path));
+    }
+
+    pub fn ipc_message(&self, kind: &'static str) {
+        *self
+            .inner
+            .lock()
+            .unwrap()
+            .ipc_messages
+            .entry(kind)
+            .or_default() += 1;
+    }
+
+    pub fn page_load_failed(&self, connector: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        *inner
+            .page_load_failures
+            .entry(connector.to_string())
+            .or_default() += 1;
+    }
+
+    pub fn render_process_restarted(&self, connector: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        *inner
+            .render_process_restarts
+            .entry(connector.to_string())
+            .or_default() += 1;
+    }
+
+    // Prometheus text exposition format
+    fn render(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "# HELP maypaper_http_requests_total Requests served, per wallpaper server"
+        );
+        let _ = writeln!(out, "# TYPE maypaper_http_requests_total counter");
+        for (path, statuses) in &inner.requests {
+            for (status, count) in statuses {
+                let _ = writeln!(
+                    out,
+                    "maypaper_http_requests_total{{instance=\"{}\",status=\"{status}\"}} {count}",
+                    escape(path)
+                );
+            }
+        }
+
+        let name = "maypaper_http_request_duration_seconds";
+        let _ = writeln!(out, "# HELP {name} Request latency, per wallpaper server");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        for (path, latency) in &inner.latency {
+            let path = escape(path);
+            for (bucket, bound) in latency.buckets.iter().zip(BUCKETS) {
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{{instance=\"{path}\",le=\"{bound}\"}} {bucket}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{instance=\"{path}\",le=\"+Inf\"}} {}",
+                latency.count
+            );
+            let _ = writeln!(out, "{name}_sum{{instance=\"{path}\"}} {}", latency.sum);
+            let _ = writeln!(out, "{name}_count{{instance=\"{path}\"}} {}", latency.count);
+        }
+
+        let lingering = inner.instances.values().filter(|i| i.lingering).count();
+        let _ = writeln!(
+            out,
+            "# HELP maypaper_servers Wallpaper servers currently running"
+        );
+        let _ = writeln!(out, "# TYPE maypaper_servers gauge");
+        let _ = writeln!(
+            out,
+            "maypaper_servers{{state=\"active\"}} {}",
+            inner.instances.len() - lingering
+        );
+        let _ = writeln!(out, "maypaper_servers{{state=\"lingering\"}} {lingering}");
+
+        let _ = writeln!(
+            out,
+            "# HELP maypaper_server_watchers Monitors showing each wallpaper server"
+        );
+        let _ = writeln!(out, "# TYPE maypaper_server_watchers gauge");
+        for (path, instance) in &inner.instances {
+            let _ = writeln!(
+                out,
+                "maypaper_server_watchers{{instance=\"{}\"}} {}",
+                escape(path),
+                instance.watchers
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP maypaper_ipc_messages_total IPC messages received, by type"
+        );
+        let _ = writeln!(out, "# TYPE maypaper_ipc_messages_total counter");
+        for (kind, count) in &inner.ipc_messages {
+            let _ = writeln!(
+                out,
+                "maypaper_ipc_messages_total{{type=\"{kind}\"}} {count}"
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP maypaper_page_load_failures_total Pages that failed to load, per monitor"
+        );
+        let _ = writeln!(out, "# TYPE maypaper_page_load_failures_total counter");
+        for (connector, count) in &inner.page_load_failures {
+            let _ = writeln!(
+                out,
+                "maypaper_page_load_failures_total{{connector=\"{}\"}} {count}",
+                escape(connector)
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP maypaper_render_process_restarts_total Renderer processes that died and \
+             had to be replaced, per monitor"
+        );
+        let _ = writeln!(out, "# TYPE maypaper_render_process_restarts_total counter");
+        for (connector, count) in &inner.render_process_restarts {
+            let _ = writeln!(
+                out,
+                "maypaper_render_process_restarts_total{{connector=\"{}\"}} {count}",
+                escape(connector)
+            );
+        }
+
+        out
+    }
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Layered onto every wallpaper server, the state is the instance's path
+pub async fn track_request(State(path): State<String>, req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let res = next.run(req).await;
+    METRICS.request(&path, res.status().as_u16(), start.elapsed());
+    res
+}
+
+pub async fn serve(port: u16) {
+    let listener = match TcpListener::bind(("127.0.0.1", port)).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!(target: "metrics", port, error = %e, "Failed to bind metrics port");
+            return;
+        }
+    };
+    info!(target: "metrics", port, "Serving metrics");
+
+    let app = Router::new().route(
+        "/metrics",
+        get(|| async {
+            (
+                [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                METRICS.render(),
+            )
+                .into_response()
+        }),
+    );
+
+    if let Err(e) = axum::serve(listener, app).await {
+        error!(target: "metrics", error = %e, "Metrics server error");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn series_go_with_their_instance() {
+        let metrics = Metrics::default();
+        let instance = |path: &str| (path.to_string(), 1, false);
+        metrics.instances([instance("/a"), instance("/b")].into_iter());
+        metrics.request("/a", 200, Duration::from_millis(3));
+        metrics.request("/b", 404, Duration::from_millis(3));
+
+        metrics.instances([instance("/b")].into_iter());
+        let rendered = metrics.render();
+        assert!(!rendered.contains("instance=\"/a\""), "{rendered}");
+        assert!(
+            rendered.contains("maypaper_http_requests_total{instance=\"/b\",status=\"404\"} 1")
+        );
+    }
+
+    #[test]
+    fn histogram_is_cumulative() {
+        let metrics = Metrics::default();
+        metrics.instances([("/a".to_string(), 1, false)].into_iter());
+        for millis in [3, 20, 20, 30_000] {
+            metrics.request("/a", 200, Duration::from_millis(millis));
+        }
+
+        let rendered = metrics.render();
+        let series = |line: &str| {
+            assert!(rendered.lines().any(|l| l == line), "{line} in {rendered}");
+        };
+        let name = "maypaper_http_request_duration_seconds";
+        series(&format!("# TYPE {name} histogram"));
+        series(&format!("{name}_bucket{{instance=\"/a\",le=\"0.005\"}} 1"));
+        series(&format!("{name}_bucket{{instance=\"/a\",le=\"0.01\"}} 1"));
+        series(&format!("{name}_bucket{{instance=\"/a\",le=\"0.025\"}} 3"));
+        series(&format!("{name}_bucket{{instance=\"/a\",le=\"10\"}} 3"));
+        series(&format!("{name}_bucket{{instance=\"/a\",le=\"+Inf\"}} 4"));
+        series(&format!("{name}_sum{{instance=\"/a\"}} 30.043"));
+        series(&format!("{name}_count{{instance=\"/a\"}} 4"));
+        series("maypaper_servers{state=\"active\"} 1");
+        series("maypaper_server_watchers{instance=\"/a\"} 1");
+        series(
+            "# HELP maypaper_render_process_restarts_total Renderer processes that died and \
+             had to be replaced, per monitor",
+        );
+    }
+
+    #[test]
+    fn labels_are_escaped() {
+        assert_eq!(escape("plain"), "plain");
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+
+        let metrics = Metrics::default();
+        metrics.page_load_failed("DP\"1");
+        let rendered = metrics.render();
+        assert!(
+            rendered.contains("maypaper_page_load_failures_total{connector=\"DP\\\"1\"} 1"),
+            "{rendered}"
+        );
+    }
+}
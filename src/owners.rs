@@ -0,0 +1,199 @@
+use std::collections::{BTreeSet, HashMap};
+
+// Which monitor shows which served path. A monitor owns at most one path,
+// and a path is watched for as long as some monitor owns it
+#[derive(Debug, Default)]
+pub struct Owners {
+    by_connector: HashMap<String, String>,
+    by_path: HashMap<String, BTreeSet<String>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Claimed {
+    // The path had no owners before, so its server has to be started or revived
+    pub first: bool,
+    // The path the monitor showed before, now owned by nobody
+    pub orphaned: Option<String>,
+}
+
+impl Owners {
+    // The monitor now shows path. Claiming the path it already owns changes nothing
+    pub fn claim(&mut self, connector: &str, path: &str) -> Claimed {
+        if self.path(connector) == Some(path) {
+            return Claimed {
+                first: false,
+                orphaned: None,
+            };
+        }
+
+        let orphaned = self.release(connector);
+        let owners = self.by_path.entry(path.to_string()).or_default();
+        let first = owners.is_empty();
+        owners.insert(connector.to_string());
+        self.by_connector
+            .insert(connector.to_string(), path.to_string());
+
+        Claimed { first, orphaned }
+    }
+
+    // The monitor shows no served path anymore, be it a URL or nothing at all.
+    // Returns its old path if nobody else owns it
+    pub fn release(&mut self, connector: &str) -> Option<String> {
+        let path = self.by_connector.remove(connector)?;
+        let owners = self.by_path.get_mut(&path)?;
+        owners.remove(connector);
+        if !owners.is_empty() {
+            return None;
+        }
+        self.by_path.remove(&path);
+        Some(path)
+    }
+
+    pub fn path(&self, connector: &str) -> Option<&str> {
+        self.by_connector.get(connector).map(String::as_str)
+    }
+
+    pub fn watchers(&self, path: &str) -> usize {
+        self.by_path.get(path).map_or(0, BTreeSet::len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every owned path is in both maps, and no path is kept without owners
+    fn check(owners: &Owners) {
+        for (connector, path) in &owners.by_connector {
+            assert!(owners.by_path[path].contains(connector));
+        }
+        for (path, connectors) in &owners.by_path {
+            assert!(!connectors.is_empty(), "{path} kept with no owners");
+            for connector in connectors {
+                assert_eq!(owners.path(connector), Some(path.as_str()));
+            }
+        }
+    }
+
+    #[test]
+    fn first_claim_starts_the_path() {
+        let mut owners = Owners::default();
+        let claimed = owners.claim("DP-1", "/a");
+        assert_eq!(
+            claimed,
+            Claimed {
+                first: true,
+                orphaned: None
+            }
+        );
+        assert_eq!(owners.watchers("/a"), 1);
+        check(&owners);
+    }
+
+    #[test]
+    fn second_monitor_shares_the_path() {
+        let mut owners = Owners::default();
+        owners.claim("DP-1", "/a");
+        let claimed = owners.claim("DP-2", "/a");
+        assert_eq!(
+            claimed,
+            Claimed {
+                first: false,
+                orphaned: None
+            }
+        );
+        assert_eq!(owners.watchers("/a"), 2);
+        check(&owners);
+    }
+
+    #[test]
+    fn reclaiming_the_same_path_changes_nothing() {
+        let mut owners = Owners::default();
+        owners.claim("DP-1", "/a");
+        let claimed = owners.claim("DP-1", "/a");
+        assert_eq!(
+            claimed,
+            Claimed {
+                first: false,
+                orphaned: None
+            }
+        );
+        assert_eq!(owners.watchers("/a"), 1);
+        check(&owners);
+    }
+
+    #[test]
+    fn switching_path_orphans_the_old_one() {
+        let mut owners = Owners::default();
+        owners.claim("DP-1", "/a");
+        let claimed = owners.claim("DP-1", "/b");
+        assert_eq!(
+            claimed,
+            Claimed {
+                first: true,
+                orphaned: Some("/a".to_string())
+            }
+        );
+        assert_eq!(owners.watchers("/a"), 0);
+        assert_eq!(owners.watchers("/b"), 1);
+        check(&owners);
+    }
+
+    #[test]
+    fn switching_path_keeps_a_shared_one() {
+        let mut owners = Owners::default();
+        owners.claim("DP-1", "/a");
+        owners.claim("DP-2", "/a");
+        let claimed = owners.claim("DP-1", "/b");
+        assert_eq!(claimed.orphaned, None);
+        assert_eq!(owners.watchers("/a"), 1);
+        check(&owners);
+    }
+
+    #[test]
+    fn switching_onto_a_watched_path() {
+        let mut owners = Owners::default();
+        owners.claim("DP-1", "/a");
+        owners.claim("DP-2", "/b");
+        let claimed = owners.claim("DP-1", "/b");
+        assert_eq!(
+            claimed,
+            Claimed {
+                first: false,
+                orphaned: Some("/a".to_string())
+            }
+        );
+        assert_eq!(owners.watchers("/b"), 2);
+        check(&owners);
+    }
+
+    #[test]
+    fn releasing_the_last_owner_orphans_the_path() {
+        let mut owners = Owners::default();
+        owners.claim("DP-1", "/a");
+        owners.claim("DP-2", "/a");
+        assert_eq!(owners.release("DP-1"), None);
+        assert_eq!(owners.release("DP-2"), Some("/a".to_string()));
+        assert_eq!(owners.path("DP-2"), None);
+        check(&owners);
+    }
+
+    #[test]
+    fn releasing_twice_or_unknown_is_a_no_op() {
+        let mut owners = Owners::default();
+        assert_eq!(owners.release("DP-1"), None);
+        owners.claim("DP-1", "/a");
+        assert_eq!(owners.release("DP-1"), Some("/a".to_string()));
+        assert_eq!(owners.release("DP-1"), None);
+        check(&owners);
+    }
+
+    #[test]
+    fn reclaiming_after_release_starts_again() {
+        let mut owners = Owners::default();
+        owners.claim("DP-1", "/a");
+        owners.release("DP-1");
+        assert!(owners.claim("DP-2", "/a").first);
+        check(&owners);
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+use crate::event::{Playlist, RequestServer, RequestWebview};
+
+// A request for monitors QML hasn't reported yet
+#[derive(Debug)]
+pub enum Held {
+    Server(RequestServer),
+    Webview(RequestWebview),
+    Playlist {
+        connector: Option<String>,
+        name: String,
+        playlist: Playlist,
+    },
+}
+
+impl Held {
+    // None is every monitor
+    pub fn connector(&self) -> Option<&str> {
+        match self {
+            Held::Server(request_server) => request_server.connector.as_deref(),
+            Held::Webview(request_webview) => request_webview.connector.as_deref(),
+            Held::Playlist { connector, .. } => connector.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Pending {
+    all: Option<Held>,
+    by_connector: HashMap<String, Held>,
+}
+
+// Whether a request for this monitor, or every monitor, has somewhere to go.
+// A spanned one needs all of its monitors
+pub fn ready(connector: Option<&str>, span: &[String], connectors: &[String]) -> bool {
+    if !span.is_empty() {
+        return span.iter().all(|s| connectors.contains(s));
+    }
+    match connector {
+        Some(connector) => connectors.iter().any(|c| c == connector),
+        None => !connectors.is_empty(),
+    }
+}
+
+impl Pending {
+    // The newest request for a monitor replaces the one held for it,
+    // and one for every monitor replaces them all
+    pub fn hold(&mut self, held: Held) {
+        match held.connector() {
+            Some(connector) => {
+                self.by_connector.insert(connector.to_string(), held);
+            }
+            None => {
+                self.by_connector.clear();
+                self.all = Some(held);
+            }
+        }
+    }
+
+    // The requests these monitors can now take, every-monitor ones first so the others win
+    pub fn take(&mut self, connectors: &[String]) -> Vec<Held> {
+        if connectors.is_empty() {
+            return Vec::new();
+        }
+
+        let mut ready: Vec<Held> = self.all.take().into_iter().collect();
+        for connector in connectors {
+            if let Some(held) = self.by_connector.remove(connector) {
+                ready.push(held);
+            }
+        }
+        ready
+    }
+}
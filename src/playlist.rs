@@ -0,0 +1,425 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, bail};
+use tokio::{
+    sync::mpsc,
+    time::{Instant, sleep_until},
+};
+use tracing::{debug, info, warn};
+
+use crate::event::{
+    CursorMode, IpcReply, Playlist, PlaylistAction, PlaylistCmd, PlaylistEntry, TokioEvent,
+};
+use crate::ipc;
+
+// How far back prev can go
+const HISTORY: usize = 64;
+
+// A year, which keeps a deadline of interval times weight far from overflowing
+const MAX_INTERVAL: u64 = 365 * 24 * 60 * 60;
+
+pub fn validate(playlist: &Playlist) -> Result<()> {
+    if playlist.entries.is_empty() {
+        bail!("playlist has no entries");
+    }
+    if playlist.interval == 0 {
+        bail!("playlist interval must be at least 1 second");
+    }
+    if playlist.interval > MAX_INTERVAL {
+        bail!("playlist interval must be at most {MAX_INTERVAL} seconds");
+    }
+
+    for entry in &playlist.entries {
+        if let PlaylistEntry::Item {
+            wallpaper,
+            path,
+            url,
+            weight,
+            ..
+        } = entry
+        {
+            let given = [wallpaper, path, url]
+                .iter()
+                .filter(|t| t.is_some())
+                .count();
+            if given != 1 {
+                bail!("playlist entries need exactly one of wallpaper, path or url");
+            }
+            if *weight == 0 {
+                bail!("playlist entry weights must be at least 1");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Good enough to shuffle wallpapers, not worth a dependency
+struct Rng(u64);
+
+impl Rng {
+    fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self(seed | 1)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        // xorshift64
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % n
+    }
+}
+
+// A position in a playlist, shared by the monitors it drives
+struct Cursor {
+    name: String,
+    playlist: Arc<Playlist>,
+    connectors: Vec<String>,
+    next_at: Instant,
+    // Shuffled playlists draw every entry weight times per round
+    remaining: Vec<u32>,
+    next_in_order: usize,
+    // Shown entries, the last one is current
+    history: Vec<usize>,
+    // Entries stepped back over, shown again by next before drawing new ones
+    back: Vec<usize>,
+}
+
+impl Cursor {
+    fn new(name: &str, playlist: Arc<Playlist>, connectors: Vec<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            playlist,
+            connectors,
+            next_at: Instant::now(),
+            remaining: Vec::new(),
+            next_in_order: 0,
+            history: Vec::new(),
+            back: Vec::new(),
+        }
+    }
+
+    fn advance(&mut self, rng: &mut Rng) -> usize {
+        let index = match self.back.pop() {
+            Some(index) => index,
+            None => self.draw(rng),
+        };
+
+        self.history.push(index);
+        if self.history.len() > HISTORY {
+            self.history.remove(0);
+        }
+        index
+    }
+
+    fn retreat(&mut self) -> Option<usize> {
+        if self.history.len() < 2 {
+            return None;
+        }
+        let current = self.history.pop()?;
+        self.back.push(current);
+        self.history.last().copied()
+    }
+
+    fn draw(&mut self, rng: &mut Rng) -> usize {
+        let entries = &self.playlist.entries;
+
+        if !self.playlist.shuffle {
+            let index = self.next_in_order % entries.len();
+            self.next_in_order = index + 1;
+            return index;
+        }
+
+        if self.remaining.iter().all(|&n| n == 0) {
+            self.remaining = entries.iter().map(PlaylistEntry::weight).collect();
+        }
+
+        // Weighted by what's left of this round, never what was just shown unless nothing else is left
+        let last = self.history.last().copied();
+        let total: u64 = self
+            .remaining
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| Some(*i) != last)
+            .map(|(_, &n)| u64::from(n))
+            .sum();
+
+        let index = if total == 0 {
+            last.unwrap_or(0)
+        } else {
+            let mut pick = rng.below(total);
+            self.remaining
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| Some(*i) != last)
+                .find_map(|(i, &n)| {
+                    if pick < u64::from(n) {
+                        Some(i)
+                    } else {
+                        pick -= u64::from(n);
+                        None
+                    }
+                })
+                .unwrap_or(0)
+        };
+
+        self.remaining[index] = self.remaining[index].saturating_sub(1);
+        index
+    }
+
+    fn show(&mut self, index: usize, tx: &mpsc::UnboundedSender<TokioEvent>) {
+        let entry = &self.playlist.entries[index];
+
+        // Ordered playlists give heavier entries more time, shuffled ones more turns
+        let turns = if self.playlist.shuffle {
+            1
+        } else {
+            entry.weight()
+        };
+        self.next_at = Instant::now()
+            + Duration::from_secs(self.playlist.interval.saturating_mul(u64::from(turns)));
+
+        for connector in &self.connectors {
+            info!(target: "playlist", playlist = %self.name, connector = %connector, index, "Showing");
+
+            // Through the IPC path, spawned so a slow name lookup doesn't hold up the others
+            let msg = entry.to_ipc(Some(connector.clone()));
+            let tx = tx.clone();
+            let connector = connector.clone();
+            tokio::spawn(async move {
+                if let IpcReply::Error { message } = ipc::handle_msg(msg, &tx).await {
+                    warn!(target: "playlist", connector = %connector, error = %message, "Failed to show entry");
+                }
+            });
+        }
+    }
+}
+
+pub async fn playlist_manager(
+    tx: mpsc::UnboundedSender<TokioEvent>,
+    mut rx: mpsc::UnboundedReceiver<PlaylistCmd>,
+) {
+    let mut cursors: Vec<Cursor> = Vec::new();
+    let mut rng = Rng::new();
+
+    loop {
+        let next_at = cursors.iter().map(|c| c.next_at).min();
+
+        tokio::select! {
+            cmd = rx.recv() => {
+                let Some(cmd) = cmd else {
+                    break;
+                };
+                debug!(target: "playlist", cmd = ?cmd, "Received");
+
+                let result = handle_cmd(&mut cursors, cmd.action, &cmd.connectors, &tx, &mut rng);
+                let _ = cmd.reply.send(result);
+            }
+
+            _ = sleep_until(next_at.unwrap_or_else(Instant::now)), if next_at.is_some() => {
+                let now = Instant::now();
+                for cursor in cursors.iter_mut().filter(|c| c.next_at <= now) {
+                    let index = cursor.advance(&mut rng);
+                    cursor.show(index, &tx);
+                }
+            }
+        }
+    }
+}
+
+fn handle_cmd(
+    cursors: &mut Vec<Cursor>,
+    action: PlaylistAction,
+    connectors: &[String],
+    tx: &mpsc::UnboundedSender<TokioEvent>,
+    rng: &mut Rng,
+) -> Result<(), String> {
+    let targets = |c: &Cursor| {
+        connectors.is_empty() || c.connectors.iter().any(|name| connectors.contains(name))
+    };
+
+    match action {
+        PlaylistAction::Start { name, playlist } => {
+            // A monitor only follows one playlist at a time
+            detach(cursors, connectors);
+
+            let playlist = Arc::new(playlist);
+            let groups: Vec<Vec<String>> = match playlist.cursor {
+                CursorMode::Shared => vec![connectors.to_vec()],
+                CursorMode::PerMonitor => connectors.iter().map(|c| vec![c.clone()]).collect(),
+            };
+
+            for group in groups {
+                let mut cursor = Cursor::new(&name, playlist.clone(), group);
+                let index = cursor.advance(rng);
+                cursor.show(index, tx);
+                cursors.push(cursor);
+            }
+            info!(target: "playlist", playlist = %name, connectors = ?connectors, "Started");
+        }
+
+        PlaylistAction::Stop => {
+            if !cursors.iter().any(targets) {
+                return Err(not_running(connectors));
+            }
+            if connectors.is_empty() {
+                cursors.clear();
+            } else {
+                detach(cursors, connectors);
+            }
+            info!(target: "playlist", connectors = ?connectors, "Stopped");
+        }
+
+        PlaylistAction::Next | PlaylistAction::Prev => {
+            let forward = matches!(action, PlaylistAction::Next);
+            let mut stepped = false;
+
+            // A shared cursor moves every monitor it drives, not just the one asked about
+            for cursor in cursors.iter_mut().filter(|c| targets(c)) {
+                let index = if forward {
+                    Some(cursor.advance(rng))
+                } else {
+                    cursor.retreat()
+                };
+                if let Some(index) = index {
+                    cursor.show(index, tx);
+                    stepped = true;
+                }
+            }
+
+            if !stepped {
+                return Err(if forward || !cursors.iter().any(targets) {
+                    not_running(connectors)
+                } else {
+                    "Already at the start of the playlist's history".to_string()
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn detach(cursors: &mut Vec<Cursor>, connectors: &[String]) {
+    for cursor in cursors.iter_mut() {
+        cursor.connectors.retain(|c| !connectors.contains(c));
+    }
+    cursors.retain(|c| !c.connectors.is_empty());
+}
+
+fn not_running(connectors: &[String]) -> String {
+    if connectors.is_empty() {
+        "No playlist is running".to_string()
+    } else {
+        format!("No playlist is running on {}", connectors.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(weights: &[u32], shuffle: bool) -> Cursor {
+        let entries = weights
+            .iter()
+            .enumerate()
+            .map(|(i, &weight)| PlaylistEntry::Item {
+                wallpaper: Some(format!("w{i}")),
+                path: None,
+                url: None,
+                weight,
+                properties: Default::default(),
+            })
+            .collect();
+        let playlist = Playlist {
+            interval: 60,
+            shuffle,
+            cursor: CursorMode::PerMonitor,
+            entries,
+        };
+        Cursor::new("test", Arc::new(playlist), vec!["DP-1".to_string()])
+    }
+
+    #[test]
+    fn interval_is_bounded() {
+        let mut playlist = Playlist::clone(&cursor(&[u32::MAX], false).playlist);
+        assert!(validate(&playlist).is_ok());
+        playlist.interval = MAX_INTERVAL + 1;
+        assert!(validate(&playlist).is_err());
+    }
+
+    #[test]
+    fn shuffled_rounds_draw_each_entry_by_its_weight() {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        let mut cursor = cursor(&[1, 3, 2], true);
+
+        for _ in 0..20 {
+            let mut counts = [0; 3];
+            for _ in 0..6 {
+                counts[cursor.advance(&mut rng)] += 1;
+            }
+            assert_eq!(counts, [1, 3, 2]);
+        }
+    }
+
+    #[test]
+    fn shuffle_never_repeats_while_others_are_left() {
+        let mut rng = Rng(12345);
+        let mut cursor = cursor(&[1, 1, 1], true);
+
+        let mut last = cursor.advance(&mut rng);
+        for _ in 0..300 {
+            let index = cursor.advance(&mut rng);
+            assert_ne!(index, last);
+            last = index;
+        }
+    }
+
+    #[test]
+    fn a_single_entry_repeats() {
+        let mut rng = Rng(1);
+        let mut cursor = cursor(&[2], true);
+        assert_eq!(cursor.advance(&mut rng), 0);
+        assert_eq!(cursor.advance(&mut rng), 0);
+    }
+
+    #[test]
+    fn prev_goes_back_and_next_replays_forward() {
+        let mut rng = Rng(1);
+        let mut cursor = cursor(&[1, 1, 1, 1], false);
+        assert_eq!(cursor.retreat(), None);
+
+        for expected in [0, 1, 2] {
+            assert_eq!(cursor.advance(&mut rng), expected);
+        }
+        assert_eq!(cursor.retreat(), Some(1));
+        assert_eq!(cursor.retreat(), Some(0));
+        assert_eq!(cursor.retreat(), None);
+
+        assert_eq!(cursor.advance(&mut rng), 1);
+        assert_eq!(cursor.advance(&mut rng), 2);
+        assert_eq!(cursor.advance(&mut rng), 3);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut rng = Rng(1);
+        let mut cursor = cursor(&[1, 1], false);
+        for _ in 0..HISTORY * 2 {
+            cursor.advance(&mut rng);
+        }
+        assert_eq!(cursor.history.len(), HISTORY);
+
+        let mut steps = 0;
+        while cursor.retreat().is_some() {
+            steps += 1;
+        }
+        assert_eq!(steps, HISTORY - 1);
+    }
+}
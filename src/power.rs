@@ -0,0 +1,293 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, info};
+
+use crate::config::Config;
+use crate::event::{PowerMode, UiCmd};
+
+const SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+// Set to low-power by power-profiles-daemon and the like, on machines that have it
+const PLATFORM_PROFILE: &str = "/sys/firmware/acpi/platform_profile";
+
+// For on = "low" rules that leave out below
+pub const DEFAULT_LOW_CHARGE: u8 = 20;
+
+// sysfs can't be watched for these, so they are read this often
+const POLL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Battery,
+    // On battery, with less charge than this percentage left
+    Low(u8),
+    PowerSaver,
+}
+
+// Later rules win where several match
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub on: Condition,
+    pub mode: PowerMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Supply {
+    pub on_battery: bool,
+    // The mean over every system battery, None without one
+    pub charge: Option<u8>,
+    pub power_saver: bool,
+}
+
+impl Condition {
+    fn matches(self, supply: &Supply) -> bool {
+        match self {
+            Condition::Battery => supply.on_battery,
+            Condition::Low(below) => supply.on_battery && supply.charge.is_some_and(|c| c < below),
+            Condition::PowerSaver => supply.power_saver,
+        }
+    }
+}
+
+pub fn mode(rules: &[Rule], supply: &Supply) -> PowerMode {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| rule.on.matches(supply))
+        .map(|rule| rule.mode.clone())
+        .unwrap_or_default()
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+// Peripherals like mice report their batteries here too, with a scope of Device
+pub fn read(supply_dir: &Path, profile: &Path) -> Supply {
+    let mut ac_online = false;
+    let mut discharging = false;
+    let mut charges: Vec<u32> = Vec::new();
+
+    for entry in fs::read_dir(supply_dir).into_iter().flatten().flatten() {
+        let dir = entry.path();
+        match read_trimmed(&dir.join("type")).as_deref() {
+            Some("Mains" | "USB") => {
+                ac_online |= read_trimmed(&dir.join("online")).as_deref() == Some("1");
+            }
+            Some("Battery") => {
+                if read_trimmed(&dir.join("scope")).as_deref() == Some("Device") {
+                    continue;
+                }
+                discharging |= read_trimmed(&dir.join("status")).as_deref() == Some("Discharging");
+                if let Some(capacity) =
+                    read_trimmed(&dir.join("capacity")).and_then(|c| c.parse().ok())
+                {
+                    charges.push(capacity);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let charge = (!charges.is_empty())
+        .then(|| (charges.iter().sum::<u32>() / charges.len() as u32).min(100) as u8);
+
+    Supply {
+        on_battery: discharging && !ac_online,
+        charge,
+        power_saver: read_trimmed(profile).as_deref() == Some("low-power"),
+    }
+}
+
+// Tells the UI how to save power whenever the supply or the rules change, and keeps
+// power_tx up to date for GetState. Back on AC the mode is Normal again, and the UI
+// shows the wallpapers as they were
+pub async fn power_manager(
+    ui_tx: mpsc::UnboundedSender<UiCmd>,
+    mut config_rx: watch::Receiver<Arc<Config>>,
+    power_tx: watch::Sender<PowerMode>,
+) {
+    let mut current = PowerMode::Normal;
+
+    loop {
+        let rules = config_rx.borrow_and_update().power.clone();
+        let mode = if rules.is_empty() {
+            PowerMode::Normal
+        } else {
+            let supply = read(Path::new(SUPPLY_DIR), Path::new(PLATFORM_PROFILE));
+            debug!(target: "power", supply = ?supply, "Read power supply");
+            mode(&rules, &supply)
+        };
+
+        if mode != current {
+            info!(target: "power", mode = ?mode, "Power mode changed");
+            let _ = ui_tx.send(UiCmd::SetPower(mode.clone()));
+            let _ = power_tx.send(mode.clone());
+            current = mode;
+        }
+
+        tokio::select! {
+            changed = config_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+            }
+            _ = tokio::time::sleep(POLL) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // A sysfs-like tree, each supply a list of (file, contents)
+    fn sysfs(
+        test: &str,
+        supplies: &[(&str, &[(&str, &str)])],
+        profile: &str,
+    ) -> (PathBuf, PathBuf) {
+        let base =
+            std::env::temp_dir().join(format!("maypaper-power-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let supply_dir = base.join("power_supply");
+        for (name, files) in supplies {
+            let dir = supply_dir.join(name);
+            fs::create_dir_all(&dir).unwrap();
+            for (file, contents) in *files {
+                fs::write(dir.join(file), format!("{contents}\n")).unwrap();
+            }
+        }
+        fs::create_dir_all(&supply_dir).unwrap();
+        let profile_file = base.join("platform_profile");
+        fs::write(&profile_file, format!("{profile}\n")).unwrap();
+        (supply_dir, profile_file)
+    }
+
+    const AC_OFFLINE: &[(&str, &str)] = &[("type", "Mains"), ("online", "0")];
+
+    #[test]
+    fn batteries_are_averaged_and_peripherals_left_out() {
+        let (supply_dir, profile) = sysfs(
+            "average",
+            &[
+                ("AC", AC_OFFLINE),
+                (
+                    "BAT0",
+                    &[
+                        ("type", "Battery"),
+                        ("status", "Discharging"),
+                        ("capacity", "30"),
+                    ],
+                ),
+                (
+                    "BAT1",
+                    &[
+                        ("type", "Battery"),
+                        ("status", "Unknown"),
+                        ("capacity", "50"),
+                    ],
+                ),
+                (
+                    "hidpp_battery_0",
+                    &[
+                        ("type", "Battery"),
+                        ("scope", "Device"),
+                        ("status", "Discharging"),
+                        ("capacity", "5"),
+                    ],
+                ),
+            ],
+            "balanced",
+        );
+        assert_eq!(
+            read(&supply_dir, &profile),
+            Supply {
+                on_battery: true,
+                charge: Some(40),
+                power_saver: false,
+            }
+        );
+    }
+
+    #[test]
+    fn mains_online_is_not_on_battery() {
+        let (supply_dir, profile) = sysfs(
+            "mains",
+            &[
+                ("AC", &[("type", "Mains"), ("online", "1")]),
+                (
+                    "BAT0",
+                    &[
+                        ("type", "Battery"),
+                        ("status", "Discharging"),
+                        ("capacity", "90"),
+                    ],
+                ),
+            ],
+            "low-power",
+        );
+        let supply = read(&supply_dir, &profile);
+        assert!(!supply.on_battery);
+        assert!(supply.power_saver);
+    }
+
+    #[test]
+    fn missing_files_read_as_a_desktop() {
+        let base =
+            std::env::temp_dir().join(format!("maypaper-power-missing-{}", std::process::id()));
+        assert_eq!(
+            read(&base.join("power_supply"), &base.join("platform_profile")),
+            Supply::default()
+        );
+    }
+
+    #[test]
+    fn the_last_matching_rule_wins() {
+        let rules = [
+            Rule {
+                on: Condition::Battery,
+                mode: PowerMode::FrameRate { fps: 30 },
+            },
+            Rule {
+                on: Condition::Low(DEFAULT_LOW_CHARGE),
+                mode: PowerMode::Pause,
+            },
+            Rule {
+                on: Condition::PowerSaver,
+                mode: PowerMode::FrameRate { fps: 10 },
+            },
+        ];
+        let supply = |on_battery, charge, power_saver| Supply {
+            on_battery,
+            charge,
+            power_saver,
+        };
+
+        assert_eq!(
+            mode(&rules, &supply(false, Some(10), false)),
+            PowerMode::Normal
+        );
+        assert_eq!(
+            mode(&rules, &supply(true, Some(50), false)),
+            PowerMode::FrameRate { fps: 30 }
+        );
+        assert_eq!(
+            mode(&rules, &supply(true, Some(10), false)),
+            PowerMode::Pause
+        );
+        assert_eq!(
+            mode(&rules, &supply(true, None, false)),
+            PowerMode::FrameRate { fps: 30 }
+        );
+        assert_eq!(
+            mode(&rules, &supply(true, Some(10), true)),
+            PowerMode::FrameRate { fps: 10 }
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// The first reload after a crash, doubled with every crash in a row
+const FIRST_RELOAD: Duration = Duration::from_secs(1);
+const MAX_RELOAD: Duration = Duration::from_secs(60);
+
+// A page up for this long since its last crash starts over from the first reload
+const STABLE: Duration = Duration::from_secs(120);
+
+#[derive(Debug)]
+struct Crashes {
+    count: u32,
+    last: Instant,
+    // The fallback is only shown once per run of crashes, in case it crashes too
+    fell_back: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crash {
+    // In a row, this one included
+    pub count: u32,
+    pub reload_in: Duration,
+    // Past the limit, so the fallback should be shown instead of reloading
+    pub fall_back: bool,
+}
+
+// Crashes in a row of each monitor's renderer
+#[derive(Debug, Default)]
+pub struct Recovery {
+    monitors: HashMap<String, Crashes>,
+}
+
+impl Recovery {
+    // Counts the crash, and says what to do about it. limit is [fallback] crashes, if any
+    pub fn crashed(&mut self, connector: &str, limit: Option<u32>) -> Crash {
+        self.crashed_at(connector, limit, Instant::now())
+    }
+
+    fn crashed_at(&mut self, connector: &str, limit: Option<u32>, now: Instant) -> Crash {
+        let crashes = self
+            .monitors
+            .entry(connector.to_string())
+            .or_insert(Crashes {
+                count: 0,
+                last: now,
+                fell_back: false,
+            });
+        if now.duration_since(crashes.last) >= STABLE {
+            crashes.count = 0;
+            crashes.fell_back = false;
+        }
+        crashes.count += 1;
+        crashes.last = now;
+
+        let fall_back = !crashes.fell_back && limit.is_some_and(|limit| crashes.count > limit);
+        crashes.fell_back |= fall_back;
+
+        let doublings = (crashes.count - 1).min(16);
+        Crash {
+            count: crashes.count,
+            reload_in: FIRST_RELOAD.saturating_mul(1 << doublings).min(MAX_RELOAD),
+            fall_back,
+        }
+    }
+
+    // The monitor is gone, it starts over if it comes back
+    pub fn forget(&mut self, connector: &str) {
+        self.monitors.remove(connector);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reloads(recovery: &mut Recovery, crashes: usize, now: Instant) -> Vec<u64> {
+        (0..crashes)
+            .map(|_| recovery.crashed_at("DP-1", None, now).reload_in.as_secs())
+            .collect()
+    }
+
+    #[test]
+    fn reloads_back_off_up_to_a_minute() {
+        let mut recovery = Recovery::default();
+        let now = Instant::now();
+        assert_eq!(
+            reloads(&mut recovery, 9, now),
+            [1, 2, 4, 8, 16, 32, 60, 60, 60]
+        );
+        // Far past where the doubling would overflow
+        assert_eq!(reloads(&mut recovery, 40, now).last(), Some(&60));
+    }
+
+    #[test]
+    fn stable_page_starts_over() {
+        let mut recovery = Recovery::default();
+        let now = Instant::now();
+        reloads(&mut recovery, 3, now);
+
+        let crash = recovery.crashed_at("DP-1", None, now + STABLE - Duration::from_secs(1));
+        assert_eq!(crash.count, 4);
+        let crash = recovery.crashed_at("DP-1", None, now + STABLE * 2);
+        assert_eq!(crash.count, 1);
+        assert_eq!(crash.reload_in, FIRST_RELOAD);
+    }
+
+    #[test]
+    fn falls_back_once_past_the_limit() {
+        let mut recovery = Recovery::default();
+        let now = Instant::now();
+        let fell_back: Vec<bool> = (0..5)
+            .map(|_| recovery.crashed_at("DP-1", Some(2), now).fall_back)
+            .collect();
+        assert_eq!(fell_back, [false, false, true, false, false]);
+
+        // Other monitors count their own, and without a limit there's nothing to fall back to
+        assert!(recovery.crashed_at("DP-2", Some(0), now).fall_back);
+        assert!((0..5).all(|_| !recovery.crashed_at("DP-3", None, now).fall_back));
+
+        // A new run of crashes may fall back again
+        let later = now + STABLE;
+        let fell_back: Vec<bool> = (0..3)
+            .map(|_| recovery.crashed_at("DP-1", Some(2), later).fall_back)
+            .collect();
+        assert_eq!(fell_back, [false, false, true]);
+    }
+
+    #[test]
+    fn forgotten_monitor_starts_over() {
+        let mut recovery = Recovery::default();
+        let now = Instant::now();
+        reloads(&mut recovery, 3, now);
+        recovery.forget("DP-1");
+        assert_eq!(recovery.crashed_at("DP-1", None, now).count, 1);
+    }
+}
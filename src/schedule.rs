@@ -0,0 +1,710 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, info, warn};
+
+use crate::config::{Config, Target};
+use crate::event::{Ipc, IpcReply, SyncData, TokioEvent, Transition};
+use crate::ipc;
+
+// Checked at least this often, the clock can jump on suspend or a timezone change
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+// How far back a window's start, or ahead its end, is looked for. Enough for yearly cron rules
+const SEARCH_DAYS: i64 = 366;
+
+// How far ahead `mypctl schedule` looks
+const UPCOMING_DAYS: i64 = 7;
+
+// Sunrise and sunset are where the sun's upper edge crosses the horizon, refraction included
+const SUN_ALTITUDE: f64 = -0.833;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Location {
+    pub latitude: f64,
+    // East is positive
+    pub longitude: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schedule {
+    pub location: Option<Location>,
+    // Later rules win where windows overlap
+    pub rules: Vec<Rule>,
+}
+
+// Shows target on monitor, or every monitor, from each `from` until the `to` after it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub from: When,
+    pub to: When,
+    // A connector, aliases already resolved
+    pub monitor: Option<String>,
+    pub target: Target,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SunEvent {
+    Sunrise,
+    Sunset,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum When {
+    // Every day at this local minute
+    Daily(u32),
+    // Every day, offset by this many minutes
+    Sun(SunEvent, i64),
+    Cron(Cron),
+}
+
+impl When {
+    // "HH:MM", "sunrise" or "sunset" with an optional offset like "sunset-1h30m",
+    // or five cron fields: minute hour day-of-month month day-of-week
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+
+        if s.split_whitespace().count() == 5 {
+            return Cron::parse(s).map(When::Cron);
+        }
+
+        for (name, event) in [("sunrise", SunEvent::Sunrise), ("sunset", SunEvent::Sunset)] {
+            if let Some(rest) = s.strip_prefix(name) {
+                return Ok(When::Sun(event, parse_offset(rest)?));
+            }
+        }
+
+        let (hour, minute) = s.split_once(':').with_context(|| {
+            format!("expected HH:MM, sunrise, sunset or a cron expression, got {s:?}")
+        })?;
+        let hour: u32 = hour.parse().with_context(|| format!("bad hour in {s:?}"))?;
+        let minute: u32 = minute
+            .parse()
+            .with_context(|| format!("bad minute in {s:?}"))?;
+        if hour > 23 || minute > 59 {
+            bail!("{s:?} is not a time of day");
+        }
+        Ok(When::Daily(hour * 60 + minute))
+    }
+
+    pub fn needs_location(&self) -> bool {
+        matches!(self, When::Sun(..))
+    }
+
+    // Every time this happens on a local date, in order
+    fn on(&self, date: Date, location: Option<Location>) -> Vec<i64> {
+        match self {
+            When::Daily(minute) => vec![local_time(date, *minute)],
+            When::Sun(event, offset) => {
+                let Some((rise, set)) = location.and_then(|l| sun_times(date, l)) else {
+                    // No sunrise or sunset near the poles for part of the year
+                    return Vec::new();
+                };
+                let at = match event {
+                    SunEvent::Sunrise => rise,
+                    SunEvent::Sunset => set,
+                };
+                vec![at + offset * 60]
+            }
+            When::Cron(cron) => cron.on(date),
+        }
+    }
+
+    fn last_at_or_before(&self, t: i64, location: Option<Location>) -> Option<i64> {
+        let (today, _) = local_date(t);
+        (0..=SEARCH_DAYS).find_map(|back| {
+            self.on(today.add_days(-back), location)
+                .into_iter()
+                .filter(|&at| at <= t)
+                .max()
+        })
+    }
+
+    fn next_after(&self, t: i64, location: Option<Location>) -> Option<i64> {
+        let (today, _) = local_date(t);
+        (0..=SEARCH_DAYS).find_map(|ahead| {
+            self.on(today.add_days(ahead), location)
+                .into_iter()
+                .filter(|&at| at > t)
+                .min()
+        })
+    }
+}
+
+fn parse_offset(s: &str) -> Result<i64> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Ok(0);
+    }
+
+    let (sign, mut rest) = if let Some(rest) = s.strip_prefix('+') {
+        (1, rest.trim())
+    } else if let Some(rest) = s.strip_prefix('-') {
+        (-1, rest.trim())
+    } else {
+        bail!("expected an offset like +30m or -1h, got {s:?}");
+    };
+
+    let mut minutes: i64 = 0;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let value: i64 = rest[..digits]
+            .parse()
+            .with_context(|| format!("bad offset {s:?}"))?;
+        let value = match rest[digits..].chars().next() {
+            Some('h') => value.checked_mul(60),
+            Some('m') => Some(value),
+            _ => bail!("offsets are in h and m, got {s:?}"),
+        };
+        minutes = value
+            .and_then(|v| minutes.checked_add(v))
+            .with_context(|| format!("offset {s:?} is too large"))?;
+        rest = &rest[digits + 1..];
+    }
+
+    Ok(sign * minutes)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // Cron matches either day field when both are restricted
+    any_day: bool,
+}
+
+impl Cron {
+    fn parse(s: &str) -> Result<Self> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            bail!("cron expressions have five fields, got {s:?}");
+        };
+
+        let mut weekdays =
+            cron_field(weekday, 0, 7).with_context(|| format!("bad day of week in {s:?}"))?;
+        // 7 is also Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+
+        Ok(Self {
+            minutes: cron_field(minute, 0, 59).with_context(|| format!("bad minute in {s:?}"))?,
+            hours: cron_field(hour, 0, 23).with_context(|| format!("bad hour in {s:?}"))?,
+            days: cron_field(day, 1, 31).with_context(|| format!("bad day of month in {s:?}"))?,
+            months: cron_field(month, 1, 12).with_context(|| format!("bad month in {s:?}"))?,
+            weekdays,
+            any_day: !day.starts_with('*') && !weekday.starts_with('*'),
+        })
+    }
+
+    fn on(&self, date: Date) -> Vec<i64> {
+        let day = self.days & (1 << date.day) != 0;
+        let weekday = self.weekdays & (1 << date.weekday()) != 0;
+        let matches = if self.any_day {
+            day || weekday
+        } else {
+            day && weekday
+        };
+        if self.months & (1 << date.month) == 0 || !matches {
+            return Vec::new();
+        }
+
+        let mut times = Vec::new();
+        for hour in (0..24).filter(|h| self.hours & (1 << h) != 0) {
+            for minute in (0..60).filter(|m| self.minutes & (1 << m) != 0) {
+                times.push(local_time(date, hour * 60 + minute));
+            }
+        }
+        times
+    }
+}
+
+// A comma separated list of *, N, N-M, each optionally with /STEP
+fn cron_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut bits = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().context("bad step")?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            bail!("step can't be 0");
+        }
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (a.parse()?, b.parse()?),
+                None => {
+                    let n = range.parse()?;
+                    // N/STEP runs to the end of the range, like */STEP from N
+                    (n, if part.contains('/') { max } else { n })
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            bail!("{range} is outside {min}-{max}");
+        }
+
+        for n in (start..=end).step_by(step as usize) {
+            bits |= 1 << n;
+        }
+    }
+
+    Ok(bits)
+}
+
+impl Schedule {
+    pub fn active(&self, connector: &str, t: i64) -> Option<&Rule> {
+        self.rules
+            .iter()
+            .rev()
+            .filter(|r| r.monitor.as_deref().is_none_or(|m| m == connector))
+            .find(|r| r.active(t, self.location))
+    }
+
+    fn next_change(&self, t: i64) -> Option<i64> {
+        self.rules
+            .iter()
+            .flat_map(|r| [&r.from, &r.to])
+            .filter_map(|w| w.next_after(t, self.location))
+            .min()
+    }
+
+    // Every window start and end in the next few days
+    pub fn upcoming(&self, t: i64) -> Vec<Transition> {
+        let end = t + UPCOMING_DAYS * 86400;
+        let (today, _) = local_date(t);
+
+        let mut transitions = Vec::new();
+        for rule in &self.rules {
+            for (when, starts) in [(&rule.from, true), (&rule.to, false)] {
+                for day in 0..=UPCOMING_DAYS {
+                    for at in when.on(today.add_days(day), self.location) {
+                        if at <= t || at > end {
+                            continue;
+                        }
+                        transitions.push(Transition {
+                            at,
+                            local: format_local(at),
+                            monitor: rule.monitor.clone(),
+                            starts,
+                            target: rule.target.describe(),
+                        });
+                    }
+                }
+            }
+        }
+
+        transitions.sort_by_key(|t| t.at);
+        transitions
+    }
+}
+
+impl Rule {
+    fn active(&self, t: i64, location: Option<Location>) -> bool {
+        let Some(start) = self.from.last_at_or_before(t, location) else {
+            return false;
+        };
+        // A window that never ends stays on
+        self.to
+            .next_after(start, location)
+            .is_none_or(|end| end > t)
+    }
+}
+
+/*
+* Dates and local time
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Date {
+    year: i64,
+    month: u32,
+    day: u32,
+}
+
+impl Date {
+    // Days since 1970-01-01, from Howard Hinnant's date algorithms
+    fn days(self) -> i64 {
+        let year = if self.month <= 2 {
+            self.year - 1
+        } else {
+            self.year
+        };
+        let era = year.div_euclid(400);
+        let yoe = year - era * 400;
+        let month = i64::from(self.month);
+        let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5
+            + i64::from(self.day)
+            - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146097 + doe - 719468
+    }
+
+    fn from_days(days: i64) -> Self {
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        Self { year, month, day }
+    }
+
+    fn add_days(self, n: i64) -> Self {
+        Self::from_days(self.days() + n)
+    }
+
+    // 0 is Sunday, like cron
+    fn weekday(self) -> u32 {
+        (self.days() + 4).rem_euclid(7) as u32
+    }
+}
+
+// The local date, and minute of that day, at a unix time
+fn local_date(t: i64) -> (Date, u32) {
+    // SAFETY: localtime_r only writes to the tm it is given
+    let tm = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&(t as libc::time_t), &mut tm);
+        tm
+    };
+    let date = Date {
+        year: i64::from(tm.tm_year) + 1900,
+        month: (tm.tm_mon + 1) as u32,
+        day: tm.tm_mday as u32,
+    };
+    (date, (tm.tm_hour * 60 + tm.tm_min) as u32)
+}
+
+// Times skipped by a DST change land just after it, as mktime normalizes them
+fn local_time(date: Date, minute: u32) -> i64 {
+    // SAFETY: mktime only reads and normalizes the tm it is given
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        tm.tm_year = (date.year - 1900) as libc::c_int;
+        tm.tm_mon = date.month as libc::c_int - 1;
+        tm.tm_mday = date.day as libc::c_int;
+        tm.tm_hour = (minute / 60) as libc::c_int;
+        tm.tm_min = (minute % 60) as libc::c_int;
+        tm.tm_isdst = -1;
+        libc::mktime(&mut tm) as i64
+    }
+}
+
+fn format_local(t: i64) -> String {
+    let (date, minute) = local_date(t);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        date.year,
+        date.month,
+        date.day,
+        minute / 60,
+        minute % 60
+    )
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// The sunrise equation, good to a minute or two away from the poles.
+// Returns None on days the sun doesn't rise or doesn't set
+fn sun_times(date: Date, location: Location) -> Option<(i64, i64)> {
+    let rad = f64::to_radians;
+
+    // Days since J2000, at the location's solar noon
+    let n = date.days() as f64 - 10957.0;
+    let mean_noon = n - location.longitude / 360.0;
+
+    let anomaly = (357.5291 + 0.98560028 * mean_noon).rem_euclid(360.0);
+    let center = 1.9148 * rad(anomaly).sin()
+        + 0.0200 * rad(2.0 * anomaly).sin()
+        + 0.0003 * rad(3.0 * anomaly).sin();
+    let longitude = (anomaly + center + 180.0 + 102.9372).rem_euclid(360.0);
+    let transit = mean_noon + 0.0053 * rad(anomaly).sin() - 0.0069 * rad(2.0 * longitude).sin();
+
+    let declination = (rad(longitude).sin() * rad(23.4397).sin()).asin();
+    let latitude = rad(location.latitude);
+    let cos_hour = (rad(SUN_ALTITUDE).sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour) {
+        return None;
+    }
+    let hour = cos_hour.acos().to_degrees() / 360.0;
+
+    // Days since J2000 noon to unix seconds
+    let unix = |days: f64| ((days + 10957.5) * 86400.0).round() as i64;
+    Some((unix(transit - hour), unix(transit + hour)))
+}
+
+/*
+* Scheduler
+*/
+
+// Follows the schedule of the current config, showing each monitor what its active rule names,
+// and its default once no rule is. Startup picks the active rules itself, see start_tokio
+pub async fn scheduler(
+    tx: mpsc::UnboundedSender<TokioEvent>,
+    mut config_rx: watch::Receiver<Arc<Config>>,
+    mut synx_rx: watch::Receiver<Arc<SyncData>>,
+) {
+    // What the schedule last showed on each monitor it has seen
+    let mut applied: HashMap<String, Option<Rule>> = HashMap::new();
+
+    loop {
+        let config = config_rx.borrow_and_update().clone();
+        let connectors = synx_rx.borrow_and_update().connectors.clone();
+        let t = now();
+
+        for connector in connectors {
+            let active = config.schedule.active(&connector, t).cloned();
+            match applied.get(&connector) {
+                Some(before) if *before != active => {
+                    apply(&config, &connector, before.as_ref(), active.as_ref(), &tx);
+                }
+                _ => {}
+            }
+            applied.insert(connector, active);
+        }
+
+        let wait = config
+            .schedule
+            .next_change(t)
+            .map(|at| Duration::from_secs((at - t).max(0) as u64))
+            .unwrap_or(MAX_SLEEP)
+            .min(MAX_SLEEP);
+        debug!(target: "schedule", wait = ?wait, "Sleeping");
+
+        tokio::select! {
+            changed = config_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+            }
+            changed = synx_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+            }
+            _ = tokio::time::sleep(wait) => {}
+        }
+    }
+}
+
+fn apply(
+    config: &Config,
+    connector: &str,
+    before: Option<&Rule>,
+    active: Option<&Rule>,
+    tx: &mpsc::UnboundedSender<TokioEvent>,
+) {
+    let monitor = Some(connector.to_string());
+    let target = match active {
+        Some(rule) => Some(&rule.target),
+        None => config.monitors.get(connector),
+    };
+    info!(target: "schedule", connector, target = ?target.map(Target::describe), "Window changed");
+
+    let mut msgs = Vec::new();
+    // A playlist the last window started would otherwise carry on over the new wallpaper
+    if before.is_some_and(|r| r.target.playlist.is_some())
+        && target.is_none_or(|t| t.playlist.is_none())
+    {
+        msgs.push(Ipc::StopPlaylist {
+            monitor: monitor.clone(),
+        });
+    }
+    if let Some(target) = target {
+        msgs.push(target.to_ipc(monitor));
+    }
+
+    // Through the IPC path, in order, so the stop lands before the new wallpaper
+    let tx = tx.clone();
+    let connector = connector.to_string();
+    tokio::spawn(async move {
+        for msg in msgs {
+            let kind = msg.kind();
+            if let IpcReply::Error { message } = ipc::handle_msg(msg, &tx).await {
+                warn!(target: "schedule", connector = %connector, kind, error = %message, "Failed to apply");
+            }
+        }
+    });
+}
+
+pub fn upcoming(config: &Config) -> Vec<Transition> {
+    config.schedule.upcoming(now())
+}
+
+pub fn active(config: &Config, connector: &str) -> Option<Ipc> {
+    let rule = config.schedule.active(connector, now())?;
+    Some(rule.target.to_ipc(Some(connector.to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONDON: Location = Location {
+        latitude: 51.5074,
+        longitude: -0.1278,
+    };
+
+    fn date(year: i64, month: u32, day: u32) -> Date {
+        Date { year, month, day }
+    }
+
+    fn rule(from: When, to: When) -> Rule {
+        Rule {
+            from,
+            to,
+            monitor: None,
+            target: Target {
+                wallpaper: Some("night".to_string()),
+                path: None,
+                url: None,
+                playlist: None,
+                properties: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn cron_fields_cover_lists_ranges_and_steps() {
+        let bits = |ns: &[u32]| ns.iter().fold(0u64, |b, n| b | 1 << n);
+        assert_eq!(cron_field("*/15", 0, 59).unwrap(), bits(&[0, 15, 30, 45]));
+        assert_eq!(cron_field("1-3,7", 0, 59).unwrap(), bits(&[1, 2, 3, 7]));
+        assert_eq!(cron_field("10/20", 0, 59).unwrap(), bits(&[10, 30, 50]));
+        assert_eq!(
+            cron_field("*", 1, 12).unwrap(),
+            bits(&(1..=12).collect::<Vec<_>>())
+        );
+
+        for bad in ["0", "5-1", "*/0", "32", "x", "1-"] {
+            assert!(cron_field(bad, 1, 31).is_err(), "{bad} was accepted");
+        }
+    }
+
+    #[test]
+    fn cron_sunday_is_0_or_7() {
+        let Ok(When::Cron(cron)) = When::parse("0 9 * * 7") else {
+            panic!("not a cron expression");
+        };
+        // 2024-06-02 is a Sunday
+        assert_eq!(
+            cron.on(date(2024, 6, 2)),
+            vec![local_time(date(2024, 6, 2), 540)]
+        );
+        assert!(cron.on(date(2024, 6, 3)).is_empty());
+    }
+
+    #[test]
+    fn cron_day_fields_are_or_when_both_restricted() {
+        let matches = |expr: &str, d: Date| !Cron::parse(expr).unwrap().on(d).is_empty();
+        // A Saturday the 1st, a Monday the 3rd and a Tuesday the 4th
+        let (first, monday, tuesday) = (date(2024, 6, 1), date(2024, 6, 3), date(2024, 6, 4));
+
+        assert!(matches("0 12 1 * 1", first));
+        assert!(matches("0 12 1 * 1", monday));
+        assert!(!matches("0 12 1 * 1", tuesday));
+
+        assert!(matches("0 12 1 * *", first));
+        assert!(!matches("0 12 1 * *", monday));
+        assert!(!matches("0 12 * * 1", first));
+        assert!(matches("0 12 * * 1", monday));
+    }
+
+    #[test]
+    fn offsets_are_signed_hours_and_minutes() {
+        assert_eq!(parse_offset("").unwrap(), 0);
+        assert_eq!(parse_offset("+30m").unwrap(), 30);
+        assert_eq!(parse_offset("-1h30m").unwrap(), -90);
+        assert_eq!(parse_offset(" - 2h").unwrap(), -120);
+
+        // U+2212, the minus sign, isn't taken for a hyphen
+        assert!(parse_offset("\u{2212}1h").is_err());
+        assert!(parse_offset("1h").is_err());
+        assert!(parse_offset("+1d").is_err());
+        assert!(parse_offset("+h").is_err());
+        assert!(parse_offset("+999999999999999999h").is_err());
+        assert!(When::parse("sunset \u{2212}1h").is_err());
+    }
+
+    #[test]
+    fn sun_times_match_published_tables() {
+        let close = |at: i64, expected: i64| (at - expected).abs() <= 3 * 60;
+
+        // London at midsummer, 03:43 and 20:21 UTC
+        let (rise, set) = sun_times(date(2024, 6, 21), LONDON).unwrap();
+        assert!(close(rise, 1718941380), "sunrise at {rise}");
+        assert!(close(set, 1719001260), "sunset at {set}");
+
+        // New York at midwinter, 12:16 and 21:32 UTC
+        let new_york = Location {
+            latitude: 40.7128,
+            longitude: -74.0060,
+        };
+        let (rise, set) = sun_times(date(2024, 12, 21), new_york).unwrap();
+        assert!(close(rise, 1734783360), "sunrise at {rise}");
+        assert!(close(set, 1734816720), "sunset at {set}");
+    }
+
+    #[test]
+    fn no_sun_times_during_the_midnight_sun() {
+        let tromso = Location {
+            latitude: 69.6492,
+            longitude: 18.9553,
+        };
+        assert_eq!(sun_times(date(2024, 6, 21), tromso), None);
+        assert!(sun_times(date(2024, 3, 20), tromso).is_some());
+    }
+
+    #[test]
+    fn windows_run_across_midnight() {
+        let night = rule(When::Daily(22 * 60), When::Daily(6 * 60));
+        let day = date(2024, 1, 15);
+        let at = |d: Date, minute: u32| local_time(d, minute);
+
+        assert!(!night.active(at(day, 21 * 60 + 59), None));
+        assert!(night.active(at(day, 22 * 60), None));
+        assert!(night.active(at(day, 23 * 60 + 30), None));
+        assert!(night.active(at(day.add_days(1), 60), None));
+        assert!(!night.active(at(day.add_days(1), 6 * 60), None));
+        assert!(!night.active(at(day.add_days(1), 12 * 60), None));
+    }
+
+    #[test]
+    fn later_rules_win_where_windows_overlap() {
+        let mut evening = rule(When::Daily(18 * 60), When::Daily(23 * 60));
+        evening.monitor = Some("DP-1".to_string());
+        let schedule = Schedule {
+            location: None,
+            rules: vec![
+                rule(When::Daily(12 * 60), When::Daily(20 * 60)),
+                evening.clone(),
+            ],
+        };
+        let t = local_time(date(2024, 1, 15), 19 * 60);
+
+        assert_eq!(schedule.active("DP-1", t), Some(&evening));
+        assert_eq!(schedule.active("DP-2", t), Some(&schedule.rules[0]));
+    }
+}
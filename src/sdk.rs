@@ -0,0 +1,104 @@
+use std::fs;
+
+use axum::{
+    Json, Router,
+    extract::Path,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use rust_embed::Embed;
+use serde::Serialize;
+
+// Reserved on every local webserver, wallpapers can't serve their own files here
+pub const SDK_PREFIX: &str = "/_maypaper";
+
+#[derive(Embed)]
+#[folder = "sdk/"]
+struct Sdk;
+
+#[derive(Debug, Serialize)]
+struct Memory {
+    total: u64,
+    available: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct SystemInfo {
+    version: &'static str,
+    os: &'static str,
+    arch: &'static str,
+    hostname: Option<String>,
+    cpus: usize,
+    uptime: Option<f64>,
+    load: Option<[f64; 3]>,
+    memory: Option<Memory>,
+}
+
+pub fn sdk_routes<S: Clone + Send + Sync + 'static>() -> Router<S> {
+    Router::new().route(&format!("{SDK_PREFIX}/{{*file}}"), get(serve_sdk))
+}
+
+// Part of each site, so remote pages can be told less than local ones
+pub fn system_routes<S: Clone + Send + Sync + 'static>(hostname: bool) -> Router<S> {
+    Router::new().route(
+        "/api/system",
+        get(move || async move { Json(system_info(hostname)) }),
+    )
+}
+
+async fn serve_sdk(Path(file): Path<String>) -> Response {
+    let Some(asset) = Sdk::get(&file) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    // mime_guess takes .ts for an MPEG transport stream
+    let mime = if file.ends_with(".ts") {
+        "text/plain; charset=utf-8".to_string()
+    } else {
+        mime_guess::from_path(&file)
+            .first_or_octet_stream()
+            .to_string()
+    };
+
+    ([(header::CONTENT_TYPE, mime)], asset.data).into_response()
+}
+
+fn system_info(hostname: bool) -> SystemInfo {
+    let read = |path: &str| fs::read_to_string(path).ok();
+
+    let uptime = read("/proc/uptime").and_then(|s| s.split_whitespace().next()?.parse().ok());
+
+    let load = read("/proc/loadavg").and_then(|s| {
+        let mut fields = s.split_whitespace().map(|f| f.parse::<f64>().ok());
+        Some([fields.next()??, fields.next()??, fields.next()??])
+    });
+
+    // Values in /proc/meminfo are in kB
+    let memory = read("/proc/meminfo").and_then(|s| {
+        let field = |name: &str| {
+            s.lines()
+                .find_map(|l| l.strip_prefix(name)?.strip_prefix(':'))
+                .and_then(|v| v.split_whitespace().next()?.parse::<u64>().ok())
+                .map(|kb| kb * 1024)
+        };
+        Some(Memory {
+            total: field("MemTotal")?,
+            available: field("MemAvailable")?,
+        })
+    });
+
+    SystemInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        hostname: hostname
+            .then(|| read("/proc/sys/kernel/hostname"))
+            .flatten()
+            .map(|h| h.trim().to_string()),
+        cpus: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        uptime,
+        load,
+        memory,
+    }
+}
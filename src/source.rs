@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+
+pub const ENTRY_FILE: &str = "index.html";
+
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp", "avif", "bmp", "svg"];
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "webm", "mkv", "mov", "ogv", "m4v"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaKind {
+    Image,
+    Video,
+}
+
+impl MediaKind {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+            Some(MediaKind::Image)
+        } else if VIDEO_EXTENSIONS.contains(&extension.as_str()) {
+            Some(MediaKind::Video)
+        } else {
+            None
+        }
+    }
+
+    // The myptmp template the file gets wrapped in, and the placeholder it fills
+    pub fn template(self) -> (&'static str, &'static str) {
+        match self {
+            MediaKind::Image => ("image", "{IMAGE}"),
+            MediaKind::Video => ("video", "{VIDEO}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    TarZst,
+}
+
+// Everything a local wallpaper path can point at
+#[derive(Debug, Clone)]
+pub enum Source {
+    Dir(PathBuf),
+    // A directory of images and videos, with no entry file of its own
+    Slideshow(PathBuf),
+    Html { root: PathBuf, entry: PathBuf },
+    Media { file: PathBuf, kind: MediaKind },
+    Archive { file: PathBuf, kind: ArchiveKind },
+}
+
+impl Source {
+    pub fn classify(path: &Path) -> Result<Self> {
+        if path.is_dir() {
+            if path.join(ENTRY_FILE).is_file() {
+                return Ok(Source::Dir(path.to_path_buf()));
+            }
+            if !list_media(path)?.is_empty() {
+                return Ok(Source::Slideshow(path.to_path_buf()));
+            }
+            bail!(
+                "Wallpaper directory has no {ENTRY_FILE}, nor any images or videos: {}",
+                path.display()
+            );
+        }
+
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(str::to_ascii_lowercase)
+            .with_context(|| format!("Invalid wallpaper filename: {}", path.display()))?;
+
+        if name.ends_with(".zip") {
+            return Ok(Source::Archive {
+                file: path.to_path_buf(),
+                kind: ArchiveKind::Zip,
+            });
+        }
+        if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            return Ok(Source::Archive {
+                file: path.to_path_buf(),
+                kind: ArchiveKind::TarZst,
+            });
+        }
+
+        if name.ends_with(".html") || name.ends_with(".htm") {
+            let root = path
+                .parent()
+                .with_context(|| format!("Wallpaper file has no parent: {}", path.display()))?;
+            return Ok(Source::Html {
+                root: root.to_path_buf(),
+                entry: path.to_path_buf(),
+            });
+        }
+        if let Some(kind) = MediaKind::from_path(path) {
+            return Ok(Source::Media {
+                file: path.to_path_buf(),
+                kind,
+            });
+        }
+
+        bail!("Unsupported wallpaper file type: {}", path.display())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MediaEntry {
+    pub name: String,
+    pub kind: MediaKind,
+}
+
+// The images and videos directly inside a directory, sorted by name
+pub fn list_media(dir: &Path) -> Result<Vec<MediaEntry>> {
+    let entries = fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+
+    let mut media: Vec<MediaEntry> = entries
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_file() || t.is_symlink()))
+        .filter_map(|e| {
+            let kind = MediaKind::from_path(&e.path())?;
+            let name = e.file_name().into_string().ok()?;
+            Some(MediaEntry { name, kind })
+        })
+        .collect();
+
+    media.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(media)
+}
+
+// Turns a user supplied path into the canonical form used to key servers, so that
+// different spellings of the same wallpaper (relative, symlinked, trailing slash) share one.
+// Blocks while an archive is listed
+pub fn resolve(path: &str) -> Result<String> {
+    let canonical = Path::new(path)
+        .canonicalize()
+        .with_context(|| format!("Wallpaper path does not exist: {path}"))?;
+
+    // Archives are listed up front, so a broken or entry-less one is rejected now
+    if let Source::Archive { file, kind } = Source::classify(&canonical)? {
+        check_archive(&file, kind)?;
+    }
+
+    canonical
+        .to_str()
+        .map(str::to_string)
+        .with_context(|| format!("Wallpaper path is not valid UTF-8: {}", canonical.display()))
+}
+
+// Where a file's contents sit in its archive
+#[derive(Debug, Clone, Copy)]
+enum Location {
+    // Its index in the zip
+    Zip(usize),
+    // A byte range of the uncompressed tar
+    Tar { offset: u64, size: u64 },
+}
+
+// Every file of an archive, keyed by its path inside it, read one at a time as they're served.
+// A zip is read in place. A tar.zst can't be seeked, so it's decompressed once into memory
+#[derive(Debug)]
+pub struct ArchiveIndex {
+    file: PathBuf,
+    entries: HashMap<String, Location>,
+    // The uncompressed tar of a tar.zst
+    tar: Option<Vec<u8>>,
+}
+
+// Only the names are read, not what's in the files
+pub fn check_archive(file: &Path, kind: ArchiveKind) -> Result<()> {
+    let entries = match kind {
+        ArchiveKind::Zip => zip_entries(file)?,
+        ArchiveKind::TarZst => tar_entries(zstd_decoder(file)?, file)?,
+    };
+    if !strip_root(entries).contains_key(ENTRY_FILE) {
+        bail!("Wallpaper archive has no {ENTRY_FILE}: {}", file.display());
+    }
+    Ok(())
+}
+
+// Blocks while a tar.zst is decompressed
+pub fn index_archive(file: &Path, kind: ArchiveKind) -> Result<ArchiveIndex> {
+    let index = match kind {
+        ArchiveKind::Zip => ArchiveIndex {
+            file: file.to_path_buf(),
+            entries: strip_root(zip_entries(file)?),
+            tar: None,
+        },
+        ArchiveKind::TarZst => {
+            let mut tar = Vec::new();
+            zstd_decoder(file)?
+                .read_to_end(&mut tar)
+                .with_context(|| format!("Failed to decompress {}", file.display()))?;
+            ArchiveIndex {
+                file: file.to_path_buf(),
+                entries: strip_root(tar_entries(tar.as_slice(), file)?),
+                tar: Some(tar),
+            }
+        }
+    };
+
+    if !index.entries.contains_key(ENTRY_FILE) {
+        bail!("Wallpaper archive has no {ENTRY_FILE}: {}", file.display());
+    }
+    Ok(index)
+}
+
+impl ArchiveIndex {
+    // None for a name that isn't in the archive. Blocks while a zip entry is read
+    pub fn read(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let Some(location) = self.entries.get(name).copied() else {
+            return Ok(None);
+        };
+
+        match location {
+            Location::Zip(i) => {
+                let file = File::open(&self.file)
+                    .with_context(|| format!("Failed to open {}", self.file.display()))?;
+                let mut archive = zip::ZipArchive::new(file)
+                    .with_context(|| format!("Failed to read zip {}", self.file.display()))?;
+                let mut data = Vec::new();
+                archive.by_index(i)?.read_to_end(&mut data)?;
+                Ok(Some(data))
+            }
+            Location::Tar { offset, size } => {
+                let tar = self.tar.as_deref().unwrap_or_default();
+                let range = usize::try_from(offset)
+                    .ok()
+                    .zip(usize::try_from(offset + size).ok())
+                    .and_then(|(start, end)| tar.get(start..end))
+                    .with_context(|| format!("Truncated tar in {}", self.file.display()))?;
+                Ok(Some(range.to_vec()))
+            }
+        }
+    }
+}
+
+fn zstd_decoder(file: &Path) -> Result<zstd::Decoder<'static, io::BufReader<File>>> {
+    let reader = File::open(file).with_context(|| format!("Failed to open {}", file.display()))?;
+    zstd::Decoder::new(reader).with_context(|| format!("Failed to read zstd {}", file.display()))
+}
+
+// Only the central directory is read
+fn zip_entries(file: &Path) -> Result<Vec<(String, Location)>> {
+    let reader = File::open(file).with_context(|| format!("Failed to open {}", file.display()))?;
+    let mut archive = zip::ZipArchive::new(reader)
+        .with_context(|| format!("Failed to read zip {}", file.display()))?;
+
+    let mut entries = Vec::new();
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        if !entry.is_file() {
+            continue;
+        }
+        let Some(name) = entry.enclosed_name() else {
+            continue;
+        };
+        let Some(name) = name.to_str().map(str::to_string) else {
+            continue;
+        };
+        entries.push((name, Location::Zip(i)));
+    }
+    Ok(entries)
+}
+
+// Offsets are into the uncompressed tar that reader yields. file is only for errors
+fn tar_entries(reader: impl Read, file: &Path) -> Result<Vec<(String, Location)>> {
+    let mut archive = tar::Archive::new(reader);
+
+    let mut entries = Vec::new();
+    for entry in archive
+        .entries()
+        .with_context(|| format!("Failed to read tar {}", file.display()))?
+    {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?;
+        // Same rules as zip's enclosed_name, no escaping the archive root
+        if path.components().any(|c| {
+            !matches!(
+                c,
+                std::path::Component::Normal(_) | std::path::Component::CurDir
+            )
+        }) {
+            continue;
+        }
+        let Some(name) = path
+            .to_str()
+            .map(|n| n.trim_start_matches("./").to_string())
+        else {
+            continue;
+        };
+
+        let location = Location::Tar {
+            offset: entry.raw_file_position(),
+            size: entry.size(),
+        };
+        entries.push((name, location));
+    }
+    Ok(entries)
+}
+
+// If everything sits under a single top level directory, that directory is treated as the root
+fn strip_root(entries: Vec<(String, Location)>) -> HashMap<String, Location> {
+    let has_entry = entries.iter().any(|(name, _)| name == ENTRY_FILE);
+    let prefixes: Vec<&str> = entries
+        .iter()
+        .map(|(name, _)| name.split_once('/').map(|(dir, _)| dir).unwrap_or(""))
+        .collect();
+
+    let root = match prefixes.first().copied() {
+        Some(first) if !has_entry && !first.is_empty() && prefixes.iter().all(|p| *p == first) => {
+            Some(format!("{first}/"))
+        }
+        _ => None,
+    };
+
+    entries
+        .into_iter()
+        .map(|(name, location)| match &root {
+            Some(root) => (name[root.len()..].to_string(), location),
+            None => (name, location),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    // A fresh directory for one test's archives
+    fn scratch(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("maypaper-source-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_zip(file: &Path, files: &[(&str, &str)]) {
+        let mut zip = zip::ZipWriter::new(File::create(file).unwrap());
+        for (name, contents) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn write_tar_zst(file: &Path, files: &[(&str, &str)]) {
+        let encoder = zstd::Encoder::new(File::create(file).unwrap(), 0).unwrap();
+        let mut tar = tar::Builder::new(encoder);
+        for (name, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, name, contents.as_bytes())
+                .unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+    }
+
+    fn read(index: &ArchiveIndex, name: &str) -> Option<String> {
+        index
+            .read(name)
+            .unwrap()
+            .map(|data| String::from_utf8(data).unwrap())
+    }
+
+    const FILES: &[(&str, &str)] = &[
+        ("site/index.html", "<h1>hi</h1>"),
+        ("site/js/main.js", "console.log(1)"),
+    ];
+
+    #[test]
+    fn zip_is_served_from_a_single_root() {
+        let dir = scratch("zip");
+        let file = dir.join("site.zip");
+        write_zip(&file, FILES);
+
+        check_archive(&file, ArchiveKind::Zip).unwrap();
+        let index = index_archive(&file, ArchiveKind::Zip).unwrap();
+        assert_eq!(read(&index, "index.html").as_deref(), Some("<h1>hi</h1>"));
+        assert_eq!(
+            read(&index, "js/main.js").as_deref(),
+            Some("console.log(1)")
+        );
+        assert_eq!(read(&index, "missing.html"), None);
+    }
+
+    #[test]
+    fn tar_zst_is_served_from_memory() {
+        let dir = scratch("tar");
+        let file = dir.join("site.tar.zst");
+        write_tar_zst(&file, FILES);
+
+        check_archive(&file, ArchiveKind::TarZst).unwrap();
+        let index = index_archive(&file, ArchiveKind::TarZst).unwrap();
+        assert_eq!(
+            read(&index, "js/main.js").as_deref(),
+            Some("console.log(1)")
+        );
+        assert_eq!(read(&index, "index.html").as_deref(), Some("<h1>hi</h1>"));
+        assert_eq!(read(&index, "missing.html"), None);
+
+        // Nothing is left beside the archive
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn archive_without_an_entry_is_rejected() {
+        let dir = scratch("no-entry");
+        let zip = dir.join("site.zip");
+        write_zip(&zip, &[("a/index.html", ""), ("b/index.html", "")]);
+        assert!(check_archive(&zip, ArchiveKind::Zip).is_err());
+        assert!(index_archive(&zip, ArchiveKind::Zip).is_err());
+
+        let tar = dir.join("site.tar.zst");
+        write_tar_zst(&tar, &[("page.html", "")]);
+        assert!(check_archive(&tar, ArchiveKind::TarZst).is_err());
+    }
+}
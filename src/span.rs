@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+
+use crate::config::Bezel;
+use crate::event::{Monitor, Span, Viewport};
+
+// Lays the monitors out as the compositor does, pushed apart by their bezels, and gives each
+// the part of one page covering all of them. Fails when a monitor's geometry isn't known
+pub fn layout(
+    connectors: &[String],
+    monitors: &[Monitor],
+    bezels: &BTreeMap<String, Bezel>,
+) -> Result<Vec<(String, Span)>, String> {
+    let mut spanned = Vec::new();
+    for connector in connectors {
+        let Some(monitor) = monitors.iter().find(|m| &m.name == connector) else {
+            return Err(format!("No geometry is known for monitor {connector}"));
+        };
+        let bezel = bezels.get(connector).copied().unwrap_or_default();
+        spanned.push((monitor, bezel));
+    }
+
+    // Every monitor wholly left of or above another moves that one by its bezels
+    let placed: Vec<(i32, i32)> = spanned
+        .iter()
+        .map(|(m, b)| {
+            let left_of: i32 = spanned
+                .iter()
+                .filter(|(o, _)| o.x + o.width <= m.x)
+                .map(|(_, ob)| ob.left + ob.right)
+                .sum();
+            let above: i32 = spanned
+                .iter()
+                .filter(|(o, _)| o.y + o.height <= m.y)
+                .map(|(_, ob)| ob.top + ob.bottom)
+                .sum();
+            (m.x + b.left + left_of, m.y + b.top + above)
+        })
+        .collect();
+
+    let bounds = spanned.iter().zip(&placed).map(|((m, b), (x, y))| {
+        (
+            x - b.left,
+            y - b.top,
+            x + m.width + b.right,
+            y + m.height + b.bottom,
+        )
+    });
+    let left = bounds.clone().map(|b| b.0).min().unwrap_or(0);
+    let top = bounds.clone().map(|b| b.1).min().unwrap_or(0);
+    let right = bounds.clone().map(|b| b.2).max().unwrap_or(0);
+    let bottom = bounds.map(|b| b.3).max().unwrap_or(0);
+
+    Ok(connectors
+        .iter()
+        .zip(placed)
+        .map(|(connector, (x, y))| {
+            let span = Span {
+                connectors: connectors.to_vec(),
+                viewport: Viewport {
+                    x: x - left,
+                    y: y - top,
+                    width: right - left,
+                    height: bottom - top,
+                },
+            };
+            (connector.clone(), span)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(name: &str, x: i32, y: i32, width: i32, height: i32) -> Monitor {
+        Monitor {
+            name: name.to_string(),
+            x,
+            y,
+            width,
+            height,
+            scale: 1.0,
+            refresh_rate: 60.0,
+        }
+    }
+
+    fn names(connectors: &[&str]) -> Vec<String> {
+        connectors.iter().map(|c| c.to_string()).collect()
+    }
+
+    fn bezels(connectors: &[&str], bezel: Bezel) -> BTreeMap<String, Bezel> {
+        connectors.iter().map(|c| (c.to_string(), bezel)).collect()
+    }
+
+    // Where each window sits in the page, and the page's size
+    fn viewports(spanned: &[(String, Span)]) -> Vec<(i32, i32, i32, i32)> {
+        spanned
+            .iter()
+            .map(|(_, s)| {
+                (
+                    s.viewport.x,
+                    s.viewport.y,
+                    s.viewport.width,
+                    s.viewport.height,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn side_by_side() {
+        let connectors = names(&["DP-1", "DP-2"]);
+        let monitors = [
+            monitor("DP-1", 0, 0, 1920, 1080),
+            monitor("DP-2", 1920, 0, 1920, 1080),
+        ];
+
+        let spanned = layout(&connectors, &monitors, &BTreeMap::new()).unwrap();
+        assert_eq!(
+            viewports(&spanned),
+            [(0, 0, 3840, 1080), (1920, 0, 3840, 1080)]
+        );
+        assert_eq!(spanned[1].0, "DP-2");
+        assert_eq!(spanned[1].1.connectors, connectors);
+
+        let bezel = Bezel {
+            left: 10,
+            right: 10,
+            ..Bezel::default()
+        };
+        let spanned = layout(&connectors, &monitors, &bezels(&["DP-1", "DP-2"], bezel)).unwrap();
+        assert_eq!(
+            viewports(&spanned),
+            [(10, 0, 3880, 1080), (1950, 0, 3880, 1080)]
+        );
+    }
+
+    #[test]
+    fn stacked() {
+        let connectors = names(&["DP-1", "DP-2"]);
+        let monitors = [
+            monitor("DP-1", 0, 0, 1920, 1080),
+            monitor("DP-2", 0, 1080, 1920, 1080),
+        ];
+
+        let spanned = layout(&connectors, &monitors, &BTreeMap::new()).unwrap();
+        assert_eq!(
+            viewports(&spanned),
+            [(0, 0, 1920, 2160), (0, 1080, 1920, 2160)]
+        );
+
+        let bezel = Bezel {
+            top: 5,
+            bottom: 5,
+            ..Bezel::default()
+        };
+        let spanned = layout(&connectors, &monitors, &bezels(&["DP-1", "DP-2"], bezel)).unwrap();
+        assert_eq!(
+            viewports(&spanned),
+            [(0, 5, 1920, 2180), (0, 1095, 1920, 2180)]
+        );
+    }
+
+    #[test]
+    fn mixed_sizes_and_offsets() {
+        let connectors = names(&["DP-1", "eDP-1", "HDMI-A-1"]);
+        let monitors = [
+            monitor("DP-1", 0, 0, 2560, 1440),
+            monitor("eDP-1", 2560, 360, 1920, 1080),
+            monitor("HDMI-A-1", 0, 1440, 1920, 1080),
+        ];
+
+        let spanned = layout(&connectors, &monitors, &BTreeMap::new()).unwrap();
+        assert_eq!(
+            viewports(&spanned),
+            [
+                (0, 0, 4480, 2520),
+                (2560, 360, 4480, 2520),
+                (0, 1440, 4480, 2520)
+            ]
+        );
+
+        // Only monitors wholly left of or above one push it, both of them for HDMI-A-1
+        let bezel = Bezel {
+            left: 10,
+            right: 10,
+            top: 10,
+            bottom: 10,
+        };
+        let spanned = layout(
+            &connectors,
+            &monitors,
+            &bezels(&["DP-1", "eDP-1", "HDMI-A-1"], bezel),
+        )
+        .unwrap();
+        assert_eq!(
+            viewports(&spanned),
+            [
+                (10, 10, 4540, 2580),
+                (2610, 370, 4540, 2580),
+                (10, 1490, 4540, 2580)
+            ]
+        );
+    }
+
+    #[test]
+    fn the_page_starts_at_the_leftmost_monitor() {
+        let connectors = names(&["DP-1", "DP-2"]);
+        let monitors = [
+            monitor("DP-1", -1920, 0, 1920, 1080),
+            monitor("DP-2", 0, 0, 1920, 1080),
+        ];
+
+        let spanned = layout(&connectors, &monitors, &BTreeMap::new()).unwrap();
+        assert_eq!(
+            viewports(&spanned),
+            [(0, 0, 3840, 1080), (1920, 0, 3840, 1080)]
+        );
+    }
+
+    #[test]
+    fn unknown_geometry_is_an_error() {
+        let connectors = names(&["DP-1", "DP-9"]);
+        let monitors = [monitor("DP-1", 0, 0, 1920, 1080)];
+
+        let error = layout(&connectors, &monitors, &BTreeMap::new()).unwrap_err();
+        assert!(error.contains("DP-9"), "{error}");
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::cache::is_remote;
+use crate::event::{Ipc, Properties};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Wallpaper {
+    Path { path: String },
+    Url { url: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Saved {
+    #[serde(flatten)]
+    pub wallpaper: Wallpaper,
+    #[serde(default, skip_serializing_if = "Properties::is_empty")]
+    pub properties: Properties,
+    // Every monitor the wallpaper was spanned across, this one included
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub span: Vec<String>,
+}
+
+impl Saved {
+    // A served path can also be a remote URL, when it goes through the caching proxy
+    pub fn new(url: &str, path: Option<&str>, properties: Properties, span: Vec<String>) -> Self {
+        let wallpaper = match path {
+            Some(path) if !is_remote(path) => Wallpaper::Path {
+                path: path.to_string(),
+            },
+            Some(path) => Wallpaper::Url {
+                url: path.to_string(),
+            },
+            None => Wallpaper::Url {
+                url: url.to_string(),
+            },
+        };
+
+        Self {
+            wallpaper,
+            properties,
+            span,
+        }
+    }
+
+    // A spanned wallpaper comes back across all of its monitors
+    pub fn to_ipc(&self, connector: &str) -> Ipc {
+        let monitor = self.span.is_empty().then(|| connector.to_string());
+        let properties = self.properties.clone();
+        let span = self.span.clone();
+        match &self.wallpaper {
+            Wallpaper::Path { path } => Ipc::SetPath {
+                monitor,
+                path: path.clone(),
+                properties,
+                span,
+            },
+            Wallpaper::Url { url } => Ipc::SetUrl {
+                monitor,
+                url: url.clone(),
+                properties,
+                span,
+            },
+        }
+    }
+}
+
+// What every connector last showed, kept across restarts. Keyed by connector
+pub type Assignments = BTreeMap<String, Saved>;
+
+pub struct State {
+    file: PathBuf,
+    assignments: Assignments,
+}
+
+impl State {
+    // A missing or broken state file just means nothing gets restored
+    pub fn load(file: PathBuf) -> Self {
+        let assignments = match fs::read_to_string(&file) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(assignments) => assignments,
+                Err(e) => {
+                    warn!(target: "state", file = %file.display(), error = %e, "Ignoring unreadable state file");
+                    Assignments::new()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Assignments::new(),
+            Err(e) => {
+                warn!(target: "state", file = %file.display(), error = %e, "Failed to read state file");
+                Assignments::new()
+            }
+        };
+
+        info!(target: "state", file = %file.display(), saved = assignments.len(), "Loaded");
+        Self { file, assignments }
+    }
+
+    pub fn assignments(&self) -> &Assignments {
+        &self.assignments
+    }
+
+    pub fn set(&mut self, connector: &str, saved: Saved) {
+        if self.assignments.get(connector) == Some(&saved) {
+            return;
+        }
+        self.assignments.insert(connector.to_string(), saved);
+        self.save();
+    }
+
+    pub fn set_property(&mut self, connector: &str, key: String, value: serde_json::Value) {
+        let Some(saved) = self.assignments.get_mut(connector) else {
+            return;
+        };
+        if saved.properties.get(&key) == Some(&value) {
+            return;
+        }
+        saved.properties.insert(key, value);
+        self.save();
+    }
+
+    fn save(&self) {
+        if let Err(e) = self.write() {
+            error!(target: "state", file = %self.file.display(), error = %format!("{e:#}"), "Failed to save state");
+        }
+    }
+
+    // Written aside and renamed over, so a crash mid write can't lose the old state
+    fn write(&self) -> Result<()> {
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+
+        let tmp = self.file.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&self.assignments)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &self.file)
+            .with_context(|| format!("Failed to replace {}", self.file.display()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,6 @@
+use rust_embed::Embed;
+
+// Shared by myptmp, which writes them out, and the daemon, which renders them in memory
+#[derive(Embed)]
+#[folder = "templates/"]
+pub struct Templates;
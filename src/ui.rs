@@ -0,0 +1,91 @@
+use tokio::sync::mpsc;
+
+use crate::event::{Defaults, PowerMode, RestoreMonitor, UiCmd, UiEvent, Viewport};
+use crate::state::{Saved, State};
+
+// What shows the wallpapers. The daemon uses QML, tests the headless one
+pub trait Ui: Send + 'static {
+    // viewport is set when the page is spanned across several monitors
+    fn set_wallpaper(&mut self, connector: &str, url: &str, viewport: Option<Viewport>);
+    fn set_property(&mut self, connector: &str, key: &str, value: &serde_json::Value);
+    fn apply_defaults(&mut self, defaults: &Defaults);
+    // Applies to every monitor, Normal undoes the others
+    fn set_power(&mut self, mode: &PowerMode);
+    // None goes back to the default of apply_defaults
+    fn set_frame_rate(&mut self, connector: &str, fps: Option<u32>);
+    fn reload(&mut self, connector: &str);
+}
+
+// --- UI adapter thread ---
+// Turns UiCmds into Ui calls, saving what each monitor shows
+pub fn spawn_adapter<U: Ui>(
+    mut ui: U,
+    mut ui_rx: mpsc::UnboundedReceiver<UiCmd>,
+    ui_event_tx: mpsc::UnboundedSender<UiEvent>,
+    mut state: State,
+) {
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to build ui adapter runtime");
+
+        rt.block_on(async move {
+            while let Some(cmd) = ui_rx.recv().await {
+                match cmd {
+                    UiCmd::SetWebview(set_webview) => {
+                        let (span, viewport) = match set_webview.span {
+                            Some(span) => (span.connectors, Some(span.viewport)),
+                            None => (Vec::new(), None),
+                        };
+                        if set_webview.persist {
+                            state.set(
+                                &set_webview.connector,
+                                Saved::new(
+                                    &set_webview.url,
+                                    set_webview.path.as_deref(),
+                                    set_webview.properties,
+                                    span,
+                                ),
+                            );
+                        }
+
+                        ui.set_wallpaper(&set_webview.connector, &set_webview.url, viewport);
+                    }
+                    UiCmd::SetProperty(set_property) => {
+                        state.set_property(
+                            &set_property.connector,
+                            set_property.key.clone(),
+                            set_property.value.clone(),
+                        );
+                        ui.set_property(
+                            &set_property.connector,
+                            &set_property.key,
+                            &set_property.value,
+                        );
+                    }
+                    UiCmd::ApplyDefaults(defaults) => {
+                        ui.apply_defaults(&defaults);
+                    }
+                    UiCmd::SetPower(mode) => {
+                        ui.set_power(&mode);
+                    }
+                    UiCmd::SetFrameRate(set_frame_rate) => {
+                        ui.set_frame_rate(&set_frame_rate.connector, set_frame_rate.fps);
+                    }
+                    UiCmd::Reload(connector) => {
+                        ui.reload(&connector);
+                    }
+                    UiCmd::RecallMonitor(connector) => {
+                        let msg = state
+                            .assignments()
+                            .get(&connector)
+                            .map(|saved| saved.to_ipc(&connector));
+                        let _ = ui_event_tx
+                            .send(UiEvent::RestoreMonitor(RestoreMonitor { connector, msg }));
+                    }
+                }
+            }
+        });
+    });
+}
@@ -1,143 +1,659 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 use tokio::{
     net::TcpListener,
-    sync::{mpsc, oneshot},
+    sync::{mpsc, oneshot, watch},
+    time::{Instant, sleep_until},
 };
 
-use axum::{Router, routing::get};
-use tower::ServiceBuilder;
-use tower_http::{services::ServeDir, trace::TraceLayer};
-use tracing::{debug, error, info};
+use anyhow::Context;
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::{Path, Request, State},
+    http::{StatusCode, Uri, header},
+    middleware,
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{any, get},
+};
+use percent_encoding::percent_decode_str;
+use tower::{ServiceBuilder, ServiceExt};
+use tower_http::{
+    services::{ServeDir, ServeFile},
+    trace::TraceLayer,
+};
+use tracing::{debug, error, info, warn};
+
+use crate::cache::{self, CacheOptions};
+use crate::control::{control_router, new_token};
+use crate::event::{
+    AcquireFailed, AcquireServer, SetWebview, SyncData, TokioEvent, WebCmd, WebEvent,
+};
+use crate::inject::{Assignment, MountBase, PageContext, inject_context, with_connector};
+use crate::metrics::{METRICS, track_request};
+use crate::owners::Owners;
+use crate::sdk::{sdk_routes, system_routes};
+use crate::source::{ArchiveIndex, ENTRY_FILE, MediaKind, Source, index_archive, list_media};
+use crate::templates::Templates;
+
+#[derive(Debug, Clone)]
+pub struct WebOptions {
+    // Serve every path from one server, under /w/<id>/, instead of one server per path
+    pub shared: bool,
+    // How long a server with no watchers is kept alive for, in case it is acquired again
+    pub linger: Duration,
+    // The most lingering servers kept at once, beyond which the least recently used are evicted
+    pub max_idle: usize,
+    // Set when remote URLs should be served through a local caching proxy
+    pub cache: Option<CacheOptions>,
+}
 
-use crate::event::{SetWebview, TokioEvent, WebCmd, WebEvent};
+#[derive(Debug)]
+enum Handle {
+    Dedicated(oneshot::Sender<()>),
+    Mounted(u64),
+}
 
 #[derive(Debug)]
 struct Instance {
     url: String,
-    watchers: usize,
-    shutdown: oneshot::Sender<()>,
+    // Set while no monitor owns the path, but the server is kept warm
+    idle_since: Option<Instant>,
+    handle: Handle,
+}
+
+type Mounts = Arc<RwLock<HashMap<u64, Router>>>;
+
+// A path's routes, built off the manager, along with the path
+type Built = (String, anyhow::Result<(Router, String)>);
+
+#[derive(Debug)]
+struct SharedServer {
+    url: String,
+    mounts: Mounts,
+    next_id: u64,
+    // Never sent, the shared server lives as long as the manager does
+    _shutdown: oneshot::Sender<()>,
 }
 
 pub async fn web_manager(
     tx: mpsc::UnboundedSender<TokioEvent>,
     mut rx: mpsc::UnboundedReceiver<WebCmd>,
+    options: WebOptions,
+    sync_rx: watch::Receiver<Arc<SyncData>>,
 ) {
     // Key is the path
     let mut instances: HashMap<String, Instance> = HashMap::new();
+    let mut owners = Owners::default();
+    // Each server gets its own token, filled in by site_router
+    let ctx = PageContext {
+        sync_rx,
+        assignments: Arc::default(),
+        token: None,
+    };
+    // Only started when the first path is acquired in shared mode
+    let mut shared: Option<SharedServer> = None;
+    if options.shared {
+        // Any page could read another's token from the same origin, so none get one
+        warn!(target: "web", "Pages on the shared webserver can't use the control API");
+    }
+    let client = reqwest::Client::new();
+    // Keyed by path, the acquires waiting on its routes to be built
+    let mut building: HashMap<String, Vec<AcquireServer>> = HashMap::new();
+    let (built_tx, mut built_rx) = mpsc::unbounded_channel::<Built>();
+    // Acquires whose server has just started, handled before any new command
+    let mut ready: VecDeque<AcquireServer> = VecDeque::new();
+
+    loop {
+        METRICS.instances(instances.iter().map(|(path, inst)| {
+            (
+                path.clone(),
+                owners.watchers(path),
+                inst.idle_since.is_some(),
+            )
+        }));
+
+        // The next lingering instance to expire, if any
+        let next_expiry = instances
+            .values()
+            .filter_map(|inst| inst.idle_since)
+            .min()
+            .map(|idle_since| idle_since + options.linger);
+
+        let cmd = if let Some(acquire) = ready.pop_front() {
+            WebCmd::AcquireServer(acquire)
+        } else {
+            tokio::select! {
+                cmd = rx.recv() => match cmd {
+                    Some(cmd) => cmd,
+                    None => break,
+                },
+
+                Some((path, site)) = built_rx.recv() => {
+                    let waiting = building.remove(&path).unwrap_or_default();
+                    // Monitors unplugged in the meantime would never release it
+                    let (waiting, gone): (Vec<_>, Vec<_>) = waiting
+                        .into_iter()
+                        .partition(|a| ctx.sync_rx.borrow().connectors.contains(&a.connector));
+                    for acquire in gone {
+                        acquire_failed(&tx, acquire.connector, "no such monitor".to_string());
+                    }
+
+                    let site = match site {
+                        Ok(site) => site,
+                        Err(e) => {
+                            error!(target: "web", path = %path, error = %format!("{e:#}"), "Failed to prepare wallpaper");
+                            for acquire in waiting {
+                                acquire_failed(&tx, acquire.connector, format!("{e:#}"));
+                            }
+                            continue;
+                        }
+                    };
+                    if waiting.is_empty() {
+                        debug!(target: "web", path = %path, "Nobody waits for the webserver anymore");
+                        continue;
+                    }
+
+                    let started = if options.shared {
+                        mount_shared(&mut shared, &path, site).await
+                    } else {
+                        spawn_dedicated(&path, site).await
+                    };
+                    let Some((url, handle)) = started else {
+                        for acquire in waiting {
+                            acquire_failed(&tx, acquire.connector, "its webserver failed to start".to_string());
+                        }
+                        continue;
+                    };
+
+                    instances.insert(path, Instance { url, idle_since: None, handle });
+                    debug!(target: "web", instances = ?instances, "Current instances");
+                    ready.extend(waiting);
+                    continue;
+                }
+
+                _ = sleep_until(next_expiry.unwrap_or_else(Instant::now)), if next_expiry.is_some() => {
+                    let expired: Vec<String> = instances
+                        .iter()
+                        .filter(|(_, inst)| inst.idle_since.is_some_and(|t| t.elapsed() >= options.linger))
+                        .map(|(path, _)| path.clone())
+                        .collect();
+
+                    for path in expired {
+                        info!(target: "web", path = %path, "Linger period elapsed");
+                        if let Some(inst) = instances.remove(&path) {
+                            shutdown_instance(&path, inst, &shared);
+                        }
+                    }
+                    continue;
+                }
+            }
+        };
 
-    while let Some(cmd) = rx.recv().await {
         debug!(target: "web", "Received a cmd from tokio");
         match cmd {
             WebCmd::AcquireServer(acquire) => {
                 debug!(target: "web", acquire = ?acquire, "Received");
+                stop_waiting(&mut building, &acquire.connector);
 
-                if let Some(inst) = instances.get_mut(&acquire.path) {
-                    inst.watchers += 1;
-                    info!(target: "web", watchers = %inst.watchers, "Existing webserver found, incremented watchers");
-
-                    let set_webview = SetWebview {
-                        url: inst.url.clone(),
-                        path: Some(acquire.path.clone()),
-                        connector: acquire.connector,
-                    };
-
-                    debug!(target: "web", set_webview = ?set_webview, "Sending");
-                    let _ = tx.send(TokioEvent::WebEvent(WebEvent::SetWebview(set_webview)));
-                    debug!(target: "web", "Sent");
+                // QML has no window to show it in, so nobody would ever release it
+                if !ctx.sync_rx.borrow().connectors.contains(&acquire.connector) {
+                    warn!(target: "web", connector = %acquire.connector, "Ignoring a wallpaper for an unknown monitor");
+                    acquire_failed(&tx, acquire.connector, "no such monitor".to_string());
                     continue;
                 }
-                info!(target: "web", "Did not find existing webserver");
 
-                let listener = match TcpListener::bind(("127.0.0.1", 0)).await {
-                    Ok(l) => l,
-                    Err(e) => {
-                        error!("bind failed: {e}");
-                        continue;
+                let url = match instances.get(&acquire.path) {
+                    Some(inst) => {
+                        info!(target: "web", path = %acquire.path, "Existing webserver found");
+                        inst.url.clone()
                     }
-                };
+                    None => {
+                        info!(target: "web", "Did not find existing webserver");
 
-                let port = match listener.local_addr() {
-                    Ok(addr) => addr.port(),
-                    Err(e) => {
-                        error!("local_addr failed: {e}");
+                        // Built in a task, a large archive or remote page takes a while and
+                        // nothing else should wait on it. Acquired again once it's up
+                        let waiting = building.entry(acquire.path.clone()).or_default();
+                        if waiting.is_empty() {
+                            let (path, options, client, ctx, tx, built_tx) = (
+                                acquire.path.clone(),
+                                options.clone(),
+                                client.clone(),
+                                ctx.clone(),
+                                tx.clone(),
+                                built_tx.clone(),
+                            );
+                            tokio::spawn(async move {
+                                let site = site_router(&path, &options, &client, &ctx, &tx).await;
+                                let _ = built_tx.send((path, site));
+                            });
+                        }
+                        waiting.push(acquire);
                         continue;
                     }
                 };
 
-                let url = format!("http://127.0.0.1:{port}/");
-
-                let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
-
-                // A tiny race condition, I doubt it will cause any issues, if it does we'll change
-                let path_for_task = acquire.path.clone();
-                debug!(target: "web", path = path_for_task, "Spawning webserver task");
-                tokio::spawn(async move {
-                    run_web(listener, path_for_task, shutdown_rx).await;
-                });
+                let claimed = owners.claim(&acquire.connector, &acquire.path);
+                if claimed.first
+                    && let Some(inst) = instances.get_mut(&acquire.path)
+                    && inst.idle_since.take().is_some()
+                {
+                    info!(target: "web", path = %acquire.path, "Revived lingering webserver");
+                }
+                info!(target: "web", path = %acquire.path, watchers = owners.watchers(&acquire.path), "Acquired webserver");
 
-                instances.insert(
-                    acquire.path.clone(),
-                    Instance {
-                        url: url.clone(),
-                        watchers: 1,
-                        shutdown: shutdown_tx,
+                ctx.assignments.write().unwrap().insert(
+                    acquire.connector.clone(),
+                    Assignment {
+                        path: acquire.path.clone(),
+                        properties: acquire.properties.clone(),
                     },
                 );
-                debug!(target: "web", instances = ?instances, "Current instances");
 
                 let set_webview = SetWebview {
-                    url,
+                    url: with_connector(&url, &acquire.connector),
                     path: Some(acquire.path),
                     connector: acquire.connector,
+                    properties: acquire.properties,
+                    span: acquire.span,
+                    persist: true,
                 };
                 debug!(target: "web", set_webview = ?set_webview, "Sending");
                 let _ = tx.send(TokioEvent::WebEvent(WebEvent::SetWebview(set_webview)));
-                debug!(target: "web", "Sent")
+                debug!(target: "web", "Sent");
+
+                if let Some(path) = claimed.orphaned {
+                    unwatched(path, &mut instances, &shared, &options);
+                }
             }
 
             WebCmd::ReleaseServer(release) => {
                 debug!(target: "web", release = ?release, "Received");
+                stop_waiting(&mut building, &release.connector);
 
-                let should_shutdown = match instances.get_mut(&release.path) {
-                    Some(inst) => {
-                        if inst.watchers > 1 {
-                            inst.watchers -= 1;
-                            info!(target: "web", path = %release.path, watchers = %inst.watchers, "Not removing, still watched");
-                            false
-                        } else {
-                            true
-                        }
-                    }
-                    None => {
-                        error!(target: "web", "Received a release request for a path that isn't served!");
-                        false
-                    }
-                };
+                if let Some(path) = owners.release(&release.connector) {
+                    unwatched(path, &mut instances, &shared, &options);
+                }
+            }
 
-                if !should_shutdown {
+            WebCmd::RefreshCache(refresh) => {
+                debug!(target: "web", refresh = ?refresh, "Received");
+
+                let Some(cache) = &options.cache else {
+                    error!(target: "web", "Received a cache refresh, but caching is disabled");
+                    let message =
+                        "Caching is disabled, start maypaper with --cache-urls".to_string();
+                    let _ = refresh.reply.send(Err(message));
                     continue;
+                };
+
+                // Off the manager, a large cache takes a while to walk
+                let dir = cache.dir.clone();
+                tokio::spawn(async move {
+                    let url = refresh.url.clone();
+                    let expired =
+                        tokio::task::spawn_blocking(move || cache::expire(&dir, url.as_deref()))
+                            .await
+                            .context("Cache expiry panicked")
+                            .and_then(|expired| expired);
+                    let result = match expired {
+                        Ok(expired) => {
+                            info!(target: "web", expired, "Expired cached responses");
+                            Ok(())
+                        }
+                        Err(e) => {
+                            error!(target: "web", error = %format!("{e:#}"), "Failed to expire cache");
+                            Err(format!("{e:#}"))
+                        }
+                    };
+                    let _ = refresh.reply.send(result);
+                });
+            }
+
+            WebCmd::ForgetConnector(connector) => {
+                debug!(target: "web", connector = %connector, "Received ForgetConnector");
+                stop_waiting(&mut building, &connector);
+
+                // Its page is gone, so is its right to control anything
+                ctx.assignments.write().unwrap().remove(&connector);
+                if let Some(path) = owners.release(&connector) {
+                    unwatched(path, &mut instances, &shared, &options);
                 }
+            }
+
+            WebCmd::SetProperty(set_property) => {
+                debug!(target: "web", set_property = ?set_property, "Received");
 
-                // There are no longer any watchers
-                if let Some(inst) = instances.remove(&release.path) {
-                    info!(target: "web", path = %release.path, "No watchers, attempting to shutdown");
-                    let _ = inst.shutdown.send(());
+                // Kept, so a reload of the page still sees the new value
+                if let Some(assignment) = ctx
+                    .assignments
+                    .write()
+                    .unwrap()
+                    .get_mut(&set_property.connector)
+                {
+                    assignment
+                        .properties
+                        .insert(set_property.key, set_property.value);
                 }
             }
         }
     }
 }
 
-async fn run_web(listener: TcpListener, path: String, shutdown: oneshot::Receiver<()>) {
-    let addr = listener.local_addr().unwrap();
-    info!(target: "web", _path = %path, %addr, "Starting webserver");
+// The monitor moved on, so it no longer waits for a server it asked for before
+fn stop_waiting(building: &mut HashMap<String, Vec<AcquireServer>>, connector: &str) {
+    for waiting in building.values_mut() {
+        waiting.retain(|acquire| acquire.connector != connector);
+    }
+}
+
+// No monitor owns the path anymore, so its server lingers or goes
+fn unwatched(
+    path: String,
+    instances: &mut HashMap<String, Instance>,
+    shared: &Option<SharedServer>,
+    options: &WebOptions,
+) {
+    if options.linger.is_zero() {
+        if let Some(inst) = instances.remove(&path) {
+            shutdown_instance(&path, inst, shared);
+        }
+        return;
+    }
 
-    let static_site = ServeDir::new(path.clone()).append_index_html_on_directories(true);
+    if let Some(inst) = instances.get_mut(&path) {
+        inst.idle_since = Some(Instant::now());
+        info!(target: "web", path = %path, linger = ?options.linger, "No watchers, lingering");
+    }
+    evict_idle(instances, shared, options.max_idle);
+}
+
+// Drops the least recently used lingering instances, until at most max_idle remain
+fn evict_idle(
+    instances: &mut HashMap<String, Instance>,
+    shared: &Option<SharedServer>,
+    max_idle: usize,
+) {
+    let mut idle: Vec<(Instant, String)> = instances
+        .iter()
+        .filter_map(|(path, inst)| inst.idle_since.map(|t| (t, path.clone())))
+        .collect();
+
+    if idle.len() <= max_idle {
+        return;
+    }
 
-    let app = Router::new()
+    let excess = idle.len() - max_idle;
+    idle.sort();
+    for (_, path) in idle.into_iter().take(excess) {
+        info!(target: "web", path = %path, max_idle, "Evicting least recently used lingering webserver");
+        if let Some(inst) = instances.remove(&path) {
+            shutdown_instance(&path, inst, shared);
+        }
+    }
+}
+
+fn shutdown_instance(path: &str, inst: Instance, shared: &Option<SharedServer>) {
+    info!(target: "web", path = %path, "No watchers, attempting to shutdown");
+    match inst.handle {
+        Handle::Dedicated(shutdown) => {
+            let _ = shutdown.send(());
+        }
+        Handle::Mounted(id) => {
+            if let Some(server) = shared {
+                server.mounts.write().unwrap().remove(&id);
+                info!(target: "web", path = %path, id, "Unmounted");
+            }
+        }
+    }
+}
+
+async fn bind_local() -> Option<(TcpListener, String)> {
+    let listener = match TcpListener::bind(("127.0.0.1", 0)).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!("bind failed: {e}");
+            return None;
+        }
+    };
+
+    let port = match listener.local_addr() {
+        Ok(addr) => addr.port(),
+        Err(e) => {
+            error!("local_addr failed: {e}");
+            return None;
+        }
+    };
+
+    Some((listener, format!("http://127.0.0.1:{port}/")))
+}
+
+// Answers the load waiting on this, instead of leaving it to time out
+fn acquire_failed(tx: &mpsc::UnboundedSender<TokioEvent>, connector: String, error: String) {
+    let failed = AcquireFailed { connector, error };
+    let _ = tx.send(TokioEvent::WebEvent(WebEvent::AcquireFailed(failed)));
+}
+
+async fn spawn_dedicated(path: &str, (site, entry): (Router, String)) -> Option<(String, Handle)> {
+    let (listener, url) = bind_local().await?;
+
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+
+    // A tiny race condition, I doubt it will cause any issues, if it does we'll change
+    let path_for_task = path.to_string();
+    debug!(target: "web", path = path_for_task, "Spawning webserver task");
+    tokio::spawn(async move {
+        run_web(listener, path_for_task, site, shutdown_rx).await;
+    });
+
+    Some((format!("{url}{entry}"), Handle::Dedicated(shutdown_tx)))
+}
+
+async fn mount_shared(
+    shared: &mut Option<SharedServer>,
+    path: &str,
+    (site, entry): (Router, String),
+) -> Option<(String, Handle)> {
+    if shared.is_none() {
+        let (listener, url) = bind_local().await?;
+        let mounts: Mounts = Arc::default();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+
+        let mounts_for_task = mounts.clone();
+        debug!(target: "web", url, "Spawning shared webserver task");
+        tokio::spawn(async move {
+            run_shared_web(listener, mounts_for_task, shutdown_rx).await;
+        });
+
+        *shared = Some(SharedServer {
+            url,
+            mounts,
+            next_id: 0,
+            _shutdown: shutdown_tx,
+        });
+    }
+
+    let server = shared.as_mut()?;
+    let id = server.next_id;
+    server.next_id += 1;
+
+    server.mounts.write().unwrap().insert(id, site);
+    info!(target: "web", path, id, "Mounted on shared webserver");
+
+    Some((format!("{}w/{id}/{entry}", server.url), Handle::Mounted(id)))
+}
+
+// Builds the routes that serve a wallpaper's files, depending on what the path points at,
+// along with the entry point relative to the server's root
+async fn site_router(
+    path: &str,
+    options: &WebOptions,
+    client: &reqwest::Client,
+    ctx: &PageContext,
+    tx: &mpsc::UnboundedSender<TokioEvent>,
+) -> anyhow::Result<(Router, String)> {
+    let remote = options.cache.is_some() && cache::is_remote(path);
+    let ctx = PageContext {
+        token: match remote || options.shared {
+            true => None,
+            false => Some(new_token()?),
+        },
+        ..ctx.clone()
+    };
+    let mut api = system_routes(!remote);
+    if let Some(token) = &ctx.token {
+        api = api.merge(control_router(
+            path,
+            token,
+            ctx.assignments.clone(),
+            tx.clone(),
+        ));
+    }
+    let inject = middleware::from_fn_with_state(ctx, inject_context);
+    let track = middleware::from_fn_with_state(path.to_string(), track_request);
+
+    if let Some(cache) = &options.cache
+        && remote
+    {
+        let (site, entry) = cache::proxy_router(path, cache, client.clone()).await?;
+        return Ok((site.merge(api).layer(inject).layer(track), entry));
+    }
+
+    let site = match Source::classify(std::path::Path::new(path))? {
+        Source::Dir(root) => Router::new()
+            .fallback_service(ServeDir::new(root).append_index_html_on_directories(true)),
+
+        Source::Slideshow(dir) => {
+            let page = template_page("slideshow")?;
+            let dir_for_api = dir.clone();
+            Router::new()
+                .route(
+                    "/",
+                    get(move || {
+                        let page = page.clone();
+                        async move { Html(page) }
+                    }),
+                )
+                .route(
+                    "/api/media",
+                    get(move || {
+                        let dir = dir_for_api.clone();
+                        async move {
+                            match list_media(&dir) {
+                                Ok(media) => Json(media).into_response(),
+                                Err(e) => {
+                                    error!(target: "web", error = %format!("{e:#}"), "Failed to list media");
+                                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                                }
+                            }
+                        }
+                    }),
+                )
+                .fallback_service(ServeDir::new(dir))
+        }
+
+        Source::Html { root, entry } => Router::new()
+            .route_service("/", ServeFile::new(entry))
+            .fallback_service(ServeDir::new(root).append_index_html_on_directories(true)),
+
+        Source::Media { file, kind } => {
+            let (page, name) = media_page(&file, kind)?;
+            Router::new()
+                .route(
+                    "/",
+                    get(move || {
+                        let page = page.clone();
+                        async move { Html(page) }
+                    }),
+                )
+                .route_service(&format!("/{name}"), ServeFile::new(file))
+        }
+
+        // Decompressing a large one takes a while, which the runtime shouldn't wait on
+        Source::Archive { file, kind } => {
+            let index = tokio::task::spawn_blocking(move || index_archive(&file, kind))
+                .await
+                .context("Archive indexing panicked")??;
+            Router::new()
+                .fallback(serve_archive)
+                .with_state(Arc::new(index))
+        }
+    };
+
+    Ok((site.merge(api).layer(inject).layer(track), String::new()))
+}
+
+// Renders the matching myptmp template around a bare image or video
+fn media_page(file: &std::path::Path, kind: MediaKind) -> anyhow::Result<(String, String)> {
+    let (template, placeholder) = kind.template();
+
+    let extension = file
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let name = format!("wallpaper.{extension}");
+
+    let html = template_page(template)?.replace(placeholder, &name);
+
+    Ok((html, name))
+}
+
+fn template_page(template: &str) -> anyhow::Result<String> {
+    let asset = Templates::get(&format!("{template}/{ENTRY_FILE}"))
+        .with_context(|| format!("Missing built in template: {template}"))?;
+
+    std::str::from_utf8(&asset.data)
+        .map(str::to_string)
+        .with_context(|| format!("templates/{template}/{ENTRY_FILE} is not valid UTF-8"))
+}
+
+async fn serve_archive(State(index): State<Arc<ArchiveIndex>>, uri: Uri) -> Response {
+    let mut name = percent_decode_str(uri.path().trim_start_matches('/'))
+        .decode_utf8_lossy()
+        .into_owned();
+    if name.is_empty() || name.ends_with('/') {
+        name.push_str(ENTRY_FILE);
+    }
+
+    let mime = mime_guess::from_path(&name).first_or_octet_stream();
+    match tokio::task::spawn_blocking(move || index.read(&name)).await {
+        Ok(Ok(Some(data))) => (
+            [(header::CONTENT_TYPE, mime.to_string())],
+            Bytes::from(data),
+        )
+            .into_response(),
+        Ok(Ok(None)) => StatusCode::NOT_FOUND.into_response(),
+        Ok(Err(e)) => {
+            error!(target: "web", error = %format!("{e:#}"), "Failed to read from archive");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(e) => {
+            error!(target: "web", error = %e, "Archive read panicked");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+fn api_routes<S: Clone + Send + Sync + 'static>() -> Router<S> {
+    Router::new()
         .route("/api/health", get(|| async { "ok" }))
-        .fallback_service(static_site)
+        .merge(sdk_routes())
+}
+
+async fn run_web(
+    listener: TcpListener,
+    path: String,
+    site: Router,
+    shutdown: oneshot::Receiver<()>,
+) {
+    let addr = listener.local_addr().unwrap();
+    info!(target: "web", _path = %path, %addr, "Starting webserver");
+
+    let app = api_routes()
+        .merge(site)
         .layer(ServiceBuilder::new().layer(TraceLayer::new_for_http()));
 
     let path_for_shutdown = path.clone();
@@ -151,3 +667,76 @@ async fn run_web(listener: TcpListener, path: String, shutdown: oneshot::Receive
         error!(target: "web", path = %path, error = %e, "Web server error");
     }
 }
+
+async fn run_shared_web(listener: TcpListener, mounts: Mounts, shutdown: oneshot::Receiver<()>) {
+    let addr = listener.local_addr().unwrap();
+    info!(target: "web", %addr, "Starting shared webserver");
+
+    let app = api_routes()
+        .route(
+            "/w/{id}",
+            get(|Path(id): Path<u64>| async move { Redirect::permanent(&format!("/w/{id}/")) }),
+        )
+        .route("/w/{id}/", any(serve_mount))
+        .route("/w/{id}/{*rest}", any(serve_mount))
+        .with_state(mounts)
+        .layer(ServiceBuilder::new().layer(TraceLayer::new_for_http()));
+
+    let server = axum::serve(listener, app).with_graceful_shutdown(async move {
+        let _ = shutdown.await;
+        info!(target: "web", "Shutdown shared webserver");
+    });
+
+    if let Err(e) = server.await {
+        error!(target: "web", error = %e, "Shared web server error");
+    }
+}
+
+async fn serve_mount(
+    State(mounts): State<Mounts>,
+    Path(params): Path<HashMap<String, String>>,
+    mut req: Request,
+) -> Response {
+    let Some(id) = params.get("id").and_then(|id| id.parse::<u64>().ok()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    // Clone it out, so we don't hold the lock across the await
+    let Some(site) = mounts.read().unwrap().get(&id).cloned() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    // Strip the mount prefix, so the directory sees a root relative request. Cut from the
+    // raw path, a decoded one breaks on spaces and turns %3F or %23 into a query or fragment
+    let rest = req
+        .uri()
+        .path()
+        .strip_prefix("/w/")
+        .and_then(|p| p.find('/').map(|i| &p[i..]))
+        .unwrap_or("/");
+    let rewritten = match req.uri().query() {
+        Some(query) => format!("{rest}?{query}"),
+        None => rest.to_string(),
+    };
+    match rewritten.parse::<Uri>() {
+        Ok(uri) => *req.uri_mut() = uri,
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    }
+    req.extensions_mut().insert(MountBase(format!("/w/{id}/")));
+
+    let mut res = match site.oneshot(req).await {
+        Ok(res) => res,
+        Err(never) => match never {},
+    };
+
+    // ServeDir redirects directories to their trailing slash form, which needs the prefix back
+    if let Some(location) = res.headers().get(header::LOCATION).cloned()
+        && let Ok(location) = location.to_str()
+        && location.starts_with('/')
+        && let Ok(value) = format!("/w/{id}{location}").parse()
+    {
+        res.headers_mut().insert(header::LOCATION, value);
+    }
+
+    res
+}
@@ -3,7 +3,7 @@
 import QtQuick 2.15
 import QtQml 2.15
 import QtQuick.Window 2.15
-import QtWebEngine 1.0
+import QtWebEngine 1.10
 import org.kde.layershell 1.0 as LayerShell
 
 Item {
@@ -12,14 +12,130 @@ Item {
     // Map: connectorName -> Window instance
     property var windowsByConnector: ({})
 
-    // Called from Rust, sets one connector 
-    function setWallpaper(connectorName, url) {
+    // The [defaults] table of wallpapers.toml
+    property string placeholderColor: "#222222"
+    property string wallpaperLayer: "background"
+    property string audioPolicy: "focused"
+
+    // What the [[power]] rules ask for, see PowerMode
+    property var powerMode: ({ action: "normal" })
+    readonly property bool powerFallback: powerMode.action === "fallback"
+
+    // [defaults] frame_rate, and what SetFrameRate set per connector, 0 is uncapped
+    property int defaultFrameRate: 0
+    property var frameRates: ({})
+
+    // Called from Rust, defaults is JSON
+    function setDefaults(defaults) {
+        const d = JSON.parse(defaults)
+        placeholderColor = d.placeholder
+        wallpaperLayer = d.layer
+        audioPolicy = d.audio
+        defaultFrameRate = d.frame_rate
+
+        for (const name in windowsByConnector) {
+            windowsByConnector[name].pushFocusStateToWeb()
+            windowsByConnector[name].pushRenderStateToWeb()
+        }
+    }
+
+    // Called from Rust, mode is JSON
+    function setPower(mode) {
+        powerMode = JSON.parse(mode)
+
+        for (const name in windowsByConnector) {
+            windowsByConnector[name].pushRenderStateToWeb()
+        }
+    }
+
+    // Called from Rust, 0 goes back to the default. Kept for monitors that aren't there yet
+    function setFrameRate(connectorName, fps) {
+        if (fps > 0) {
+            frameRates[connectorName] = fps
+        } else {
+            delete frameRates[connectorName]
+        }
+
+        const w = windowsByConnector[connectorName]
+        if (w) {
+            w.pushRenderStateToWeb()
+        }
+    }
+
+    // Caps requestAnimationFrame at fps, 0 is uncapped. The shim is only installed once a cap is set.
+    // Every callback of a frame that is let through runs, the frames in between are skipped
+    function frameRateScript(fps) {
+        return "(() => {\n" +
+               "  const mp = (globalThis.maypaper ??= {});\n" +
+               "  mp.frameRate = " + fps + ";\n" +
+               "  if (mp.frameRateShim || !mp.frameRate) return;\n" +
+               "  mp.frameRateShim = true;\n" +
+               "  const raf = globalThis.requestAnimationFrame.bind(globalThis);\n" +
+               "  const caf = globalThis.cancelAnimationFrame.bind(globalThis);\n" +
+               "  const waiting = new Map();\n" +
+               "  let next = 0, last = -Infinity;\n" +
+               "  const due = (t) => !mp.frameRate || t === last || t - last >= 1000 / mp.frameRate - 1;\n" +
+               "  globalThis.requestAnimationFrame = (callback) => {\n" +
+               "    const id = ++next;\n" +
+               "    const tick = (t) => {\n" +
+               "      if (!due(t)) { waiting.set(id, raf(tick)); return; }\n" +
+               "      last = t;\n" +
+               "      waiting.delete(id);\n" +
+               "      callback(t);\n" +
+               "    };\n" +
+               "    waiting.set(id, raf(tick));\n" +
+               "    return id;\n" +
+               "  };\n" +
+               "  globalThis.cancelAnimationFrame = (id) => {\n" +
+               "    caf(waiting.get(id));\n" +
+               "    waiting.delete(id);\n" +
+               "  };\n" +
+               "})();\n"
+    }
+
+    // Called from Rust, sets one connector. viewport is JSON, null unless spanned. url is
+    // what Qt made of requested, which is the string Rust sent and is what gets reported back
+    function setWallpaper(connectorName, url, viewport, requested) {
         const w = windowsByConnector[connectorName]
         if (w) {
+            w.viewport = JSON.parse(viewport || "null")
+            // The same page is only loaded again if it failed, otherwise Rust hears how it went the last time
+            if (w.requestedUrl === requested) {
+                if (w.loadError) {
+                    w.loadFinished = false
+                    w.reloadPage()
+                } else {
+                    w.reportLastLoad()
+                }
+                return
+            }
+            w.requestedUrl = requested
+            w.loadFinished = false
             w.currentUrl = url
             return
         }
         console.log("setWallpaper: connector not found:", connectorName)
+        bridge.reportLoadFailed(connectorName, requested, "no window for " + connectorName)
+    }
+
+    // Called from Rust, after a page's renderer died
+    function reloadWallpaper(connectorName) {
+        const w = windowsByConnector[connectorName]
+        if (w) {
+            w.reloadPage()
+            return
+        }
+        console.log("reloadWallpaper: connector not found:", connectorName)
+    }
+
+    // Called from Rust, value is JSON
+    function setProperty(connectorName, key, value) {
+        const w = windowsByConnector[connectorName]
+        if (w) {
+            w.pushPropertyToWeb(key, value)
+            return
+        }
+        console.log("setProperty: connector not found:", connectorName)
     }
 
     function reportMonitorsToRust() {
@@ -29,10 +145,22 @@ Item {
         }
 
         let names = []
+        let info = []
         for (let i = 0; i < Application.screens.length; ++i) {
-            names.push(Application.screens[i].name)
+            const scr = Application.screens[i]
+            names.push(scr.name)
+            info.push({
+                name: scr.name,
+                x: scr.virtualX,
+                y: scr.virtualY,
+                width: scr.width,
+                height: scr.height,
+                scale: scr.devicePixelRatio,
+                // Not every Qt version exposes this on screens
+                refreshRate: scr.refreshRate || 0
+            })
         }
-        bridge.setMonitorNames(names)
+        bridge.setMonitorNames(names, JSON.stringify(info))
     }
 
 
@@ -88,19 +216,32 @@ Item {
         Window {
             id: root
             visible: true
-            color: "black"
+            color: app.placeholderColor
 
             property var targetScreen
             property string connectorName: ""
+            // Set when the page spans several monitors: the page's size, and where this window sits in it
+            property var viewport: null
 
-            // By default, load a grey background so we don't sear people's eyes out
-            property url currentUrl: "data:text/html,<html><body style='margin:0;background:%23222222;'></body></html>"
+            // By default, load a grey background so we don't sear people's eyes out.
+            // Bound until the first wallpaper is set, so a changed placeholder applies
+            property url currentUrl: "data:text/html,<html><body style='margin:0;background:"
+                                     + encodeURIComponent(app.placeholderColor) + ";'></body></html>"
+            // currentUrl as Rust gave it, before QUrl tidies it up, for reporting back
+            property string requestedUrl: ""
+            property bool loadFinished: false
+            // Empty when the last load succeeded
+            property string loadError: ""
+            // Paused by the power rules, the page's last frame shows as a still
+            property bool frozen: false
 
             screen: targetScreen
             width: Screen.width
             height: Screen.height
 
-            LayerShell.Window.layer: LayerShell.Window.LayerBackground
+            LayerShell.Window.layer: app.wallpaperLayer === "bottom"
+                                     ? LayerShell.Window.LayerBottom
+                                     : LayerShell.Window.LayerBackground
             LayerShell.Window.anchors: LayerShell.Window.AnchorTop
                                      | LayerShell.Window.AnchorBottom
                                      | LayerShell.Window.AnchorLeft
@@ -113,18 +254,52 @@ Item {
 
             WebEngineView {
                 id: web
-                anchors.fill: parent
+                // Hidden pages aren't rendered at all
+                visible: !app.powerFallback && !root.frozen
+                x: root.viewport ? -root.viewport.x : 0
+                y: root.viewport ? -root.viewport.y : 0
+                width: root.viewport ? root.viewport.width : root.width
+                height: root.viewport ? root.viewport.height : root.height
                 url: root.currentUrl
+
+                onLoadingChanged: function(loadRequest) {
+                    // Left over from a page that was replaced, and aborted for it
+                    if (loadRequest.url.toString() !== web.url.toString()) {
+                        return
+                    }
+                    if (loadRequest.status === WebEngineView.LoadStartedStatus) {
+                        // Shown, so it can be grabbed again once it has loaded
+                        root.setFrozen(false)
+                        root.loadFinished = false
+                    } else if (loadRequest.status === WebEngineView.LoadFailedStatus) {
+                        root.loadFinished = true
+                        root.loadError = loadRequest.errorString || "failed to load"
+                        root.reportLastLoad()
+                    } else if (loadRequest.status === WebEngineView.LoadSucceededStatus) {
+                        root.loadFinished = true
+                        root.loadError = ""
+                        root.pushRenderStateToWeb()
+                        root.reportLastLoad()
+                    }
+                }
+
+                // Chromium starts a fresh renderer on the next load, which Rust asks for with reloadWallpaper
+                onRenderProcessTerminated: function(terminationStatus, exitCode) {
+                    bridge.reportRenderProcessTerminated(root.connectorName, terminationStatus, exitCode)
+                }
             }
 
             function pushFocusStateToWeb() {
                 const activeNow = root.active
 
                 // Mute/unmute audio (QtWebEngineView uses audioMuted)
+                const muted = app.audioPolicy === "muted" ? true
+                            : app.audioPolicy === "always" ? false
+                            : !activeNow
                 if ("audioMuted" in web) {
-                    web.audioMuted = !activeNow
+                    web.audioMuted = muted
                 } else if ("muted" in web) {
-                    web.muted = !activeNow
+                    web.muted = muted
                 }
 
                 const js =
@@ -134,6 +309,108 @@ Item {
                 web.runJavaScript(js)
             }
 
+            // The page as it was when it was paused
+            Image {
+                id: still
+                x: web.x
+                y: web.y
+                width: web.width
+                height: web.height
+                visible: root.frozen && !app.powerFallback
+            }
+
+            // Shown instead of the page while the power rules ask for a fallback
+            Rectangle {
+                anchors.fill: parent
+                visible: app.powerFallback
+                color: app.powerMode.color || app.placeholderColor
+
+                Image {
+                    anchors.fill: parent
+                    visible: !!app.powerMode.image
+                    source: app.powerMode.image ? "file://" + encodeURI(app.powerMode.image) : ""
+                    fillMode: Image.PreserveAspectCrop
+                    asynchronous: true
+                }
+            }
+
+            // The lowest of this monitor's cap and the power rules' one, 0 is uncapped
+            function frameRateCap() {
+                const own = app.frameRates[root.connectorName] || app.defaultFrameRate
+                const power = app.powerMode.action === "frame_rate" ? app.powerMode.fps : 0
+                if (own > 0 && power > 0) {
+                    return Math.min(own, power)
+                }
+                return own || power
+            }
+
+            function pushRenderStateToWeb() {
+                const mode = app.powerMode
+                const paused = mode.action === "pause" || mode.action === "fallback"
+                const fps = frameRateCap()
+
+                const js =
+                    "globalThis.maypaper?.setPaused?.(" + (paused ? "true" : "false") + ");\n" +
+                    app.frameRateScript(fps) +
+                    "//# sourceURL=maypaper://power"
+
+                // Unfrozen first so the page hears it resumed, freezing waits on a grab anyway
+                setFrozen(mode.action === "pause")
+                web.runJavaScript(js)
+            }
+
+            // Chromium only freezes pages that aren't visible, so a still of the last frame
+            // stands in while it is
+            function setFrozen(freeze) {
+                if (!freeze) {
+                    web.lifecycleState = WebEngineView.LifecycleState.Active
+                    root.frozen = false
+                    still.source = ""
+                    return
+                }
+                if (root.frozen) {
+                    return
+                }
+                web.grabToImage(function(result) {
+                    // Resumed or reloaded while it was being grabbed
+                    if (app.powerMode.action !== "pause" || !root.loadFinished) {
+                        return
+                    }
+                    still.source = result.url
+                    root.frozen = true
+                    web.lifecycleState = WebEngineView.LifecycleState.Frozen
+                })
+            }
+
+            function reloadPage() {
+                web.reload()
+            }
+
+            // Still loading, it reports once it's done
+            function reportLastLoad() {
+                if (!root.loadFinished) {
+                    return
+                }
+                if (root.loadError) {
+                    bridge.reportLoadFailed(root.connectorName, root.requestedUrl, root.loadError)
+                } else {
+                    bridge.reportLoadSucceeded(root.connectorName, root.requestedUrl)
+                }
+            }
+
+            function pushPropertyToWeb(key, value) {
+                const js =
+                    "(() => {\n" +
+                    "  const properties = (globalThis.maypaper ??= {}).properties ??= {};\n" +
+                    "  const key = " + JSON.stringify(key) + ";\n" +
+                    "  properties[key] = " + value + ";\n" +
+                    "  globalThis.dispatchEvent(new CustomEvent('maypaper:property', { detail: { key, value: properties[key] } }));\n" +
+                    "})();\n" +
+                    "//# sourceURL=maypaper://property"
+
+                web.runJavaScript(js)
+            }
+
             onActiveChanged: pushFocusStateToWeb()
             Component.onCompleted: pushFocusStateToWeb()
         }
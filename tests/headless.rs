@@ -0,0 +1,538 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use maypaper::event::{
+    CursorMode, Event, Ipc, IpcReply, Playlist, PlaylistEntry, Properties, Termination,
+};
+use maypaper::headless::{Headless, UiCall};
+use maypaper::webserver::WebOptions;
+use maypaper::{Paths, subscribe};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// A fresh config directory, with a wallpaper directory per name
+fn setup(test: &str, wallpapers: &[&str]) -> (Paths, Vec<String>) {
+    let base = std::env::temp_dir().join(format!("maypaper-{test}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&base);
+    let paths = Paths::get_dirs(Some(base.clone())).unwrap();
+
+    let dirs = wallpapers
+        .iter()
+        .map(|name| {
+            let dir: PathBuf = base.join("sites").join(name);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("index.html"), format!("<h1>{name}</h1>")).unwrap();
+            dir.to_string_lossy().into_owned()
+        })
+        .collect();
+    (paths, dirs)
+}
+
+fn start(paths: Paths) -> Headless {
+    let web = WebOptions {
+        shared: false,
+        linger: Duration::ZERO,
+        max_idle: 0,
+        cache: None,
+    };
+    Headless::start(paths, web).unwrap()
+}
+
+// The status line and body of a plain HTTP/1.0 GET
+fn get(addr: SocketAddr, path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    write!(stream, "GET {path} HTTP/1.0\r\nHost: {addr}\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response.lines().next().unwrap_or("").to_string();
+    let body = response.split_once("\r\n\r\n").map_or("", |(_, b)| b);
+    (status, body.to_string())
+}
+
+fn set_path(monitor: Option<&str>, path: &str) -> Ipc {
+    Ipc::SetPath {
+        monitor: monitor.map(str::to_string),
+        path: path.to_string(),
+        properties: Properties::default(),
+        span: Vec::new(),
+    }
+}
+
+fn addr(url: &str) -> SocketAddr {
+    let host = url
+        .strip_prefix("http://")
+        .and_then(|rest| rest.split('/').next())
+        .unwrap();
+    host.parse().unwrap()
+}
+
+fn wait_closed(addr: SocketAddr) -> bool {
+    let deadline = Instant::now() + TIMEOUT;
+    while Instant::now() < deadline {
+        if TcpStream::connect_timeout(&addr, Duration::from_millis(100)).is_err() {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
+
+#[test]
+fn set_path_reaches_every_monitor() {
+    let (paths, dirs) = setup("every", &["a"]);
+    let daemon = start(paths);
+    daemon.set_connectors(&["DP-1", "HDMI-A-1"]);
+
+    assert!(matches!(
+        daemon.send(&set_path(None, &dirs[0])).unwrap(),
+        IpcReply::Ok
+    ));
+
+    let mut seen: Vec<(String, String)> = (0..2)
+        .map(|_| daemon.next_wallpaper(TIMEOUT).expect("no wallpaper set"))
+        .collect();
+    seen.sort();
+    assert_eq!(seen[0].0, "DP-1");
+    assert_eq!(seen[1].0, "HDMI-A-1");
+    assert!(seen[0].1.starts_with("http://127.0.0.1:"));
+
+    // One server, watched by both
+    assert_eq!(addr(&seen[0].1), addr(&seen[1].1));
+}
+
+#[test]
+fn server_shuts_down_once_unwatched() {
+    let (paths, dirs) = setup("refcount", &["a", "b"]);
+    let daemon = start(paths);
+    daemon.set_connectors(&["DP-1", "DP-2"]);
+
+    daemon.send(&set_path(None, &dirs[0])).unwrap();
+    let (_, url) = daemon.next_wallpaper(TIMEOUT).unwrap();
+    daemon.next_wallpaper(TIMEOUT).unwrap();
+    let old = addr(&url);
+    assert!(TcpStream::connect(old).is_ok());
+
+    // Still watched by DP-2
+    daemon.send(&set_path(Some("DP-1"), &dirs[1])).unwrap();
+    daemon.next_wallpaper(TIMEOUT).unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(TcpStream::connect(old).is_ok());
+
+    daemon.send(&set_path(Some("DP-2"), &dirs[1])).unwrap();
+    daemon.next_wallpaper(TIMEOUT).unwrap();
+    assert!(wait_closed(old), "server kept running with no watchers");
+}
+
+#[test]
+fn replugged_monitor_gets_its_wallpaper_back() {
+    let (paths, dirs) = setup("hotplug", &["a", "b"]);
+    let daemon = start(paths);
+    daemon.set_connectors(&["DP-1", "DP-2"]);
+
+    daemon.send(&set_path(Some("DP-1"), &dirs[0])).unwrap();
+    daemon.send(&set_path(Some("DP-2"), &dirs[1])).unwrap();
+    daemon.next_wallpaper(TIMEOUT).unwrap();
+    daemon.next_wallpaper(TIMEOUT).unwrap();
+
+    // Unplugging releases DP-2's server
+    daemon.set_connectors(&["DP-1"]);
+    std::thread::sleep(Duration::from_millis(200));
+
+    daemon.set_connectors(&["DP-1", "DP-2"]);
+    let (connector, url) = daemon.next_wallpaper(TIMEOUT).expect("not restored");
+    assert_eq!(connector, "DP-2");
+    assert!(TcpStream::connect(addr(&url)).is_ok());
+}
+
+#[test]
+fn request_waits_for_its_monitor() {
+    let (paths, dirs) = setup("held", &["a"]);
+    let daemon = start(paths);
+
+    daemon.send(&set_path(Some("DP-3"), &dirs[0])).unwrap();
+    assert!(daemon.next_wallpaper(Duration::from_millis(300)).is_none());
+
+    daemon.set_connectors(&["DP-3"]);
+    let (connector, _) = daemon
+        .next_wallpaper(TIMEOUT)
+        .expect("held request not applied");
+    assert_eq!(connector, "DP-3");
+}
+
+#[test]
+fn shared_server_serves_encoded_names() {
+    let (paths, dirs) = setup("encoded", &["a"]);
+    fs::write(PathBuf::from(&dirs[0]).join("two words.txt"), "spaced").unwrap();
+    fs::write(PathBuf::from(&dirs[0]).join("what?#.txt"), "escaped").unwrap();
+    let web = WebOptions {
+        shared: true,
+        linger: Duration::ZERO,
+        max_idle: 0,
+        cache: None,
+    };
+    let daemon = Headless::start(paths, web).unwrap();
+    daemon.set_connectors(&["DP-1"]);
+
+    daemon.send(&set_path(None, &dirs[0])).unwrap();
+    let (_, url) = daemon.next_wallpaper(TIMEOUT).unwrap();
+    let mount = url
+        .strip_prefix("http://")
+        .and_then(|rest| rest.find('/').map(|i| &rest[i..]))
+        .and_then(|path| path.split('?').next())
+        .unwrap();
+
+    let (status, body) = get(addr(&url), &format!("{mount}two%20words.txt"));
+    assert!(status.contains("200"), "{status}");
+    assert_eq!(body, "spaced");
+    let (status, body) = get(addr(&url), &format!("{mount}what%3F%23.txt"));
+    assert!(status.contains("200"), "{status}");
+    assert_eq!(body, "escaped");
+}
+
+fn start_playlist(daemon: &Headless, dirs: &[String], interval: u64) {
+    let entry = |path: &String| PlaylistEntry::Item {
+        wallpaper: None,
+        path: Some(path.clone()),
+        url: None,
+        weight: 1,
+        properties: Properties::default(),
+    };
+    let playlist = Playlist {
+        interval,
+        shuffle: false,
+        cursor: CursorMode::PerMonitor,
+        entries: dirs.iter().map(entry).collect(),
+    };
+    let start = Ipc::StartPlaylist {
+        monitor: Some("DP-1".to_string()),
+        name: None,
+        playlist: Some(playlist),
+    };
+    assert!(matches!(daemon.send(&start).unwrap(), IpcReply::Ok));
+}
+
+fn playlist_running(daemon: &Headless) -> bool {
+    let stop = Ipc::StopPlaylist {
+        monitor: Some("DP-1".to_string()),
+    };
+    matches!(daemon.send(&stop).unwrap(), IpcReply::Ok)
+}
+
+// A control request as a page would make it
+fn post_control(addr: SocketAddr, token: &str, body: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    write!(
+        stream,
+        "POST /api/control HTTP/1.0\r\nHost: {addr}\r\nContent-Type: application/json\r\n\
+         x-maypaper-token: {token}\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response.lines().next().unwrap_or("").to_string()
+}
+
+#[test]
+fn setting_by_hand_stops_the_playlist() {
+    let (paths, dirs) = setup("manual", &["a", "b", "c"]);
+    let daemon = start(paths);
+    daemon.set_connectors(&["DP-1"]);
+
+    start_playlist(&daemon, &dirs[..2], 1);
+    daemon
+        .next_wallpaper(TIMEOUT)
+        .expect("playlist not started");
+
+    daemon.send(&set_path(Some("DP-1"), &dirs[2])).unwrap();
+    daemon.next_wallpaper(TIMEOUT).expect("wallpaper not set");
+    assert!(
+        daemon.next_wallpaper(Duration::from_millis(1500)).is_none(),
+        "the playlist carried on"
+    );
+    assert!(!playlist_running(&daemon));
+}
+
+#[test]
+fn rejected_wallpaper_keeps_the_playlist() {
+    let (paths, dirs) = setup("rejected", &["a", "b"]);
+    let daemon = start(paths);
+    daemon.set_connectors(&["DP-1"]);
+
+    start_playlist(&daemon, &dirs, 1);
+    daemon
+        .next_wallpaper(TIMEOUT)
+        .expect("playlist not started");
+
+    let reply = daemon
+        .send(&set_path(Some("DP-1"), "/nonexistent/maypaper"))
+        .unwrap();
+    assert!(matches!(reply, IpcReply::Error { .. }));
+    daemon
+        .next_wallpaper(TIMEOUT)
+        .expect("the playlist stopped");
+    assert!(playlist_running(&daemon));
+}
+
+#[test]
+fn page_setting_a_named_wallpaper_stops_the_playlist() {
+    let (paths, dirs) = setup("control", &["a", "b", "c"]);
+    fs::write(
+        &paths.config,
+        format!("[[wallpapers]]\nname = \"c\"\npath = {:?}\n", dirs[2]),
+    )
+    .unwrap();
+    let daemon = start(paths);
+    daemon.set_connectors(&["DP-1"]);
+
+    start_playlist(&daemon, &dirs[..2], 3);
+    let (_, url) = daemon
+        .next_wallpaper(TIMEOUT)
+        .expect("playlist not started");
+    let addr = addr(&url);
+    let page = url.split_once(&addr.to_string()).unwrap().1;
+    let (_, body) = get(addr, page);
+    let token = body
+        .split_once("\"controlToken\":\"")
+        .and_then(|(_, rest)| rest.split_once('"'))
+        .expect("page has no token")
+        .0;
+
+    let status = post_control(
+        addr,
+        token,
+        r#"{"connector":"DP-1","type":"set_named","name":"c"}"#,
+    );
+    assert!(status.contains("200"), "{status}");
+    daemon.next_wallpaper(TIMEOUT).expect("wallpaper not set");
+    assert!(
+        daemon.next_wallpaper(Duration::from_millis(3500)).is_none(),
+        "the playlist carried on"
+    );
+    assert!(!playlist_running(&daemon));
+}
+
+#[test]
+fn cache_refresh_without_caching_is_an_error() {
+    let (paths, _) = setup("nocache", &[]);
+    let daemon = start(paths);
+
+    let reply = daemon.send(&Ipc::RefreshCache { url: None }).unwrap();
+    assert!(matches!(reply, IpcReply::Error { .. }));
+}
+
+#[test]
+fn missing_path_is_an_error() {
+    let (paths, _) = setup("missing", &[]);
+    let daemon = start(paths);
+    daemon.set_connectors(&["DP-1"]);
+
+    let reply = daemon
+        .send(&set_path(None, "/nonexistent/maypaper"))
+        .unwrap();
+    assert!(matches!(reply, IpcReply::Error { .. }));
+    assert!(daemon.next_wallpaper(Duration::from_millis(300)).is_none());
+}
+
+#[test]
+fn frame_rate_cap_shows_in_state() {
+    let (paths, _) = setup("frame-rate", &[]);
+    let daemon = start(paths);
+    daemon.set_connectors(&["DP-1", "DP-2"]);
+
+    let set = Ipc::SetFrameRate {
+        monitor: Some("DP-1".to_string()),
+        fps: Some(30),
+    };
+    assert!(matches!(daemon.send(&set).unwrap(), IpcReply::Ok));
+
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match daemon.next_call(left).expect("frame rate not set") {
+            UiCall::SetFrameRate { connector, fps } => {
+                assert_eq!((connector.as_str(), fps), ("DP-1", Some(30)));
+                break;
+            }
+            _ => continue,
+        }
+    }
+
+    let IpcReply::State { monitors, .. } = daemon.send(&Ipc::GetState).unwrap() else {
+        panic!("no state");
+    };
+    let rates: Vec<(String, Option<u32>)> = monitors
+        .into_iter()
+        .map(|m| (m.connector, m.frame_rate))
+        .collect();
+    assert_eq!(
+        rates,
+        [
+            ("DP-1".to_string(), Some(30)),
+            ("DP-2".to_string(), Some(60))
+        ]
+    );
+
+    let zero = Ipc::SetFrameRate {
+        monitor: None,
+        fps: Some(0),
+    };
+    assert!(matches!(
+        daemon.send(&zero).unwrap(),
+        IpcReply::Error { .. }
+    ));
+}
+
+#[test]
+fn crashed_page_is_reloaded_then_replaced() {
+    let (paths, dirs) = setup("crash", &["a"]);
+    fs::create_dir_all(&paths.base).unwrap();
+    fs::write(
+        &paths.config,
+        "[fallback]\ncolor = \"black\"\ncrashes = 1\n",
+    )
+    .unwrap();
+    let state = paths.state.clone();
+    let daemon = start(paths);
+    daemon.set_connectors(&["DP-1"]);
+
+    daemon.send(&set_path(None, &dirs[0])).unwrap();
+    daemon.next_wallpaper(TIMEOUT).unwrap();
+    // Waits for the socket, the subscription doesn't retry
+    daemon.send(&Ipc::GetState).unwrap();
+    let mut events = subscribe(daemon.socket()).unwrap();
+
+    daemon.terminate_render_process("DP-1", Termination::Crashed);
+    match events.next().unwrap().unwrap() {
+        Event::RenderProcessTerminated {
+            connector,
+            crashes,
+            reload_in_ms,
+            ..
+        } => {
+            assert_eq!((connector.as_str(), crashes), ("DP-1", 1));
+            assert_eq!(reload_in_ms, Some(1000));
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if let UiCall::Reload(connector) = daemon.next_call(left).expect("not reloaded") {
+            assert_eq!(connector, "DP-1");
+            break;
+        }
+    }
+
+    // Past [fallback] crashes, the colour is shown instead
+    daemon.terminate_render_process("DP-1", Termination::Killed);
+    match events.next().unwrap().unwrap() {
+        Event::RenderProcessTerminated { reload_in_ms, .. } => assert_eq!(reload_in_ms, None),
+        other => panic!("unexpected {other:?}"),
+    }
+    assert!(matches!(
+        events.next().unwrap().unwrap(),
+        Event::FallbackShown { .. }
+    ));
+    let (connector, url) = daemon.next_wallpaper(TIMEOUT).expect("no fallback");
+    assert_eq!(connector, "DP-1");
+    assert!(url.starts_with("data:text/html,"));
+
+    // Restarting or replugging brings back the page, not the colour
+    let saved = fs::read_to_string(state).unwrap();
+    assert!(saved.contains(&dirs[0]));
+    assert!(!saved.contains("data:text/html"));
+}
+
+fn set_url(monitor: Option<&str>, url: &str) -> Ipc {
+    Ipc::SetUrl {
+        monitor: monitor.map(str::to_string),
+        url: url.to_string(),
+        properties: Properties::default(),
+        span: Vec::new(),
+    }
+}
+
+#[test]
+fn failed_load_is_reported_and_replaced() {
+    let (paths, _) = setup("load-failed", &[]);
+    fs::create_dir_all(&paths.base).unwrap();
+    fs::write(&paths.config, "[fallback]\ncolor = \"black\"\n").unwrap();
+    let state = paths.state.clone();
+    let daemon = start(paths);
+    daemon.set_connectors(&["DP-1"]);
+
+    let broken = "https://broken.invalid/";
+    daemon.fail_load(broken, "Host not found");
+    daemon.send(&Ipc::GetState).unwrap();
+    let mut events = subscribe(daemon.socket()).unwrap();
+
+    match daemon.send(&set_url(Some("DP-1"), broken)).unwrap() {
+        IpcReply::Error { message } => assert_eq!(message, "DP-1: Host not found"),
+        other => panic!("unexpected {other:?}"),
+    }
+    match events.next().unwrap().unwrap() {
+        Event::LoadFailed {
+            connector,
+            url,
+            error,
+        } => {
+            assert_eq!(connector, "DP-1");
+            assert_eq!(url.as_deref(), Some(broken));
+            assert_eq!(error, "Host not found");
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+    assert!(matches!(
+        events.next().unwrap().unwrap(),
+        Event::FallbackShown { .. }
+    ));
+
+    assert_eq!(
+        daemon.next_wallpaper(TIMEOUT),
+        Some(("DP-1".to_string(), broken.to_string()))
+    );
+    let (connector, url) = daemon.next_wallpaper(TIMEOUT).expect("no fallback");
+    assert_eq!(connector, "DP-1");
+    assert!(url.starts_with("data:text/html,"));
+
+    // What's restored is still the page that was asked for
+    let saved = fs::read_to_string(state).unwrap();
+    assert!(saved.contains(broken));
+    assert!(!saved.contains("data:text/html"));
+}
+
+#[test]
+fn stalled_load_times_out() {
+    let (paths, _) = setup("load-stalled", &[]);
+    fs::create_dir_all(&paths.base).unwrap();
+    fs::write(
+        &paths.config,
+        "[fallback]\ncolor = \"black\"\ntimeout = 1\n",
+    )
+    .unwrap();
+    let daemon = start(paths);
+    daemon.set_connectors(&["DP-1", "HDMI-A-1"]);
+
+    let stalled = "https://stalled.invalid/";
+    daemon.stall_load(stalled);
+
+    // One reply for both monitors, naming each
+    match daemon.send(&set_url(None, stalled)).unwrap() {
+        IpcReply::Error { message } => {
+            assert!(message.contains("DP-1: did not load within 1s"));
+            assert!(message.contains("HDMI-A-1: did not load within 1s"));
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+    let fallbacks = std::iter::from_fn(|| daemon.next_wallpaper(TIMEOUT))
+        .filter(|(_, url)| url.starts_with("data:text/html,"))
+        .take(2)
+        .count();
+    assert_eq!(fallbacks, 2);
+}